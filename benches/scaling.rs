@@ -0,0 +1,99 @@
+//! Measures how creating and enabling detours of unrelated targets scales
+//! across threads.
+//!
+//! Run using `cargo bench --bench scaling`.
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod scaling {
+  use retour::RawDetour;
+  use std::arch::global_asm;
+  use std::time::{Duration, Instant};
+  use std::{mem, thread};
+
+  const TARGETS: usize = 10_000;
+  const TARGET_SIZE: usize = 16;
+  const THREADS: [usize; 5] = [1, 2, 4, 8, 16];
+
+  // Distinct targets, each aligned to 16 bytes
+  global_asm!(
+    r#"
+      .global scaling_targets
+      .p2align 4
+      scaling_targets:
+      .rept 10000
+        mov eax, 1
+        ret
+        .p2align 4, 0xcc
+      .endr
+    "#
+  );
+
+  extern "C" {
+    fn scaling_targets() -> i32;
+  }
+
+  extern "C" fn ret2() -> i32 {
+    2
+  }
+
+  fn target(index: usize) -> usize {
+    scaling_targets as *const () as usize + index * TARGET_SIZE
+  }
+
+  /// Creates and enables a detour of every target, returning the time taken.
+  fn run(threads: usize) -> Duration {
+    let start = Instant::now();
+    let hooks = (0..threads)
+      .map(|thread| {
+        // Each thread detours a contiguous range of targets
+        let range = (thread * TARGETS / threads)..((thread + 1) * TARGETS / threads);
+        thread::spawn(move || {
+          range
+            .map(|index| unsafe {
+              let hook = RawDetour::new(target(index) as *const (), ret2 as *const ()).unwrap();
+              hook.enable().unwrap();
+              hook
+            })
+            .collect::<Vec<_>>()
+        })
+      })
+      .collect::<Vec<_>>()
+      .into_iter()
+      .flat_map(|worker| worker.join().unwrap())
+      .collect::<Vec<_>>();
+    let elapsed = start.elapsed();
+
+    for index in 0..TARGETS {
+      let target: extern "C" fn() -> i32 = unsafe { mem::transmute(target(index)) };
+      assert_eq!(target(), 2);
+    }
+
+    drop(hooks);
+    elapsed
+  }
+
+  pub fn main() {
+    println!("creating and enabling {} detours", TARGETS);
+    let baseline = run(1);
+
+    for &threads in &THREADS {
+      let elapsed = if threads == 1 { baseline } else { run(threads) };
+      println!(
+        "{:>2} threads: {:>8.1} ms ({:.2}x)",
+        threads,
+        elapsed.as_secs_f64() * 1000.0,
+        baseline.as_secs_f64() / elapsed.as_secs_f64(),
+      );
+    }
+  }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn main() {
+  scaling::main();
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn main() {
+  println!("the benchmark only supports x86_64 Linux");
+}
@@ -0,0 +1,160 @@
+#ifndef RETOUR_H
+#define RETOUR_H
+
+#include <stdbool.h>
+
+// The outcome of a fallible function.
+typedef enum retour_status {
+  // The function succeeded.
+  RETOUR_STATUS_OK = 0,
+  // An argument was a null pointer.
+  RETOUR_STATUS_NULL_POINTER,
+  // See [Error::SameAddress].
+  RETOUR_STATUS_SAME_ADDRESS,
+  // See [Error::InvalidCode].
+  RETOUR_STATUS_INVALID_CODE,
+  // See [Error::NoPatchArea].
+  RETOUR_STATUS_NO_PATCH_AREA,
+  // See [Error::NotExecutable].
+  RETOUR_STATUS_NOT_EXECUTABLE,
+  // See [Error::NotInitialized].
+  RETOUR_STATUS_NOT_INITIALIZED,
+  // See [Error::AlreadyInitialized].
+  RETOUR_STATUS_ALREADY_INITIALIZED,
+  // See [Error::AlreadyHooked].
+  RETOUR_STATUS_ALREADY_HOOKED,
+  // See [Error::OutOfMemory].
+  RETOUR_STATUS_OUT_OF_MEMORY,
+  // See [Error::UnsupportedInstruction].
+  RETOUR_STATUS_UNSUPPORTED_INSTRUCTION,
+  // See [Error::RegionFailure].
+  RETOUR_STATUS_REGION_FAILURE,
+  // See [Error::ThreadSafety].
+  RETOUR_STATUS_THREAD_SAFETY,
+  // See [Error::CodeModified].
+  RETOUR_STATUS_CODE_MODIFIED,
+  // See [Error::UnknownModule].
+  RETOUR_STATUS_UNKNOWN_MODULE,
+  // See [Error::LibraryFailure].
+  RETOUR_STATUS_LIBRARY_FAILURE,
+  // See [Error::ModuleUnloaded].
+  RETOUR_STATUS_MODULE_UNLOADED,
+  // See [Error::RollbackFailed].
+  RETOUR_STATUS_ROLLBACK_FAILED,
+  // The function panicked, and the panic was caught.
+  RETOUR_STATUS_PANICKED,
+} retour_status;
+
+// An opaque handle to a raw detour.
+typedef struct retour_detour retour_detour;
+
+// An opaque handle to a transaction.
+typedef struct retour_transaction retour_transaction;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns a static, nul-terminated description of a status.
+const char *retour_status_message(retour_status status);
+
+// Creates a disabled detour of `target` to `detour`, stored in `*out`.
+//
+// # Safety
+//
+// The same requirements as for [RawDetour::new] apply, and `out` must be
+// valid for writes.
+retour_status retour_create(const void *target, const void *detour, retour_detour **out);
+
+// Enables a detour.
+//
+// # Safety
+//
+// The detour must have been created by [retour_create], and not destroyed.
+retour_status retour_enable(retour_detour *detour);
+
+// Disables a detour.
+//
+// # Safety
+//
+// The detour must have been created by [retour_create], and not destroyed.
+retour_status retour_disable(retour_detour *detour);
+
+// Returns whether a detour is enabled.
+//
+// # Safety
+//
+// The detour must have been created by [retour_create], and not destroyed.
+bool retour_is_enabled(const retour_detour *detour);
+
+// Returns a callable address to the original target, or null if `detour` is
+// null.
+//
+// # Safety
+//
+// The detour must have been created by [retour_create], and not destroyed.
+const void *retour_trampoline(const retour_detour *detour);
+
+// Disables and destroys a detour. Null is ignored.
+//
+// # Safety
+//
+// The detour must have been created by [retour_create], and not destroyed.
+void retour_destroy(retour_detour *detour);
+
+// Creates a new, empty transaction.
+retour_transaction *retour_transaction_new(void);
+
+// Queues the creation of a detour, which is enabled once committed.
+//
+// # Safety
+//
+// The transaction must have been created by [retour_transaction_new], and
+// neither committed nor aborted.
+retour_status retour_transaction_create(retour_transaction *transaction,
+                                        const void *target,
+                                        const void *detour);
+
+// Queues enabling a detour.
+//
+// # Safety
+//
+// The transaction must have been created by [retour_transaction_new], and
+// neither committed nor aborted. The detour must not be destroyed before the
+// transaction is committed or aborted.
+retour_status retour_transaction_enable(retour_transaction *transaction,
+                                        const retour_detour *detour);
+
+// Queues disabling a detour.
+//
+// # Safety
+//
+// The same requirements as for [retour_transaction_enable] apply.
+retour_status retour_transaction_disable(retour_transaction *transaction,
+                                         const retour_detour *detour);
+
+// Applies all operations of a transaction atomically, and destroys it.
+//
+// The detours created by the transaction are stored in `created`, in the
+// order they were queued, which must have room for each of them (it may be
+// null if none were queued). Nothing is stored if the transaction fails.
+//
+// # Safety
+//
+// The transaction must have been created by [retour_transaction_new], and
+// neither committed nor aborted.
+retour_status retour_transaction_commit(retour_transaction *transaction, retour_detour **created);
+
+// Destroys a transaction without applying it. Null is ignored.
+//
+// # Safety
+//
+// The transaction must have been created by [retour_transaction_new], and
+// neither committed nor aborted.
+void retour_transaction_abort(retour_transaction *transaction);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // RETOUR_H
@@ -1,33 +1,95 @@
 use crate::error::Result;
+use std::fmt;
 use std::ops::{Deref, DerefMut};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, PoisonError};
 
 mod proximity;
 mod search;
 
+/// Executable memory reserved in advance, from which detours can be created
+/// (see `DetourBuilder::arena`).
+///
+/// Creating a detour from an arena never maps memory, nor searches for free
+/// regions, and fails with `OutOfMemory` once the arena is exhausted. The
+/// memory is released once the arena, and every detour created from it, has
+/// been dropped.
+#[derive(Clone)]
+pub struct Arena(ThreadAllocator);
+
+impl Arena {
+  /// Reserves `size` bytes of executable memory within reach of `origin`
+  /// (i.e the range of a relative jump).
+  pub fn reserve(origin: *const (), size: usize) -> Result<Self> {
+    Self::reserve_within(origin, size, crate::arch::DETOUR_RANGE)
+  }
+
+  /// Reserves `size` bytes of executable memory within `max_distance` of
+  /// `origin`.
+  pub fn reserve_within(origin: *const (), size: usize, max_distance: usize) -> Result<Self> {
+    let allocator = proximity::ProximityAllocator::reserve(origin, size, max_distance)?;
+    Ok(Arena(ThreadAllocator {
+      allocator: Arc::new(Mutex::new(allocator)),
+      max_distance,
+    }))
+  }
+
+  /// Returns the allocator of the arena.
+  pub(crate) fn allocator(&self) -> &ThreadAllocator {
+    &self.0
+  }
+}
+
+impl fmt::Debug for Arena {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Arena")
+      .field("max_distance", &self.0.max_distance)
+      .finish_non_exhaustive()
+  }
+}
+
 /// A thread-safe memory pool for allocating chunks close to addresses.
-pub struct ThreadAllocator(Arc<Mutex<proximity::ProximityAllocator>>);
+#[derive(Clone)]
+pub struct ThreadAllocator {
+  allocator: Arc<Mutex<proximity::ProximityAllocator>>,
+  max_distance: usize,
+}
 
-// TODO: Decrease use of mutexes
 impl ThreadAllocator {
   /// Creates a new proximity memory allocator.
   pub fn new(max_distance: usize) -> Self {
-    ThreadAllocator(Arc::new(Mutex::new(proximity::ProximityAllocator {
+    ThreadAllocator {
+      allocator: Arc::new(Mutex::new(proximity::ProximityAllocator {
+        pools: Vec::new(),
+        fixed: false,
+      })),
       max_distance,
-      pools: Vec::new(),
-    })))
+    }
+  }
+
+  /// Returns an allocator sharing the same memory pools, which allocates
+  /// within a (shorter) distance.
+  pub fn within(&self, max_distance: usize) -> Self {
+    ThreadAllocator {
+      allocator: self.allocator.clone(),
+      max_distance: max_distance.min(self.max_distance),
+    }
   }
 
   /// Allocates read-, write- & executable memory close to `origin`.
   pub fn allocate(&self, origin: *const (), size: usize) -> Result<ExecutableMemory> {
-    let mut allocator = self.0.lock().unwrap();
+    let mut allocator = self.allocator.lock().unwrap();
     allocator
-      .allocate(origin, size)
+      .allocate(origin, size, self.max_distance)
       .map(|data| ExecutableMemory {
-        allocator: self.0.clone(),
+        allocator: self.allocator.clone(),
         data,
       })
   }
+
+  /// Locks the allocator until the returned guard is dropped.
+  pub fn lock(&self) -> impl Drop + '_ {
+    self.allocator.lock().unwrap_or_else(PoisonError::into_inner)
+  }
 }
 
 /// A handle for allocated proximity memory.
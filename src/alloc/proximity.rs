@@ -11,18 +11,39 @@ pub type Allocation = SliceBox<u8>;
 
 /// Shared instance containing all pools
 pub struct ProximityAllocator {
-  pub max_distance: usize,
   pub pools: Vec<SlicePool<u8>>,
+  /// Whether the pools are reserved in advance, and kept once unused.
+  pub fixed: bool,
 }
 
 impl ProximityAllocator {
-  /// Allocates a slice in an eligible memory map.
-  pub fn allocate(&mut self, origin: *const (), size: usize) -> Result<Allocation> {
-    let memory_range = ((origin as usize).saturating_sub(self.max_distance))
-      ..((origin as usize).saturating_add(self.max_distance));
+  /// Reserves a pool within `max_distance` of `origin`, which is never
+  /// released. No other pools are allocated.
+  pub fn reserve(origin: *const (), size: usize, max_distance: usize) -> Result<Self> {
+    let memory_range = ((origin as usize).saturating_sub(max_distance))
+      ..((origin as usize).saturating_add(max_distance));
+
+    let mut allocator = ProximityAllocator {
+      pools: Vec::new(),
+      fixed: true,
+    };
+    let pool = allocator.allocate_pool(&memory_range, origin, size)?;
+    allocator.pools.push(pool);
+    Ok(allocator)
+  }
+
+  /// Allocates a slice in an eligible memory map, within `max_distance` of
+  /// `origin`.
+  pub fn allocate(&mut self, origin: *const (), size: usize, max_distance: usize) -> Result<Allocation> {
+    let memory_range = ((origin as usize).saturating_sub(max_distance))
+      ..((origin as usize).saturating_add(max_distance));
 
     // Check if an existing pool can handle the allocation request
-    self.allocate_memory(&memory_range, size).or_else(|_| {
+    self.allocate_memory(&memory_range, size).or_else(|error| {
+      if self.fixed {
+        return Err(error);
+      }
+
       // ... otherwise allocate a pool within the memory range
       self.allocate_pool(&memory_range, origin, size).map(|pool| {
         // Use the newly allocated pool for the request
@@ -49,7 +70,7 @@ impl ProximityAllocator {
       .expect("retrieving associated memory pool");
 
     // Release the pool if the associated allocation is unique
-    if self.pools[index].len() == 1 {
+    if self.pools[index].len() == 1 && !self.fixed {
       self.pools.remove(index);
     }
   }
@@ -99,6 +120,16 @@ impl ProximityAllocator {
       })
       .next()
       .unwrap_or(Err(Error::OutOfMemory))
+      .map(|pool| {
+        event!(
+          "allocated pool",
+          pool = pool.as_ptr(),
+          size = pool.len(),
+          origin = origin,
+          fixed = self.fixed,
+        );
+        pool
+      })
   }
 
   /// Tries to allocate fixed memory at the specified address.
@@ -1,4 +1,4 @@
-use crate::error::{Error, Result};
+use crate::error::{RegionError, RegionOperation, Result};
 use std::ops::Range;
 
 /// Returns an iterator for free after the specified address.
@@ -18,6 +18,7 @@ pub fn before(
 }
 
 /// Direction for the region search.
+#[derive(Debug)]
 enum SearchDirection {
   Before,
   After,
@@ -60,8 +61,11 @@ impl Iterator for FreeRegionIter {
         Err(error) => {
           // Check whether the region is free, otherwise return the error
           let result = Some(match error {
-            region::Error::UnmappedRegion => Ok(self.current as *const _),
-            inner => Err(Error::RegionFailure(inner)),
+            region::Error::UnmappedRegion => {
+              event!("found free region", address = self.current as *const (), search = self.search);
+              Ok(self.current as *const _)
+            },
+            inner => Err(RegionError::map(RegionOperation::Allocate, self.current as *const ())(inner)),
           });
 
           // Adjust the offset for repeated calls.
@@ -0,0 +1,573 @@
+use super::integrity::Integrity;
+use super::memory;
+use super::modules::Module;
+use super::reclaim::{CallGuard, Calls, Code};
+use super::relay::Relay;
+use crate::error::{Error, Result, RollbackError};
+use crate::threads::{self, ThreadSafety};
+use crate::{alloc, arch};
+use once_cell::sync::Lazy;
+use std::cell::UnsafeCell;
+use std::collections::HashMap;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// All detour chains, indexed by their target.
+static CHAINS: Lazy<Mutex<HashMap<usize, Arc<Chain>>>> = Lazy::new(Default::default);
+
+/// A source of unique link IDs and enable order.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// All detours of a single target.
+///
+/// The target is patched to jump to the chain's entry relay, which in turn
+/// jumps to the first enabled detour. Each detour has its own relay for
+/// calling the next enabled detour, with the original code (i.e the
+/// trampoline) being last. Detours can therefore be enabled and disabled in
+/// any order, without modifying the target.
+///
+/// Detours are ordered by priority (highest first), and then by the order in
+/// which they were enabled (most recent first).
+pub struct Chain {
+  target: *const (),
+  trampoline: Code,
+  /// The original instructions relocated to the trampoline.
+  prolog: Vec<u8>,
+  relocations: Vec<(usize, usize)>,
+  patcher: UnsafeCell<arch::Patcher>,
+  entry: Relay,
+  links: Mutex<Vec<Link>>,
+  /// Whether the chain has been removed, after which no links may be added.
+  released: AtomicBool,
+  /// Serializes patching the target.
+  state: Mutex<()>,
+  patched: AtomicBool,
+  calls: Calls,
+  /// The module containing the target, if it may be unloaded.
+  module: Option<Module>,
+  /// Whether the target's module has been unloaded, after which the target
+  /// is never accessed.
+  detached: AtomicBool,
+}
+
+/// A part of a chain's code.
+pub enum Part<'a> {
+  /// The trampoline, and the relocated instruction (its offset within the
+  /// prolog, and its original bytes) if known.
+  Trampoline(Option<(usize, &'a [u8])>),
+  /// The relay the target jumps to.
+  Entry,
+  /// The target's patch area.
+  Patch,
+}
+
+/// A detour's position within a chain.
+struct Link {
+  id: usize,
+  priority: i32,
+  order: usize,
+  enabled: bool,
+  detour: *const (),
+  next: *const AtomicUsize,
+}
+
+/// Locks the chains of all targets until the returned guard is dropped.
+pub fn lock_all() -> impl Drop {
+  CHAINS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns whether a target has been detoured by this library.
+pub fn is_detoured(target: *const ()) -> bool {
+  CHAINS.lock().unwrap().contains_key(&(target as usize))
+}
+
+impl Chain {
+  /// Returns the chain of a target, creating it if required.
+  ///
+  /// The chains are not locked whilst a chain is created, so chains of
+  /// different targets can be created in parallel. If another thread creates
+  /// a chain for the same target meanwhile, its chain is used instead.
+  ///
+  /// The patch options only apply when the chain is created.
+  pub unsafe fn get_or_create(
+    pool: &alloc::ThreadAllocator,
+    target: *const (),
+    options: &arch::Options,
+  ) -> Result<Arc<Chain>> {
+    if let Some(chain) = CHAINS.lock().unwrap().get(&(target as usize)) {
+      return Ok(chain.clone());
+    }
+
+    // Create a trampoline generator for the target function
+    let margin = options
+      .margin
+      .unwrap_or_else(|| arch::meta::prolog_margin(target));
+    let trampoline = arch::Trampoline::new(target, margin)?;
+    let calls = Calls::default();
+    let code = Code::new(
+      memory::allocate_pic(pool, trampoline.emitter(), target)?,
+      calls.clone(),
+    );
+
+    // The target always jumps to the entry, which defaults to the original
+    let entry = Relay::new(pool, target, code.as_ptr() as *const (), &calls)?;
+
+    let chain = Arc::new(Chain {
+      patcher: UnsafeCell::new(arch::Patcher::new(
+        target,
+        entry.as_ptr(),
+        trampoline.prolog_size(),
+        options,
+      )?),
+      prolog: slice::from_raw_parts(target as *const u8, trampoline.prolog_size()).to_vec(),
+      relocations: trampoline.relocations().to_vec(),
+      links: Mutex::new(Vec::new()),
+      released: AtomicBool::new(false),
+      state: Mutex::new(()),
+      patched: AtomicBool::new(false),
+      module: Module::of(target),
+      detached: AtomicBool::new(false),
+      trampoline: code,
+      target,
+      entry,
+      calls,
+    });
+
+    let mut chains = CHAINS.lock().unwrap();
+    Ok(chains.entry(target as usize).or_insert(chain).clone())
+  }
+
+  /// Removes a chain from the registry once it has no more links.
+  pub fn release(chain: &Arc<Chain>) {
+    let mut chains = CHAINS.lock().unwrap();
+    let links = chain.links.lock().unwrap();
+    if !links.is_empty() {
+      return;
+    }
+
+    // Any detour added from now on must create a new chain
+    chain.released.store(true, Ordering::SeqCst);
+    if chains
+      .get(&(chain.target as usize))
+      .map_or(false, |existing| Arc::ptr_eq(existing, chain))
+    {
+      chains.remove(&(chain.target as usize));
+    }
+  }
+
+  /// Detaches every chain whose target's module has been unloaded, returning
+  /// their targets.
+  ///
+  /// A detached chain is removed from the registry, so the target's address
+  /// can be detoured again once reused.
+  pub fn detach_unloaded<F: FnMut(&Module) -> bool>(mut is_loaded: F) -> Vec<*const ()> {
+    let unloaded = {
+      let mut chains = CHAINS.lock().unwrap();
+      let unloaded = chains
+        .values()
+        .filter(|chain| chain.module.as_ref().map_or(false, |module| !is_loaded(module)))
+        .cloned()
+        .collect::<Vec<_>>();
+
+      for chain in &unloaded {
+        chains.remove(&(chain.target as usize));
+      }
+      unloaded
+    };
+
+    // The registry is not held meanwhile, since the chains may already be
+    // locked by a thread detaching them (see `detach`)
+    for chain in &unloaded {
+      let _state = chain.lock();
+      chain.mark_detached();
+    }
+
+    unloaded.iter().map(|chain| chain.target).collect()
+  }
+
+  /// Returns the chains whose targets reside within a module.
+  pub fn of_module(module: &Module) -> Vec<Arc<Chain>> {
+    CHAINS
+      .lock()
+      .unwrap()
+      .values()
+      .filter(|chain| chain.module.as_ref() == Some(module))
+      .cloned()
+      .collect()
+  }
+
+  /// Detaches a chain whose target's module has been unloaded, removing it
+  /// from the registry.
+  ///
+  /// The chain must be locked.
+  pub fn detach(chain: &Arc<Chain>) {
+    let mut chains = CHAINS.lock().unwrap();
+    if chains
+      .get(&(chain.target as usize))
+      .map_or(false, |existing| Arc::ptr_eq(existing, chain))
+    {
+      chains.remove(&(chain.target as usize));
+    }
+
+    drop(chains);
+    chain.mark_detached();
+  }
+
+  /// Locks the chain for patching, until the returned guard is dropped.
+  pub fn lock(&self) -> MutexGuard<'_, ()> {
+    self.state.lock().unwrap()
+  }
+
+  /// Returns the target of the chain.
+  pub fn target(&self) -> *const () {
+    self.target
+  }
+
+  /// Returns the address of the original code.
+  pub fn trampoline(&self) -> *const () {
+    self.trampoline.as_ptr() as *const ()
+  }
+
+  /// Returns the code of the trampoline.
+  pub fn trampoline_code(&self) -> &[u8] {
+    self.trampoline.bytes()
+  }
+
+  /// Returns the calls in progress through the chain.
+  pub fn calls(&self) -> &Calls {
+    &self.calls
+  }
+
+  /// Marks a call through the chain as in progress.
+  pub fn enter(&self) -> CallGuard<'_> {
+    self.calls.enter()
+  }
+
+  /// Returns whether the target's module has been unloaded.
+  pub fn is_detached(&self) -> bool {
+    self.detached.load(Ordering::SeqCst)
+  }
+
+  /// Returns the target's patch area, which is empty once detached.
+  pub fn area(&self) -> &[u8] {
+    let area = unsafe { (*self.patcher.get()).area() };
+    if self.is_detached() {
+      &area[..0]
+    } else {
+      area
+    }
+  }
+
+  /// Returns the original bytes of the target's patch area.
+  pub fn original(&self) -> &[u8] {
+    unsafe { (*self.patcher.get()).original() }
+  }
+
+  /// Returns the part of the chain's code containing an address, if any.
+  pub fn locate(&self, address: usize) -> Option<Part<'_>> {
+    if self.trampoline.contains(address) {
+      // The relocated instruction starting closest before the address
+      let offset = address - self.trampoline() as usize;
+      let prolog = self
+        .relocations
+        .iter()
+        .filter(|(_, relocated)| *relocated <= offset)
+        .max_by_key(|(_, relocated)| *relocated)
+        .map(|&(prolog, _)| prolog);
+
+      return Some(Part::Trampoline(prolog.map(|prolog| {
+        let end = self
+          .relocations
+          .iter()
+          .map(|&(other, _)| other)
+          .filter(|&other| other > prolog)
+          .min()
+          .unwrap_or(self.prolog.len());
+        (prolog, &self.prolog[prolog..end])
+      })));
+    }
+
+    let area = self.area().as_ptr() as usize;
+    if self.entry.contains(address) {
+      Some(Part::Entry)
+    } else if (area..area + self.area().len()).contains(&address) {
+      Some(Part::Patch)
+    } else {
+      None
+    }
+  }
+
+  /// Returns whether the target is patched or not.
+  pub fn is_patched(&self) -> bool {
+    self.patched.load(Ordering::SeqCst)
+  }
+
+  /// Adds a disabled detour to the chain, returning its ID.
+  ///
+  /// Fails if the chain has been released meanwhile, in which case a new
+  /// chain must be created. The relay must remain valid until the link is
+  /// removed.
+  pub fn add(&self, detour: *const (), next: &Relay) -> Option<usize> {
+    let id = SEQUENCE.fetch_add(1, Ordering::SeqCst);
+    let mut links = self.links.lock().unwrap();
+    if self.released.load(Ordering::SeqCst) {
+      return None;
+    }
+
+    links.push(Link {
+      order: id,
+      priority: 0,
+      enabled: false,
+      next: arch::meta::relay_slot(next.as_ptr()),
+      detour,
+      id,
+    });
+    self.relink(&mut links);
+    Some(id)
+  }
+
+  /// Removes a detour from the chain.
+  pub fn remove(&self, id: usize) {
+    let mut links = self.links.lock().unwrap();
+    links.retain(|link| link.id != id);
+    self.relink(&mut links);
+  }
+
+  /// Enables or disables a detour within the chain, returning whether any
+  /// detour is enabled.
+  pub fn set_enabled(&self, id: usize, enabled: bool) -> bool {
+    self.update(id, |link| {
+      if enabled {
+        // The most recently enabled detour is placed first
+        link.order = SEQUENCE.fetch_add(1, Ordering::SeqCst);
+      }
+      link.enabled = enabled;
+    });
+    self.is_linked()
+  }
+
+  /// Returns whether any detour is enabled within the chain.
+  pub fn is_linked(&self) -> bool {
+    self.links.lock().unwrap().iter().any(|link| link.enabled)
+  }
+
+  /// Returns the priority of a detour.
+  pub fn priority(&self, id: usize) -> i32 {
+    let links = self.links.lock().unwrap();
+    links.iter().find(|link| link.id == id).map_or(0, |link| link.priority)
+  }
+
+  /// Sets the priority of a detour.
+  pub fn set_priority(&self, id: usize, priority: i32) {
+    self.update(id, |link| link.priority = priority);
+  }
+
+  /// Returns the destination of a detour.
+  pub fn detour(&self, id: usize) -> *const () {
+    let links = self.links.lock().unwrap();
+    links
+      .iter()
+      .find(|link| link.id == id)
+      .map_or(std::ptr::null(), |link| link.detour)
+  }
+
+  /// Returns the destination of a detour, unless the links are locked.
+  pub fn try_detour(&self, id: usize) -> Option<*const ()> {
+    let links = self.links.try_lock().ok()?;
+    links.iter().find(|link| link.id == id).map(|link| link.detour)
+  }
+
+  /// Changes the destination of a detour.
+  pub fn set_detour(&self, id: usize, detour: *const ()) {
+    self.update(id, |link| link.detour = detour);
+  }
+
+  /// Marks the chain as detached, after which the target is never accessed.
+  ///
+  /// The chain must be locked, before its links (as when toggling a detour).
+  fn mark_detached(&self) {
+    // Any detour added from now on must create a new chain
+    let _links = self.links.lock().unwrap();
+    self.released.store(true, Ordering::SeqCst);
+    self.detached.store(true, Ordering::SeqCst);
+    self.patched.store(false, Ordering::SeqCst);
+  }
+
+  /// Modifies a link and updates the relays accordingly.
+  fn update<F: FnOnce(&mut Link)>(&self, id: usize, modify: F) {
+    let mut links = self.links.lock().unwrap();
+    if let Some(link) = links.iter_mut().find(|link| link.id == id) {
+      modify(link);
+    }
+    self.relink(&mut links);
+  }
+
+  /// Points every relay to the next enabled detour, or the original code.
+  fn relink(&self, links: &mut [Link]) {
+    links.sort_by(|a, b| {
+      b.priority
+        .cmp(&a.priority)
+        .then_with(|| b.order.cmp(&a.order))
+    });
+
+    // Update from the tail, so a relay never points to an unlinked detour
+    let mut next = self.trampoline();
+    for link in links.iter().rev() {
+      unsafe { (*link.next).store(next as usize, Ordering::SeqCst) };
+      if link.enabled {
+        next = link.detour;
+      }
+    }
+
+    self.entry.set_destination(next);
+  }
+
+  /// Patches or unpatches the target.
+  ///
+  /// The chain must be locked, and the patch area writable.
+  pub unsafe fn patch(&self, enabled: bool, mode: ThreadSafety) -> Result<()> {
+    if self.check(enabled)? {
+      self.write(enabled, mode)?;
+    }
+    Ok(())
+  }
+
+  /// Patches or unpatches several targets, suspending other threads at most
+  /// once (if any patch requires it), so they never observe a partially
+  /// applied batch.
+  ///
+  /// If any target cannot be patched, none is. The chains must be locked (or
+  /// the global lock held exclusively), and the patch areas writable.
+  pub unsafe fn patch_all(patches: &[(&Chain, bool, ThreadSafety)]) -> Result<()> {
+    let mut pending = Vec::with_capacity(patches.len());
+    for &(chain, enabled, mode) in patches {
+      if chain.check(enabled)? {
+        pending.push((chain, enabled, mode));
+      }
+    }
+
+    if pending.iter().any(|&(_, _, mode)| mode == ThreadSafety::SuspendThreads) {
+      // Nothing may be allocated or locked whilst the threads are suspended
+      let _guard = memory::THREADS.lock().unwrap();
+      let threads = threads::suspend()?;
+      for &(chain, enabled, _) in &pending {
+        (*chain.patcher.get()).toggle(enabled);
+        chain.patched.store(enabled, Ordering::SeqCst);
+      }
+
+      // Move any thread executing the replaced instructions
+      threads.relocate(|address| {
+        pending
+          .iter()
+          .find_map(|&(chain, enabled, _)| chain.relocate(address, enabled))
+      });
+      return Ok(());
+    }
+
+    for (index, &(chain, enabled, mode)) in pending.iter().enumerate() {
+      if let Err(error) = chain.write(enabled, mode) {
+        // Restore the previous state, in reverse order
+        let mut failed = Vec::new();
+        for &(chain, enabled, mode) in pending[..index].iter().rev() {
+          if chain.write(!enabled, mode).is_err() {
+            failed.push(chain.target as usize);
+          }
+        }
+        return Err(RollbackError::check(error, failed));
+      }
+    }
+    Ok(())
+  }
+
+  /// Returns whether the target must be written to be patched (or
+  /// unpatched), or an error if it may not be.
+  fn check(&self, enabled: bool) -> Result<bool> {
+    match self.verify() {
+      // The target no longer exists, so it is neither patched nor restored
+      Integrity::Detached if enabled => Err(Error::ModuleUnloaded),
+      Integrity::Detached => Ok(false),
+      Integrity::Intact => Ok(true),
+      // The original code has already been restored by another party
+      Integrity::Restored if !enabled => {
+        self.patched.store(false, Ordering::SeqCst);
+        Ok(false)
+      },
+      // Code that was not written by this library is never overwritten
+      _ => Err(Error::CodeModified),
+    }
+  }
+
+  /// Compares the target's patch area to the code that was written.
+  pub fn verify(&self) -> Integrity {
+    if self.is_detached() {
+      return Integrity::Detached;
+    }
+
+    unsafe { (*self.patcher.get()).verify(self.is_patched()) }
+  }
+
+  /// Patches the target again, if it is patched but its code has been
+  /// modified by another party. Returns whether the target was repaired.
+  ///
+  /// The chain must be locked, and the patch area writable.
+  pub unsafe fn repair(&self, mode: ThreadSafety) -> Result<bool> {
+    if !self.is_patched() || self.verify() == Integrity::Intact {
+      return Ok(false);
+    }
+
+    self.write(true, mode)?;
+    Ok(true)
+  }
+
+  /// Writes the patch, or the original code, to the target.
+  unsafe fn write(&self, enabled: bool, mode: ThreadSafety) -> Result<()> {
+    match mode {
+      ThreadSafety::Unsynchronized => {
+        // Copy either the detour or the original bytes of the function
+        (*self.patcher.get()).toggle(enabled);
+      },
+      ThreadSafety::SuspendThreads => {
+        let _guard = memory::THREADS.lock().unwrap();
+        let threads = threads::suspend()?;
+        (*self.patcher.get()).toggle(enabled);
+
+        // Move any thread executing the replaced instructions
+        threads.relocate(|address| self.relocate(address, enabled));
+      },
+      ThreadSafety::Breakpoint => {
+        // The trampoline is equivalent to the original code
+        let _guard = memory::THREADS.lock().unwrap();
+        (*self.patcher.get()).toggle_breakpoint(enabled, self.trampoline())?;
+      },
+    }
+
+    self.patched.store(enabled, Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// Returns the equivalent address of an instruction pointer, if it resides
+  /// within the prolog (when enabling) or the trampoline (when disabling).
+  fn relocate(&self, address: usize, enabled: bool) -> Option<usize> {
+    let target = self.target as usize;
+    let trampoline = self.trampoline() as usize;
+
+    self
+      .relocations
+      .iter()
+      // The first instruction is replaced by the jump itself
+      .filter(|(prolog, _)| *prolog > 0)
+      .find_map(|&(prolog, offset)| {
+        if enabled && address == target + prolog {
+          Some(trampoline + offset)
+        } else if !enabled && address == trampoline + offset {
+          Some(target + prolog)
+        } else {
+          None
+        }
+      })
+  }
+}
+
+unsafe impl Send for Chain {}
+unsafe impl Sync for Chain {}
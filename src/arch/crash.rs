@@ -0,0 +1,219 @@
+//! Attribution of crashes within the code of detours.
+//!
+//! A fault within a trampoline (e.g. a relocated instruction which depends on
+//! its address), a relay or an invalid detour is otherwise reported at an
+//! anonymous address. [attribute] maps such an address to the detour owning
+//! the code, and [install] reports it from a handler of the crash signals,
+//! before the crash is handled as it would have been.
+use super::chain::Part;
+use super::detour::{Hook, Location};
+use super::{reclaim, registry};
+use crate::util::Hex;
+use std::fmt;
+
+/// Describes the code of a detour containing an address, if any.
+///
+/// # Example
+///
+/// ```rust
+/// # use retour::Result;
+/// use retour::{crash, RawDetour};
+///
+/// #[inline(never)]
+/// fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { RawDetour::new(add5 as *const (), add10 as *const ())? };
+/// hook.set_name("add5");
+///
+/// let trampoline = hook.trampoline() as *const ();
+/// assert!(crash::attribute(trampoline).unwrap().contains("relay following detour 'add5'"));
+/// assert!(crash::attribute(add10 as *const ()).unwrap().contains("destination of detour"));
+/// assert_eq!(crash::attribute(std::ptr::null()), None);
+/// # Ok(())
+/// # }
+/// ```
+pub fn attribute(address: *const ()) -> Option<String> {
+  let mut description = String::new();
+  match describe(address as usize, &mut description) {
+    Ok(true) => Some(description),
+    _ => None,
+  }
+}
+
+/// Describes the code containing an address, returning whether it was found.
+///
+/// No locks are waited upon and nothing is allocated (except by the writer),
+/// so this may be called from a signal handler.
+fn describe<W: fmt::Write>(address: usize, out: &mut W) -> Result<bool, fmt::Error> {
+  let found = registry::try_find(|hook| {
+    let location = hook.locate(address)?;
+    Some(describe_location(hook, location, address, out))
+  });
+
+  if let Some(result) = found {
+    return result.map(|_| true);
+  }
+
+  if reclaim::try_is_retired(address) == Some(true) {
+    write!(out, "{:#x} is within the code of a dropped detour", address)?;
+    return Ok(true);
+  }
+  Ok(false)
+}
+
+/// Describes the location of an address within the code of a detour.
+fn describe_location<W: fmt::Write>(
+  hook: &Hook,
+  location: Location,
+  address: usize,
+  out: &mut W,
+) -> fmt::Result {
+  let part = match location {
+    Location::Chain(Part::Trampoline(_)) => "within the trampoline of",
+    Location::Chain(Part::Entry) => "within the entry relay of",
+    Location::Chain(Part::Patch) => "within the patch area of",
+    Location::Next => "within the relay following",
+    Location::Gate => "within the gate of",
+    Location::Dispatch => "within the dispatch stub of",
+    Location::Detour => "the destination of",
+  };
+
+  write!(out, "{:#x} is {} detour ", address, part)?;
+  hook
+    .try_with_name(|name| match name {
+      Some(name) => write!(out, "'{}'", name),
+      None => write!(out, "<unnamed>"),
+    })
+    .unwrap_or_else(|| write!(out, "<locked>"))?;
+  write!(out, " (target {:p})", hook.target())?;
+
+  if let Location::Chain(Part::Trampoline(Some((offset, instruction)))) = location {
+    write!(
+      out,
+      ", executing the instruction relocated from target+{:#x} [{:?}]",
+      offset,
+      Hex(instruction)
+    )?;
+  }
+  Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub use self::handler::install;
+
+#[cfg(target_os = "linux")]
+mod handler {
+  use super::describe;
+  use crate::threads;
+  use once_cell::sync::OnceCell;
+  use std::os::raw::{c_int, c_void};
+  use std::{fmt, io, mem, ptr};
+
+  /// The signals raised by faulting code.
+  const SIGNALS: [c_int; 3] = [libc::SIGSEGV, libc::SIGILL, libc::SIGBUS];
+
+  /// The handlers that were installed before ours, for each signal.
+  static mut PREVIOUS: [Option<libc::sigaction>; 3] = [None; 3];
+
+  /// Installs a handler of crash signals (i.e SIGSEGV, SIGILL and SIGBUS),
+  /// which reports faults within the code of a detour to stderr.
+  ///
+  /// The report names the detour, its target and, for a trampoline, the
+  /// relocated instruction that was executing. The signal is then forwarded
+  /// to the handler which was previously installed, or handled by default.
+  /// The handler is only installed once.
+  pub fn install() -> io::Result<()> {
+    static INSTALLED: OnceCell<()> = OnceCell::new();
+    INSTALLED.get_or_try_init(|| unsafe { install_handlers() }).map(|_| ())
+  }
+
+  /// Installs the handler for each crash signal.
+  unsafe fn install_handlers() -> io::Result<()> {
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = handle_fault as extern "C" fn(_, _, _) as usize;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    for (index, &signal) in SIGNALS.iter().enumerate() {
+      // The previous handler must be known before any signal can be received
+      let mut previous: libc::sigaction = mem::zeroed();
+      if libc::sigaction(signal, ptr::null(), &mut previous) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+
+      PREVIOUS[index] = Some(previous);
+      if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+
+  /// Reports the faulting detour, and forwards the signal.
+  extern "C" fn handle_fault(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    unsafe {
+      let mut report = Report::default();
+      if let Ok(true) = describe(threads::instruction_pointer(context), &mut report) {
+        report.flush();
+      }
+
+      let previous = SIGNALS
+        .iter()
+        .position(|&other| other == signal)
+        .and_then(|index| PREVIOUS[index]);
+
+      match previous {
+        // An ignored fault would be raised again forever
+        Some(previous) if previous.sa_sigaction != libc::SIG_IGN => {
+          threads::forward_signal(&previous, signal, info, context)
+        },
+        _ => {
+          libc::signal(signal, libc::SIG_DFL);
+        },
+      }
+    }
+  }
+
+  /// A report formatted without allocating, truncated if too long.
+  struct Report {
+    buffer: [u8; 1024],
+    length: usize,
+  }
+
+  impl Default for Report {
+    fn default() -> Self {
+      let mut report = Report {
+        buffer: [0; 1024],
+        length: 0,
+      };
+      let _ = fmt::Write::write_str(&mut report, "retour: ");
+      report
+    }
+  }
+
+  impl Report {
+    /// Writes the report to stderr, on a line of its own.
+    unsafe fn flush(&mut self) {
+      self.buffer[self.length] = b'\n';
+      libc::write(2, self.buffer.as_ptr().cast(), self.length + 1);
+    }
+  }
+
+  impl fmt::Write for Report {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+      // The final byte is reserved for the newline
+      let available = self.buffer.len() - 1 - self.length;
+      let length = text.len().min(available);
+      self.buffer[self.length..self.length + length].copy_from_slice(&text.as_bytes()[..length]);
+      self.length += length;
+      Ok(())
+    }
+  }
+}
@@ -1,132 +1,777 @@
-use super::memory;
+use super::chain::{Chain, Part};
+use super::dispatch::{CallerFilter, Destination, Dispatch};
+use super::gate::Gate;
+use super::integrity::Integrity;
+use super::{memory, meta};
+use super::reclaim::{self, CallGuard, Calls};
+use super::registry;
+use super::stats::{Counters, Stats};
+use super::relay::Relay;
 use crate::error::{Error, Result};
-use crate::{alloc, arch, util};
-use std::cell::UnsafeCell;
+use crate::threads::ThreadSafety;
+use crate::util::Hex;
+use crate::{alloc, resolve, util};
+use once_cell::sync::OnceCell;
 use std::fmt;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// An architecture-independent implementation of a base detour.
 ///
 /// This class is never instantiated by itself, it merely exposes an API
 /// available through it's descendants.
-pub struct Detour {
-  #[allow(dead_code)]
-  relay: Option<alloc::ExecutableMemory>,
-  trampoline: alloc::ExecutableMemory,
-  patcher: UnsafeCell<arch::Patcher>,
+///
+/// Each detour is a link within its target's [Chain], which allows several
+/// detours to share a target. Its state is shared with the
+/// [registry](super::registry), which lists every detour of the process.
+pub struct Detour(Arc<Hook>);
+
+/// How a detour is created (see `DetourBuilder`).
+#[derive(Debug, Clone)]
+pub struct Options {
+  /// The number of jump stubs in front of the target to follow, if any.
+  pub resolve: Option<usize>,
+  /// The minimum number of bytes relocated from the target, unless the
+  /// architecture's default.
+  pub margin: Option<usize>,
+  /// The furthest distance between the target and any allocated code.
+  pub max_distance: usize,
+  /// Whether padding following the prolog may be patched.
+  pub padding: bool,
+  /// Whether a hot patch area preceding the target may be patched.
+  pub hot_patch: bool,
+  /// Whether the target remains patched, and the detour is toggled through a
+  /// gate (i.e signal-safe).
+  pub signal_safe: bool,
+  /// Whether calls are relayed through a dispatch stub from the start.
+  pub dispatch: bool,
+  /// The callers the detour applies to, if limited.
+  pub caller_filter: Option<CallerFilter>,
+  /// How other threads are treated whilst the detour is toggled.
+  pub thread_safety: ThreadSafety,
+  /// The arena to allocate code from, instead of the shared allocators.
+  pub arena: Option<alloc::Arena>,
+  /// The name of the detour, as listed by the registry.
+  pub name: Option<String>,
+  /// The group of the detour, as listed by the registry.
+  pub group: Option<String>,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      resolve: None,
+      margin: None,
+      max_distance: meta::DETOUR_RANGE,
+      padding: true,
+      hot_patch: true,
+      signal_safe: false,
+      dispatch: false,
+      caller_filter: None,
+      thread_safety: ThreadSafety::default(),
+      arena: None,
+      name: None,
+      group: None,
+    }
+  }
+}
+
+/// The state of a detour.
+pub struct Hook {
+  chain: Arc<Chain>,
+  /// A relay to the next detour of the chain, or the original code.
+  next: Relay,
+  id: usize,
+  /// The allocator of any code created later on, within the furthest
+  /// distance of the target.
+  pool: alloc::ThreadAllocator,
+  /// A stub for per-thread dispatch, created once required.
+  dispatch: OnceCell<Dispatch>,
+  /// A gate for toggling without locks, if signal-safe.
+  gate: Option<Gate>,
+  thread_safety: AtomicU8,
   enabled: AtomicBool,
+  /// Whether the detour was disabled by the registry, to be restored.
+  suspended: AtomicBool,
+  name: Mutex<Option<String>>,
+  group: Mutex<Option<String>>,
+}
+
+/// The code of a detour containing an address.
+pub enum Location<'a> {
+  /// The code of the detour's chain, shared with other detours of the target.
+  Chain(Part<'a>),
+  /// The relay to the next detour, or the original code.
+  Next,
+  /// The gate toggling the detour.
+  Gate,
+  /// The dispatch stub of the detour.
+  Dispatch,
+  /// The destination of the detour (i.e an invalid detour was called).
+  Detour,
 }
 
 impl Detour {
   pub unsafe fn new(target: *const (), detour: *const ()) -> Result<Self> {
+    Self::with_options(target, detour, &Options::default())
+  }
+
+  /// Creates a detour after resolving any jump stubs in front of the target.
+  pub unsafe fn new_resolved(target: *const (), detour: *const (), max_depth: usize) -> Result<Self> {
+    let options = Options {
+      resolve: Some(max_depth),
+      ..Options::default()
+    };
+    Self::with_options(target, detour, &options)
+  }
+
+  /// Creates a detour which can be toggled from a signal handler.
+  ///
+  /// The target is patched for as long as the detour exists, and toggling
+  /// only opens or closes the detour's [Gate].
+  pub unsafe fn new_signal_safe(target: *const (), detour: *const ()) -> Result<Self> {
+    let options = Options {
+      signal_safe: true,
+      ..Options::default()
+    };
+    Self::with_options(target, detour, &options)
+  }
+
+  /// Creates a detour with custom options.
+  pub unsafe fn with_options(target: *const (), detour: *const (), options: &Options) -> Result<Self> {
+    let target = match options.resolve {
+      Some(max_depth) => {
+        let resolution = resolve::resolve(target, max_depth)?;
+        if resolution.is_hooked() {
+          Err(Error::AlreadyHooked)?;
+        }
+        resolution.address
+      },
+      None => target,
+    };
+
+    // Code of dropped detours is reclaimed before allocating more
+    reclaim::reclaim_idle();
+
+    // Detours of unrelated targets are created in parallel
+    let guard = memory::LOCK.read().unwrap();
+    let pool = match &options.arena {
+      Some(arena) => arena.allocator(),
+      None => memory::pool(),
+    };
+    let detour = Self::create(pool, target, detour, options)?;
+    let result = detour.prepare(options);
+
+    // A detour may only be dropped once the global lock is released
+    drop(guard);
+    result.map(|_| detour)
+  }
+
+  /// Creates a detour using an allocator, whilst the global lock is held.
+  pub unsafe fn with_pool(
+    pool: &alloc::ThreadAllocator,
+    target: *const (),
+    detour: *const (),
+  ) -> Result<Self> {
+    Self::create(pool, target, detour, &Options::default())
+  }
+
+  unsafe fn create(
+    pool: &alloc::ThreadAllocator,
+    target: *const (),
+    detour: *const (),
+    options: &Options,
+  ) -> Result<Self> {
     if target == detour {
       Err(Error::SameAddress)?;
     }
 
-    // Lock this so OS operations are not performed in parallell
-    let mut pool = memory::POOL.lock().unwrap();
-
     if !util::is_executable_address(target)? || !util::is_executable_address(detour)? {
       Err(Error::NotExecutable)?;
     }
 
-    // Create a trampoline generator for the target function
-    let margin = arch::meta::prolog_margin(target);
-    let trampoline = arch::Trampoline::new(target, margin)?;
+    // All code is allocated within the requested distance of the target
+    let pool = &pool.within(options.max_distance);
+
+    let (chain, id, next, gate) = loop {
+      // Detours of the same target share a trampoline
+      let chain = Chain::get_or_create(pool, target, options)?;
+      let relays = Relay::new(pool, target, chain.trampoline(), chain.calls()).and_then(|next| {
+        let gate = if options.signal_safe {
+          Some(Gate::new(pool, target, detour, next.as_ptr(), chain.calls())?)
+        } else {
+          None
+        };
+        Ok((next, gate))
+      });
+
+      let (next, gate) = match relays {
+        Ok(relays) => relays,
+        Err(error) => {
+          Chain::release(&chain);
+          return Err(error);
+        },
+      };
 
-    // A relay is used in case a normal branch cannot reach the destination
-    let relay = if let Some(emitter) = arch::meta::relay_builder(target, detour)? {
-      Some(memory::allocate_pic(&mut pool, &emitter, target)?)
-    } else {
-      None
+      // The chain may have been released by another thread meanwhile
+      let linked = gate.as_ref().map_or(detour, |gate| gate.as_ptr());
+      if let Some(id) = chain.add(linked, &next) {
+        break (chain, id, next, gate);
+      }
     };
 
-    // If a relay is supplied, use it instead of the detour address
-    let detour = relay
-      .as_ref()
-      .map(|code| code.as_ptr() as *const ())
-      .unwrap_or(detour);
-
-    Ok(Detour {
-      patcher: UnsafeCell::new(arch::Patcher::new(
-        target,
-        detour,
-        trampoline.prolog_size(),
-      )?),
-      trampoline: memory::allocate_pic(&mut pool, trampoline.emitter(), target)?,
+    let hook = Arc::new(Hook {
+      id,
+      pool: pool.clone(),
+      dispatch: OnceCell::new(),
+      gate,
+      thread_safety: AtomicU8::new(options.thread_safety.to_u8()),
       enabled: AtomicBool::default(),
-      relay,
-    })
+      suspended: AtomicBool::default(),
+      name: Mutex::new(options.name.clone()),
+      group: Mutex::new(options.group.clone()),
+      chain,
+      next,
+    });
+
+    registry::register(hook.clone());
+    Ok(Detour(hook))
+  }
+
+  /// Creates the parts of a detour which may fail once it exists.
+  ///
+  /// The global lock must be held.
+  unsafe fn prepare(&self, options: &Options) -> Result<()> {
+    if options.dispatch {
+      self.create_dispatch()?;
+    }
+
+    if let Some(filter) = &options.caller_filter {
+      self.create_dispatch()?.set_caller_range(Some(filter.range()?));
+    }
+
+    if self.gate.is_some() {
+      // The target remains patched, and is jumping to the closed gate
+      let _chain = self.chain.lock();
+      let _handle = self.protect()?;
+      self.link(true)?;
+    }
+    Ok(())
   }
+}
 
+impl Hook {
   /// Enables the detour.
   pub unsafe fn enable(&self) -> Result<()> {
+    self.clear_thread_scope();
     self.toggle(true)
   }
 
+  /// Makes the detour apply to all threads once enabled, rather than only
+  /// the threads which enabled it.
+  pub fn clear_thread_scope(&self) {
+    if let Some(dispatch) = self.dispatch.get() {
+      dispatch.set_thread_scoped(false);
+    }
+  }
+
+  /// Enables the detour for the current thread.
+  ///
+  /// If the detour is not enabled, it becomes enabled for the threads which
+  /// enable it explicitly only.
+  pub unsafe fn enable_for_current_thread(&self) -> Result<()> {
+    let dispatch = self.dispatch()?;
+    dispatch.set_enabled_for_current_thread(true)?;
+
+    if !self.is_enabled() {
+      dispatch.set_thread_scoped(true);
+    }
+    self.toggle(true)
+  }
+
+  /// Disables the detour for the current thread, without modifying the
+  /// target.
+  pub fn disable_for_current_thread(&self) -> Result<()> {
+    self.dispatch()?.set_enabled_for_current_thread(false)
+  }
+
+  /// Calls a closure whilst the detour is bypassed on the current thread.
+  pub fn bypass_on_current_thread<R, F: FnOnce() -> R>(&self, closure: F) -> Result<R> {
+    self.dispatch()?.bypass(closure)
+  }
+
+  /// Sets whether re-entrant calls (on the same thread) bypass the detour.
+  pub fn set_reentrancy_guard(&self, enabled: bool) -> Result<()> {
+    self.dispatch()?.set_reentrancy_guard(enabled);
+    Ok(())
+  }
+
+  /// Sets the callers the detour applies to, if limited.
+  pub fn set_caller_filter(&self, filter: Option<CallerFilter>) -> Result<()> {
+    let range = filter.map(|filter| filter.range()).transpose()?;
+    self.dispatch()?.set_caller_range(range);
+    Ok(())
+  }
+
   /// Disables the detour.
   pub unsafe fn disable(&self) -> Result<()> {
     self.toggle(false)
   }
 
-  /// Returns whether the detour is enabled or not.
+  /// Returns whether the detour is enabled or not, which it never is once
+  /// detached.
   pub fn is_enabled(&self) -> bool {
-    self.enabled.load(Ordering::SeqCst)
+    self.enabled.load(Ordering::SeqCst) && !self.is_detached()
+  }
+
+  /// Returns whether the target's module has been unloaded, after which the
+  /// target is never accessed.
+  pub fn is_detached(&self) -> bool {
+    self.chain.is_detached()
+  }
+
+  /// Returns how other threads are treated whilst the detour is toggled.
+  pub fn thread_safety(&self) -> ThreadSafety {
+    ThreadSafety::from_u8(self.thread_safety.load(Ordering::SeqCst))
+  }
+
+  /// Sets how other threads are treated whilst the detour is toggled.
+  pub fn set_thread_safety(&self, mode: ThreadSafety) {
+    self.thread_safety.store(mode.to_u8(), Ordering::SeqCst);
+  }
+
+  /// Returns the name of the detour, if any.
+  pub fn name(&self) -> Option<String> {
+    self.name.lock().unwrap().clone()
+  }
+
+  /// Calls a closure with the name of the detour, unless it is locked.
+  pub fn try_with_name<R>(&self, f: impl FnOnce(Option<&str>) -> R) -> Option<R> {
+    let name = self.name.try_lock().ok()?;
+    Some(f(name.as_deref()))
+  }
+
+  /// Sets the name of the detour.
+  pub fn set_name(&self, name: Option<String>) {
+    *self.name.lock().unwrap() = name;
+  }
+
+  /// Returns the group of the detour, if any.
+  pub fn group(&self) -> Option<String> {
+    self.group.lock().unwrap().clone()
+  }
+
+  /// Sets the group of the detour.
+  pub fn set_group(&self, group: Option<String>) {
+    *self.group.lock().unwrap() = group;
+  }
+
+  /// Returns the target of the detour.
+  pub fn target(&self) -> *const () {
+    self.chain.target()
+  }
+
+  /// Returns the destination of the detour.
+  pub fn destination(&self) -> *const () {
+    match self.dispatch.get() {
+      Some(dispatch) => dispatch.detour(),
+      None => self.linked(),
+    }
+  }
+
+  /// Returns the address the detour's link jumps to (i.e the detour, or its
+  /// dispatch stub).
+  fn linked(&self) -> *const () {
+    match &self.gate {
+      Some(gate) => gate.detour(),
+      None => self.chain.detour(self.id),
+    }
+  }
+
+  /// Changes the address the detour's link jumps to.
+  fn set_linked(&self, destination: *const ()) {
+    match &self.gate {
+      Some(gate) => gate.set_detour(destination),
+      None => self.chain.set_detour(self.id, destination),
+    }
+  }
+
+  /// Sets whether the detour was disabled by the registry, returning the
+  /// previous value.
+  pub fn set_suspended(&self, suspended: bool) -> bool {
+    self.suspended.swap(suspended, Ordering::SeqCst)
+  }
+
+  /// Returns the detour's priority within its chain.
+  pub fn priority(&self) -> i32 {
+    self.chain.priority(self.id)
+  }
+
+  /// Sets the detour's priority within its chain.
+  pub fn set_priority(&self, priority: i32) {
+    let _guard = memory::LOCK.read().unwrap();
+    self.chain.set_priority(self.id, priority);
+  }
+
+  /// Changes the destination of the detour.
+  ///
+  /// Only the relay preceding the detour is updated (atomically), so this
+  /// may be done whilst the detour is enabled.
+  pub unsafe fn set_detour(&self, detour: *const ()) -> Result<()> {
+    if self.chain.target() == detour {
+      Err(Error::SameAddress)?;
+    }
+
+    if !util::is_executable_address(detour)? {
+      Err(Error::NotExecutable)?;
+    }
+
+    let _guard = memory::LOCK.read().unwrap();
+    match self.dispatch.get() {
+      Some(dispatch) => dispatch.set_detour(detour),
+      None => self.set_linked(detour),
+    }
+    Ok(())
+  }
+
+  /// Changes the destination of a dispatched detour using a single atomic
+  /// swap, without validating the detour, taking any locks or allocating.
+  /// Returns the calls which invoked the replaced detour.
+  ///
+  /// This may be done whilst other threads are suspended.
+  pub unsafe fn swap_dispatched_detour(&self, destination: Box<Destination>) -> Result<Calls> {
+    let dispatch = self.dispatch.get().ok_or(Error::NotInitialized)?;
+    Ok(dispatch.swap_destination(destination))
+  }
+
+  /// Returns whether any call which invoked the detour has yet to return.
+  ///
+  /// Calls are only accounted for once relayed through a dispatch stub.
+  pub fn is_detour_active(&self) -> bool {
+    self.dispatch.get().map_or(false, Dispatch::is_active)
   }
 
   /// Returns a reference to the generated trampoline.
+  ///
+  /// This calls the next detour of the chain, or the original code.
   pub fn trampoline(&self) -> &() {
     unsafe {
-      (self.trampoline.as_ptr() as *const ())
+      self
+        .next
+        .as_ptr()
         .as_ref()
         .expect("trampoline should not be null")
     }
   }
 
+  /// Returns the code of the detour containing an address, if any.
+  ///
+  /// No locks are waited upon and nothing is allocated, so this may be called
+  /// from a signal handler.
+  pub fn locate(&self, address: usize) -> Option<Location<'_>> {
+    if let Some(part) = self.chain.locate(address) {
+      return Some(Location::Chain(part));
+    }
+
+    if self.next.contains(address) {
+      return Some(Location::Next);
+    }
+
+    if self.gate.as_ref().map_or(false, |gate| gate.contains(address)) {
+      return Some(Location::Gate);
+    }
+
+    let destination = match self.dispatch.get() {
+      Some(dispatch) if dispatch.contains(address) => return Some(Location::Dispatch),
+      Some(dispatch) => Some(dispatch.detour()),
+      None => match &self.gate {
+        Some(gate) => Some(gate.detour()),
+        None => self.chain.try_detour(self.id),
+      },
+    };
+
+    destination
+      .filter(|&detour| detour as usize == address)
+      .map(|_| Location::Detour)
+  }
+
+  /// Marks a call through the trampoline as in progress.
+  ///
+  /// The trampoline (and any code it relays to) is not reclaimed until the
+  /// returned guard has been dropped. The call is timed, if enabled.
+  pub fn enter(&self) -> Call<'_> {
+    Call {
+      _guard: self.chain.enter(),
+      timer: self
+        .dispatch
+        .get()
+        .map(|dispatch| dispatch.counters())
+        .and_then(|counters| Some((counters, counters.start()?))),
+    }
+  }
+
+  /// Enables collecting call statistics, optionally including timings.
+  pub fn enable_stats(&self, timed: bool) -> Result<()> {
+    self.dispatch()?.counters().enable(timed);
+    Ok(())
+  }
+
+  /// Returns the call statistics of the detour.
+  pub fn stats(&self) -> Stats {
+    self
+      .dispatch
+      .get()
+      .map_or_else(Stats::default, |dispatch| dispatch.counters().snapshot())
+  }
+
+  /// Resets the call statistics of the detour.
+  pub fn reset_stats(&self) {
+    if let Some(dispatch) = self.dispatch.get() {
+      dispatch.counters().reset();
+    }
+  }
+
+  /// Compares the target's code to the code written by this library.
+  pub fn verify(&self) -> Integrity {
+    let _guard = memory::LOCK.read().unwrap();
+    let _chain = self.chain.lock();
+    self.chain_integrity()
+  }
+
+  /// Compares the target's code to the code written by this library.
+  ///
+  /// The chain must be locked, or the global lock held exclusively.
+  pub fn chain_integrity(&self) -> Integrity {
+    self.chain.verify()
+  }
+
+  /// Patches the target again, if the detour is enabled but the target has
+  /// been modified by another party. Returns whether the target was repaired.
+  ///
+  /// The global lock must be held exclusively.
+  pub unsafe fn repair(&self) -> Result<bool> {
+    let _handle = self.protect()?;
+    self.chain.repair(self.thread_safety())
+  }
+
+  /// Returns the target's patch area.
+  pub fn area(&self) -> &[u8] {
+    self.chain.area()
+  }
+
+  /// Returns the detour's dispatch stub, creating it if required.
+  ///
+  /// The chain is linked to the stub, rather than the detour, once created.
+  fn dispatch(&self) -> Result<&Dispatch> {
+    if let Some(dispatch) = self.dispatch.get() {
+      return Ok(dispatch);
+    }
+
+    let _guard = memory::LOCK.read().unwrap();
+    self.create_dispatch()
+  }
+
+  /// Creates the detour's dispatch stub, unless it already exists.
+  ///
+  /// The global lock must be held.
+  fn create_dispatch(&self) -> Result<&Dispatch> {
+    self.dispatch.get_or_try_init(|| {
+      let dispatch = Dispatch::new(
+        &self.pool,
+        self.chain.target(),
+        self.linked(),
+        self.next.as_ptr(),
+        self.chain.calls(),
+      )?;
+      self.set_linked(dispatch.as_ptr());
+      Ok(dispatch)
+    })
+  }
+
   /// Enables or disables the detour.
   unsafe fn toggle(&self, enabled: bool) -> Result<()> {
-    let _guard = memory::POOL.lock().unwrap();
+    // Explicitly toggling a detour overrides the registry
+    self.suspended.store(false, Ordering::SeqCst);
+
+    if self.gate.is_some() {
+      // Only atomic stores are required, so no lock is taken
+      return self.write(enabled);
+    }
+
+    // Only detours of the same target are toggled in series
+    let _guard = memory::LOCK.read().unwrap();
+    let _chain = self.chain.lock();
+    // The target of a detached detour is never accessed
+    if self.is_detached() {
+      return self.write(enabled);
+    }
+
+    if self.enabled.load(Ordering::SeqCst) == enabled {
+      return Ok(());
+    }
+
+    let _handle = self.protect()?;
+    self.write(enabled)
+  }
+
+  /// Makes the target's patch area writable, until the handle is dropped.
+  fn protect(&self) -> Result<memory::Writable> {
+    unsafe { memory::protect(self.area()) }
+  }
+
+  /// Enables or disables the detour.
+  ///
+  /// Unless the detour is signal-safe, the chain must be locked (or the
+  /// global lock held exclusively), and the patch area writable.
+  pub unsafe fn write(&self, enabled: bool) -> Result<()> {
+    self.set_state(enabled, true)
+  }
+
+  /// Enables or disables the detour within its chain, leaving patching the
+  /// target to [patch_chains](#method.patch_chains).
+  ///
+  /// The global lock must be held exclusively.
+  pub unsafe fn write_deferred(&self, enabled: bool) -> Result<()> {
+    self.set_state(enabled, false)
+  }
+
+  /// Patches or unpatches the targets of several detours, which were toggled
+  /// using [write_deferred](#method.write_deferred), so they match their
+  /// chains. Other threads are suspended at most once, if any detour
+  /// requires it (see `Chain::patch_all`).
+  ///
+  /// The global lock must be held exclusively, and the patch areas writable.
+  pub unsafe fn patch_chains(hooks: &[&Hook]) -> Result<()> {
+    let mut patches: Vec<(&Chain, bool, ThreadSafety)> = Vec::with_capacity(hooks.len());
+    for hook in hooks.iter().filter(|hook| hook.gate.is_none()) {
+      let mode = hook.thread_safety();
+      match patches.iter_mut().find(|(chain, ..)| std::ptr::eq(*chain, &*hook.chain)) {
+        // Suspending threads is the strictest mode
+        Some(patch) if mode == ThreadSafety::SuspendThreads => patch.2 = mode,
+        Some(_) => (),
+        None => patches.push((&hook.chain, hook.chain.is_linked(), mode)),
+      }
+    }
+
+    patches.retain(|&(chain, enabled, _)| chain.is_patched() != enabled);
+    Chain::patch_all(&patches)
+  }
+
+  /// Enables or disables the detour, and patches the target if required.
+  unsafe fn set_state(&self, enabled: bool, patch: bool) -> Result<()> {
+    if enabled && self.is_detached() {
+      Err(Error::ModuleUnloaded)?;
+    }
 
     if self.enabled.load(Ordering::SeqCst) == enabled {
       return Ok(());
     }
 
-    // Runtime code is by default only read-execute
-    let _handle = {
-      let area = (*self.patcher.get()).area();
-      region::protect_with_handle(
-        area.as_ptr(),
-        area.len(),
-        region::Protection::READ_WRITE_EXECUTE,
-      )
-    }?;
+    match &self.gate {
+      Some(gate) => gate.set_open(enabled),
+      None if patch => self.link(enabled)?,
+      None => {
+        self.chain.set_enabled(self.id, enabled);
+      },
+    }
 
-    // Copy either the detour or the original bytes of the function
-    (*self.patcher.get()).toggle(enabled);
     self.enabled.store(enabled, Ordering::SeqCst);
     Ok(())
   }
+
+  /// Links or unlinks the detour, patching the target if required.
+  ///
+  /// The chain must be locked, and the patch area writable.
+  unsafe fn link(&self, enabled: bool) -> Result<()> {
+    // The target is only patched whilst any detour of the chain is enabled
+    let was_patched = self.chain.is_patched();
+    let needs_patch = self.chain.set_enabled(self.id, enabled);
+
+    if was_patched != needs_patch {
+      if let Err(error) = self.chain.patch(needs_patch, self.thread_safety()) {
+        self.chain.set_enabled(self.id, !enabled);
+        return Err(error);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// A call through a detour's trampoline, which is in progress.
+pub struct Call<'a> {
+  _guard: CallGuard<'a>,
+  timer: Option<(&'a Counters, Instant)>,
+}
+
+impl<'a> Drop for Call<'a> {
+  fn drop(&mut self) {
+    if let Some((counters, start)) = self.timer {
+      counters.add_original_time(start);
+    }
+  }
+}
+
+impl Deref for Detour {
+  type Target = Hook;
+
+  fn deref(&self) -> &Hook {
+    &self.0
+  }
 }
 
 impl Drop for Detour {
-  /// Disables the detour, if enabled.
+  /// Disables the detour, if enabled, and removes it from its chain and the
+  /// registry.
   fn drop(&mut self) {
-    let did_succeed = unsafe { self.disable() }.is_ok();
-    debug_assert!(did_succeed);
+    // The registry may no longer toggle the detour once it is disabled
+    registry::unregister(&self.0);
+
+    // Code modified by another party is left as is, and no other failure
+    // can be reported whilst dropping
+    if let Err(error) = unsafe { self.disable() } {
+      event!("failed to disable dropped detour", target = self.chain.target(), error = error);
+    }
+
+    {
+      let _guard = memory::LOCK.read().unwrap();
+      if self.gate.is_some() && !self.is_detached() {
+        let _chain = self.chain.lock();
+        let _ = self.protect().map(|_handle| unsafe { self.link(false) });
+      }
+
+      self.chain.remove(self.id);
+
+      // A target which could not be restored still jumps to the chain, so its
+      // code is kept (and the chain reused by any later detour of the target)
+      if self.chain.is_patched() && !self.chain.is_linked() {
+        event!("kept chain of unrestored target", target = self.chain.target());
+      } else {
+        Chain::release(&self.chain);
+      }
+    }
+
+    // Code of previously dropped detours may no longer be executed
+    reclaim::reclaim_idle();
   }
 }
 
 impl fmt::Debug for Detour {
-  /// Output whether the detour is enabled or not.
+  /// Output whether the detour is enabled or not, and the code involved.
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(
-      f,
-      "Detour {{ enabled: {}, trampoline: {:?} }}",
-      self.is_enabled(),
-      self.trampoline()
-    )
+    f.debug_struct("Detour")
+      .field("name", &self.name())
+      .field("enabled", &self.is_enabled())
+      .field("detached", &self.is_detached())
+      .field("target", &self.chain.target())
+      .field("patch", &Hex(self.chain.area()))
+      .field("original", &Hex(self.chain.original()))
+      .field("trampoline", &self.trampoline())
+      .field("trampoline_code", &Hex(self.chain.trampoline_code()))
+      .finish()
   }
 }
 
-unsafe impl Send for Detour {}
-unsafe impl Sync for Detour {}
+unsafe impl Send for Hook {}
+unsafe impl Sync for Hook {}
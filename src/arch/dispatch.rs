@@ -0,0 +1,512 @@
+use super::memory;
+use super::reclaim::{Calls, Code};
+use super::stats::Counters;
+use crate::error::{Error, Result};
+use crate::{alloc, arch, util};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::time::Instant;
+use std::{mem, ptr};
+
+/// The maximum number of nested dispatched calls per thread.
+const MAX_DEPTH: usize = 64;
+
+/// The maximum number of per-thread overrides (and bypasses) per thread.
+const MAX_OVERRIDES: usize = 64;
+
+/// A source of unique dispatch IDs, which are never reused.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(1);
+
+/// The address of the shared stub that returns from dispatched detours.
+static RETURN_STUB: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+  static STATE: RefCell<ThreadState> = const { RefCell::new(ThreadState::new()) };
+}
+
+/// A stub which decides, per call, whether a detour is invoked.
+///
+/// The chain links to the stub instead of the detour. The stub calls the
+/// detour unless the call is re-entrant (if guarded), the detour is bypassed
+/// on the current thread, or the detour is not enabled for the current thread.
+/// Otherwise the call is passed on to the next detour, or the original code.
+/// The stub also collects the detour's call statistics, once enabled.
+///
+/// Calls from outside of the detour's caller filter, if any, are passed on by
+/// the stub itself, without entering the dispatcher.
+///
+/// The return address of each dispatched call is swapped with a shared return
+/// stub, so the dispatcher knows once the detour has returned. Unwinding
+/// through a dispatched detour is therefore not supported.
+pub struct Dispatch {
+  context: *const Context,
+  code: Code,
+}
+
+/// Determines which callers a detour applies to (see `set_caller_filter`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallerFilter {
+  /// Calls returning to an address within the range.
+  Range(Range<usize>),
+  /// Calls returning to the module (i.e executable or shared object)
+  /// containing the address. Modules can only be determined on Linux.
+  Module(*const ()),
+}
+
+impl CallerFilter {
+  /// Returns the range of return addresses allowed by the filter.
+  pub fn range(&self) -> Result<Range<usize>> {
+    match self {
+      CallerFilter::Range(range) => Ok(range.clone()),
+      CallerFilter::Module(address) => util::module_range(*address).ok_or(Error::UnknownModule),
+    }
+  }
+}
+
+unsafe impl Send for CallerFilter {}
+unsafe impl Sync for CallerFilter {}
+
+/// The range of return addresses allowed by a caller filter, read by the
+/// generated code.
+#[repr(C)]
+struct Filter {
+  start: AtomicUsize,
+  length: AtomicUsize,
+}
+
+/// The state of a dispatch stub, referenced by the generated code.
+struct Context {
+  filter: Filter,
+  id: usize,
+  /// The detour invoked, which owns every detour it replaced.
+  destination: AtomicPtr<Destination>,
+  next: usize,
+  reentrancy_guard: AtomicBool,
+  thread_scoped: AtomicBool,
+  counters: Counters,
+  calls: Calls,
+}
+
+impl Drop for Context {
+  fn drop(&mut self) {
+    let mut destination = *self.destination.get_mut();
+    while !destination.is_null() {
+      let mut current = unsafe { Box::from_raw(destination) };
+      destination = *current.previous.get_mut();
+    }
+  }
+}
+
+/// A detour invoked by a dispatch stub, and the calls which invoked it.
+///
+/// A destination is kept for as long as its stub once replaced, since calls
+/// which invoked it may have yet to return.
+pub struct Destination {
+  address: usize,
+  /// Calls which invoked the detour, and have yet to return.
+  active: Calls,
+  /// The destination this replaced, if any.
+  previous: AtomicPtr<Destination>,
+}
+
+impl Destination {
+  /// Allocates a destination, so it can be swapped in without allocating.
+  pub fn new(detour: *const ()) -> Box<Self> {
+    Box::new(Destination {
+      address: detour as usize,
+      active: Calls::default(),
+      previous: AtomicPtr::new(ptr::null_mut()),
+    })
+  }
+}
+
+impl Dispatch {
+  /// Allocates a dispatch stub close to `origin`, which calls `next` whenever
+  /// `detour` is not invoked.
+  pub fn new(
+    pool: &alloc::ThreadAllocator,
+    origin: *const (),
+    detour: *const (),
+    next: *const (),
+    calls: &Calls,
+  ) -> Result<Self> {
+    if RETURN_STUB.load(Ordering::SeqCst) == 0 {
+      // The return stub is shared, and never released
+      let leave = leave as extern "C" fn() -> usize as usize;
+      let emitter = arch::meta::dispatch_return_builder(leave);
+      let stub = memory::allocate_pic(pool, &emitter, leave as *const ())?;
+
+      // Another thread may have created the stub meanwhile
+      if RETURN_STUB
+        .compare_exchange(0, stub.as_ptr() as usize, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+      {
+        mem::forget(stub);
+      }
+    }
+
+    let context = Box::new(Context {
+      // Every call is allowed by default
+      filter: Filter {
+        start: AtomicUsize::new(0),
+        length: AtomicUsize::new(usize::MAX),
+      },
+      id: SEQUENCE.fetch_add(1, Ordering::SeqCst),
+      destination: AtomicPtr::new(Box::into_raw(Destination::new(detour))),
+      next: next as usize,
+      reentrancy_guard: AtomicBool::new(true),
+      thread_scoped: AtomicBool::new(false),
+      counters: Counters::default(),
+      calls: calls.clone(),
+    });
+
+    let context_ptr = &*context as *const Context;
+    let enter = enter as extern "C" fn(_, _) -> usize as usize;
+    let filter = &context.filter as *const Filter as usize;
+    let emitter = arch::meta::dispatch_builder(context_ptr as usize, enter, filter, next as usize);
+    let code = memory::allocate_pic(pool, &emitter, origin)?;
+
+    Ok(Dispatch {
+      context: context_ptr,
+      code: Code::new(code, calls.clone()).with_owned(context),
+    })
+  }
+
+  /// Returns the address of the stub.
+  pub fn as_ptr(&self) -> *const () {
+    self.code.as_ptr() as *const ()
+  }
+
+  /// Returns whether an address resides within the stub.
+  pub fn contains(&self, address: usize) -> bool {
+    self.code.contains(address)
+  }
+
+  /// Returns the detour invoked by the stub.
+  pub fn detour(&self) -> *const () {
+    self.context().destination().address as *const ()
+  }
+
+  /// Changes the detour invoked by the stub.
+  pub fn set_detour(&self, detour: *const ()) {
+    self.swap_destination(Destination::new(detour));
+  }
+
+  /// Changes the detour invoked by the stub using a single atomic swap,
+  /// returning the calls which invoked the replaced detour. Nothing is
+  /// allocated or locked.
+  pub fn swap_destination(&self, destination: Box<Destination>) -> Calls {
+    let destination = Box::into_raw(destination);
+    let previous = self.context().destination.swap(destination, Ordering::SeqCst);
+    unsafe {
+      (*destination).previous.store(previous, Ordering::SeqCst);
+      (*previous).active.clone()
+    }
+  }
+
+  /// Returns whether any call which invoked the detour (or any detour it
+  /// replaced) has yet to return.
+  pub fn is_active(&self) -> bool {
+    let mut destination = self.context().destination.load(Ordering::SeqCst) as *const Destination;
+    while let Some(current) = unsafe { destination.as_ref() } {
+      if !current.active.is_idle() {
+        return true;
+      }
+      destination = current.previous.load(Ordering::SeqCst);
+    }
+    false
+  }
+
+  /// Sets whether re-entrant calls on the same thread bypass the detour.
+  pub fn set_reentrancy_guard(&self, enabled: bool) {
+    self.context().reentrancy_guard.store(enabled, Ordering::SeqCst);
+  }
+
+  /// Sets the range of return addresses the detour applies to, if limited.
+  ///
+  /// The bounds are not updated atomically, so a call in progress may
+  /// briefly observe a mix of the previous and new range.
+  pub fn set_caller_range(&self, range: Option<Range<usize>>) {
+    let (start, length) = range.map_or((0, usize::MAX), |range| {
+      (range.start, range.end.saturating_sub(range.start))
+    });
+
+    let filter = &self.context().filter;
+    filter.start.store(start, Ordering::SeqCst);
+    filter.length.store(length, Ordering::SeqCst);
+  }
+
+  /// Sets whether the detour is only invoked on threads which enabled it.
+  pub fn set_thread_scoped(&self, scoped: bool) {
+    self.context().thread_scoped.store(scoped, Ordering::SeqCst);
+  }
+
+  /// Returns the call statistics of the detour.
+  pub fn counters(&self) -> &Counters {
+    &self.context().counters
+  }
+
+  /// Enables or disables the detour for the current thread.
+  pub fn set_enabled_for_current_thread(&self, enabled: bool) -> Result<()> {
+    let id = self.context().id;
+    with_state(|state| state.set_override(id, enabled))?
+  }
+
+  /// Calls a closure whilst the detour is bypassed on the current thread.
+  pub fn bypass<R, F: FnOnce() -> R>(&self, closure: F) -> Result<R> {
+    struct Bypass(usize);
+
+    impl Drop for Bypass {
+      fn drop(&mut self) {
+        let id = self.0;
+        let _ = with_state(|state| state.remove_bypass(id));
+      }
+    }
+
+    let id = self.context().id;
+    with_state(|state| state.push_bypass(id))??;
+
+    let _bypass = Bypass(id);
+    Ok(closure())
+  }
+
+  fn context(&self) -> &Context {
+    unsafe { &*self.context }
+  }
+}
+
+impl Context {
+  /// Returns the current destination of the stub.
+  fn destination(&self) -> &Destination {
+    unsafe { &*self.destination.load(Ordering::SeqCst) }
+  }
+
+  /// Accounts for a call invoking the current destination, which is
+  /// returned.
+  ///
+  /// The destination is read again once the call is accounted for, so a
+  /// detour is never invoked once its calls may have been observed idle
+  /// after being replaced.
+  fn enter_destination(&self) -> &Destination {
+    loop {
+      let destination = self.destination();
+      destination.active.begin();
+      if ptr::eq(destination, self.destination()) {
+        return destination;
+      }
+      destination.active.end();
+    }
+  }
+}
+
+unsafe impl Send for Dispatch {}
+unsafe impl Sync for Dispatch {}
+
+/// A dispatched call that has yet to return.
+#[derive(Clone, Copy)]
+struct Frame {
+  id: usize,
+  return_address: usize,
+  context: *const Context,
+  destination: *const Destination,
+  start: Option<Instant>,
+}
+
+/// The dispatch state of a thread, stored in fixed arrays so dispatching
+/// never allocates.
+struct ThreadState {
+  frames: [Frame; MAX_DEPTH],
+  depth: usize,
+  overrides: [(usize, bool); MAX_OVERRIDES],
+  override_count: usize,
+  bypasses: [usize; MAX_OVERRIDES],
+  bypass_count: usize,
+}
+
+impl ThreadState {
+  const fn new() -> Self {
+    ThreadState {
+      frames: [Frame {
+        id: 0,
+        return_address: 0,
+        context: ptr::null(),
+        destination: ptr::null(),
+        start: None,
+      }; MAX_DEPTH],
+      depth: 0,
+      overrides: [(0, false); MAX_OVERRIDES],
+      override_count: 0,
+      bypasses: [0; MAX_OVERRIDES],
+      bypass_count: 0,
+    }
+  }
+
+  /// Returns whether a detour should be invoked on this thread.
+  fn should_invoke(&self, context: &Context) -> bool {
+    if self.depth == MAX_DEPTH || self.bypasses[..self.bypass_count].contains(&context.id) {
+      return false;
+    }
+
+    if context.reentrancy_guard.load(Ordering::SeqCst)
+      && self.frames[..self.depth].iter().any(|frame| frame.id == context.id)
+    {
+      return false;
+    }
+
+    self.overrides[..self.override_count]
+      .iter()
+      .find(|(id, _)| *id == context.id)
+      .map_or(!context.thread_scoped.load(Ordering::SeqCst), |(_, enabled)| *enabled)
+  }
+
+  fn set_override(&mut self, id: usize, enabled: bool) -> Result<()> {
+    let count = self.override_count;
+    if let Some(entry) = self.overrides[..count].iter_mut().find(|(other, _)| *other == id) {
+      entry.1 = enabled;
+    } else if count < MAX_OVERRIDES {
+      self.overrides[count] = (id, enabled);
+      self.override_count += 1;
+    } else {
+      Err(Error::OutOfMemory)?;
+    }
+    Ok(())
+  }
+
+  fn push_bypass(&mut self, id: usize) -> Result<()> {
+    if self.bypass_count == MAX_OVERRIDES {
+      Err(Error::OutOfMemory)?;
+    }
+
+    self.bypasses[self.bypass_count] = id;
+    self.bypass_count += 1;
+    Ok(())
+  }
+
+  fn remove_bypass(&mut self, id: usize) {
+    // Bypasses are usually nested, so the most recent one is removed
+    let count = self.bypass_count;
+    if let Some(index) = self.bypasses[..count].iter().rposition(|other| *other == id) {
+      self.bypasses.copy_within(index + 1..count, index);
+      self.bypass_count -= 1;
+    }
+  }
+}
+
+/// Accesses the state of the current thread.
+fn with_state<R, F: FnOnce(&mut ThreadState) -> R>(closure: F) -> Result<R> {
+  STATE
+    .try_with(|state| {
+      state
+        .try_borrow_mut()
+        .map(|mut state| closure(&mut state))
+        .map_err(|_| Error::NotInitialized)
+    })
+    .map_err(|_| Error::NotInitialized)?
+}
+
+/// Returns the return address of the innermost dispatched call of the
+/// current thread (i.e the caller of the hooked function), if any.
+///
+/// Only calls relayed through a dispatch stub are recorded. A detour is
+/// relayed through one once created with `RelayMode::Dispatch`, or once a
+/// caller filter, statistics, or any per-thread feature is used.
+///
+/// # Example
+///
+/// ```rust
+/// # use retour::Result;
+/// use retour::{DetourBuilder, RelayMode};
+/// use std::sync::atomic::{AtomicBool, Ordering};
+///
+/// static RECORDED: AtomicBool = AtomicBool::new(false);
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn add10(val: i32) -> i32 {
+///   RECORDED.store(retour::caller().is_some(), Ordering::SeqCst);
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe {
+///   DetourBuilder::generic(add5 as extern "C" fn(i32) -> i32, add10 as extern "C" fn(i32) -> i32)
+///     .relay(RelayMode::Dispatch)
+///     .build()?
+/// };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+/// assert!(RECORDED.load(Ordering::SeqCst));
+/// assert_eq!(retour::caller(), None);
+/// # Ok(())
+/// # }
+/// ```
+pub fn caller() -> Option<*const ()> {
+  with_state(|state| {
+    let depth = state.depth.checked_sub(1)?;
+    Some(state.frames[depth].return_address as *const ())
+  })
+  .ok()?
+}
+
+/// Decides whether a detour is invoked, returning the address to jump to.
+///
+/// If invoked, the return address is replaced with the shared return stub,
+/// and the call is accounted for until it returns.
+extern "C" fn enter(context: &Context, return_address: *mut usize) -> usize {
+  let invoked = with_state(|state| {
+    if !state.should_invoke(context) {
+      return None;
+    }
+
+    let destination = context.enter_destination();
+    state.frames[state.depth] = Frame {
+      id: context.id,
+      return_address: unsafe { *return_address },
+      context,
+      destination,
+      start: context.counters.start(),
+    };
+    state.depth += 1;
+    Some(destination)
+  });
+
+  let invoked = invoked.ok().flatten();
+  context.counters.count(invoked.is_some());
+
+  match invoked {
+    Some(destination) => {
+      context.calls.begin();
+      unsafe { *return_address = RETURN_STUB.load(Ordering::SeqCst) };
+      destination.address
+    },
+    None => context.next,
+  }
+}
+
+/// Returns the original return address of the most recent dispatched call.
+extern "C" fn leave() -> usize {
+  let frame = with_state(|state| {
+    state.depth = state.depth.checked_sub(1)?;
+    Some(state.frames[state.depth])
+  });
+
+  match frame {
+    Ok(Some(frame)) => {
+      let context = unsafe { &*frame.context };
+      if let Some(start) = frame.start {
+        context.counters.add_detour_time(start);
+      }
+
+      unsafe { (*frame.destination).active.end() };
+      context.calls.end();
+      frame.return_address
+    },
+    // There is no way to continue without a return address
+    _ => std::process::abort(),
+  }
+}
@@ -0,0 +1,143 @@
+//! Fork safety of the global locks, and the detours inherited by a child.
+//!
+//! A forked child only inherits the forking thread, so any lock held by
+//! another thread would remain locked forever. Every global lock is therefore
+//! acquired before forking, and released again in both the parent and the
+//! child.
+//!
+//! Only async-signal-safe work may be done in the child of a multithreaded
+//! process before `fork` returns, so the child merely records its policy,
+//! which is applied once the global lock is first acquired.
+use super::{chain, memory, reclaim, registry};
+use once_cell::sync::Lazy;
+use std::any::Any;
+use std::cell::RefCell;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, Once, PoisonError, RwLockWriteGuard};
+
+/// The policy applied by forked children.
+static POLICY: Lazy<Mutex<ForkPolicy>> = Lazy::new(Default::default);
+
+/// The policy recorded by a forked child, until it is applied.
+static PENDING: AtomicPtr<ForkPolicy> = AtomicPtr::new(ptr::null_mut());
+
+thread_local! {
+  /// The locks held by the forking thread, and the policy of the child.
+  static HELD: RefCell<Option<Held>> = const { RefCell::new(None) };
+}
+
+/// Determines what a forked child does with the detours it inherits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForkPolicy {
+  /// The detours remain as they are (default).
+  Keep,
+  /// Every detour is disabled.
+  DisableAll,
+  /// Every detour of the listed groups is disabled.
+  DisableGroups(Vec<String>),
+}
+
+impl Default for ForkPolicy {
+  fn default() -> Self {
+    ForkPolicy::Keep
+  }
+}
+
+/// Sets what forked children do with the detours they inherit.
+///
+/// Only the detours of the child are affected. Since nothing but
+/// async-signal-safe work may be done whilst forking, the policy is applied
+/// once the child first calls into this library in a way that acquires its
+/// global lock (e.g creating, toggling or dropping a detour, or using the
+/// registry). Until then, the child's detours remain as inherited.
+pub fn set_fork_policy(policy: ForkPolicy) {
+  install();
+  *POLICY.lock().unwrap() = policy;
+}
+
+/// The locks held whilst forking, released in reverse order.
+struct Held {
+  policy: Box<ForkPolicy>,
+  _allocators: Vec<Box<dyn Any>>,
+  _retired: Box<dyn Any>,
+  _hooks: Box<dyn Any>,
+  _chains: Box<dyn Any>,
+  _threads: Box<dyn Any>,
+  _lock: RwLockWriteGuard<'static, ()>,
+}
+
+/// Installs the fork handlers, once.
+pub fn install() {
+  static INSTALL: Once = Once::new();
+  INSTALL.call_once(|| unsafe {
+    libc::pthread_atfork(Some(prepare), Some(parent), Some(child));
+  });
+}
+
+/// Acquires every global lock, in the same order as elsewhere.
+///
+/// The locks of individual chains and pages are only acquired whilst the
+/// global lock is shared, so they are released once it is held exclusively.
+unsafe extern "C" fn prepare() {
+  // The policy is allocated for the child, which may not allocate
+  let policy = Box::new(POLICY.lock().unwrap_or_else(PoisonError::into_inner).clone());
+  let lock = memory::LOCK.write().unwrap_or_else(PoisonError::into_inner);
+
+  let held = Held {
+    _threads: Box::new(memory::THREADS.lock().unwrap_or_else(PoisonError::into_inner)),
+    _chains: Box::new(chain::lock_all()),
+    _hooks: Box::new(registry::lock()),
+    _retired: Box::new(reclaim::lock()),
+    _allocators: memory::pools()
+      .iter()
+      .map(|allocator| Box::new(allocator.lock()) as Box<dyn Any>)
+      .collect(),
+    _lock: lock,
+    policy,
+  };
+
+  let _ = HELD.try_with(|cell| *cell.borrow_mut() = Some(held));
+}
+
+/// Releases every global lock in the parent.
+unsafe extern "C" fn parent() {
+  let _ = HELD.try_with(|cell| cell.borrow_mut().take());
+}
+
+/// Releases every global lock in the child, and records the fork policy.
+unsafe extern "C" fn child() {
+  let held = HELD.try_with(|cell| cell.borrow_mut().take()).ok().flatten();
+
+  if let Some(held) = held {
+    if *held.policy != ForkPolicy::Keep {
+      PENDING.store(Box::into_raw(held.policy), Ordering::SeqCst);
+    }
+  }
+}
+
+/// Applies the policy recorded by a forked child, if any.
+///
+/// This is called before the global lock is acquired, so it must not be held.
+pub fn apply_policy() {
+  if PENDING.load(Ordering::Relaxed).is_null() {
+    return;
+  }
+
+  let policy = PENDING.swap(ptr::null_mut(), Ordering::SeqCst);
+  if policy.is_null() {
+    return;
+  }
+
+  match *unsafe { Box::from_raw(policy) } {
+    ForkPolicy::DisableAll => {
+      let _ = unsafe { registry::disable_all() };
+    },
+    ForkPolicy::DisableGroups(groups) => {
+      for group in &groups {
+        let _ = unsafe { registry::disable_group(group) };
+      }
+    },
+    ForkPolicy::Keep => (),
+  }
+}
@@ -0,0 +1,79 @@
+use super::reclaim::Calls;
+use super::relay::Relay;
+use crate::alloc;
+use crate::error::Result;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A relay which either calls a detour or passes the call on.
+///
+/// A signal-safe detour is linked to its gate, and its target patched, for as
+/// long as the detour exists. Enabling or disabling the detour then only
+/// swaps the gate's destination, which requires no locks, allocations nor
+/// memory protection changes.
+pub struct Gate {
+  relay: Relay,
+  detour: AtomicUsize,
+  next: *const (),
+  open: AtomicBool,
+}
+
+impl Gate {
+  /// Allocates a closed gate close to `origin`, which calls `next` until
+  /// opened.
+  pub fn new(
+    pool: &alloc::ThreadAllocator,
+    origin: *const (),
+    detour: *const (),
+    next: *const (),
+    calls: &Calls,
+  ) -> Result<Self> {
+    Ok(Gate {
+      relay: Relay::new(pool, origin, next, calls)?,
+      detour: AtomicUsize::new(detour as usize),
+      open: AtomicBool::new(false),
+      next,
+    })
+  }
+
+  /// Returns the address of the gate.
+  pub fn as_ptr(&self) -> *const () {
+    self.relay.as_ptr()
+  }
+
+  /// Returns whether an address resides within the gate.
+  pub fn contains(&self, address: usize) -> bool {
+    self.relay.contains(address)
+  }
+
+  /// Returns the detour called whilst the gate is open.
+  pub fn detour(&self) -> *const () {
+    self.detour.load(Ordering::SeqCst) as *const ()
+  }
+
+  /// Changes the detour called whilst the gate is open.
+  pub fn set_detour(&self, detour: *const ()) {
+    self.detour.store(detour as usize, Ordering::SeqCst);
+    self.update();
+  }
+
+  /// Opens or closes the gate, using atomic operations only.
+  pub fn set_open(&self, open: bool) {
+    self.open.store(open, Ordering::SeqCst);
+    self.update();
+  }
+
+  /// Points the relay to the detour or the next destination.
+  fn update(&self) {
+    loop {
+      let open = self.open.load(Ordering::SeqCst);
+      self
+        .relay
+        .set_destination(if open { self.detour() } else { self.next });
+
+      // The gate may have been toggled meanwhile (e.g by a signal handler)
+      if self.open.load(Ordering::SeqCst) == open {
+        break;
+      }
+    }
+  }
+}
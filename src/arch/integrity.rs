@@ -0,0 +1,117 @@
+use super::registry::{self, HookInfo};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The state of a target's code, compared to the code written by this
+/// library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Integrity {
+  /// The target contains the expected code (i.e the patch whilst enabled, or
+  /// the original code whilst disabled).
+  Intact,
+  /// The target is expected to be patched, but contains its original code
+  /// (e.g restored by another party).
+  Restored,
+  /// The target contains unrecognized code, most likely written by another
+  /// hooking library.
+  Overwritten(Vec<u8>),
+  /// The target's module has been unloaded, so its code is no longer
+  /// accessed (see [detach_unloaded](./registry/fn.detach_unloaded.html)).
+  Detached,
+}
+
+/// A background thread which periodically verifies every detour.
+///
+/// Each detour whose target has been modified by another party is reported,
+/// and optionally patched again. The thread is stopped once dropped.
+///
+/// # Example
+///
+/// ```rust
+/// use retour::Watchdog;
+/// use std::time::Duration;
+///
+/// let watchdog = Watchdog::spawn(Duration::from_secs(1), |hook, integrity| {
+///   eprintln!("{:?} has been modified: {:?}", hook.name, integrity);
+/// });
+/// # drop(watchdog);
+/// ```
+pub struct Watchdog {
+  stopped: Arc<(Mutex<bool>, Condvar)>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+  /// Spawns a thread which verifies every detour at an interval.
+  ///
+  /// The report is called for each detour whose target has been modified.
+  pub fn spawn<F>(interval: Duration, report: F) -> Self
+  where
+    F: FnMut(&HookInfo, &Integrity) + Send + 'static,
+  {
+    unsafe { Self::start(interval, false, report) }
+  }
+
+  /// Spawns a thread which verifies every detour at an interval, and patches
+  /// each modified target again.
+  ///
+  /// The report is called for each detour whose target has been modified,
+  /// before it is patched again.
+  ///
+  /// # Safety
+  ///
+  /// Any code written to the targets by another party is overwritten.
+  /// Otherwise the same requirements as for enabling each detour apply.
+  pub unsafe fn spawn_reapplying<F>(interval: Duration, report: F) -> Self
+  where
+    F: FnMut(&HookInfo, &Integrity) + Send + 'static,
+  {
+    Self::start(interval, true, report)
+  }
+
+  unsafe fn start<F>(interval: Duration, reapply: bool, mut report: F) -> Self
+  where
+    F: FnMut(&HookInfo, &Integrity) + Send + 'static,
+  {
+    let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+    let thread = {
+      let stopped = stopped.clone();
+      thread::spawn(move || {
+        let (lock, condvar) = &*stopped;
+        let mut is_stopped = lock.lock().unwrap();
+
+        while !*is_stopped {
+          is_stopped = condvar.wait_timeout(is_stopped, interval).unwrap().0;
+          if *is_stopped {
+            break;
+          }
+
+          for (hook, integrity) in unsafe { registry::check(reapply) } {
+            report(&hook, &integrity);
+          }
+        }
+      })
+    };
+
+    Watchdog {
+      thread: Some(thread),
+      stopped,
+    }
+  }
+}
+
+impl Drop for Watchdog {
+  /// Stops the watchdog, waiting for any verification in progress.
+  fn drop(&mut self) {
+    let (lock, condvar) = &*self.stopped;
+    if let Ok(mut is_stopped) = lock.lock() {
+      *is_stopped = true;
+      condvar.notify_all();
+    }
+
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
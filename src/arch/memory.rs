@@ -1,17 +1,129 @@
 use once_cell::sync::Lazy;
 
-use crate::{alloc, arch, error::Result, pic};
-use std::sync::Mutex;
+use crate::error::{RegionError, RegionOperation, Result};
+use crate::{alloc, arch, pic};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{
+  LockResult, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult,
+};
 
-/// Shared allocator for all detours.
-pub static POOL: Lazy<Mutex<alloc::ThreadAllocator>> = Lazy::new(|| {
+/// The number of allocators, and of locks guarding memory protection.
+const SHARDS: usize = 16;
+
+/// Serializes operations on several detours (e.g transactions, the registry
+/// and forking), which lock it exclusively, with operations on individual
+/// detours, which share it.
+pub static LOCK: Lazy<GlobalLock> = Lazy::new(|| {
+  // The lock must not be held by another thread whilst forking
+  #[cfg(unix)]
+  arch::fork::install();
+
+  GlobalLock(RwLock::new(()))
+});
+
+/// The global lock, which applies the policy of a forked child before it is
+/// first acquired (see `fork::apply_policy`).
+pub struct GlobalLock(RwLock<()>);
+
+impl GlobalLock {
+  /// Acquires the lock shared.
+  pub fn read(&self) -> LockResult<RwLockReadGuard<'_, ()>> {
+    #[cfg(unix)]
+    arch::fork::apply_policy();
+    self.0.read()
+  }
+
+  /// Acquires the lock exclusively.
+  pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, ()>> {
+    #[cfg(unix)]
+    arch::fork::apply_policy();
+    self.0.write()
+  }
+
+  /// Attempts to acquire the lock exclusively, without blocking.
+  pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, ()>> {
+    self.0.try_write()
+  }
+}
+
+/// Serializes suspending threads and redirecting breakpoints, which rely on
+/// process-wide state.
+pub static THREADS: Lazy<Mutex<()>> = Lazy::new(Default::default);
+
+/// Allocators for all detours, each shared by a subset of threads.
+static POOLS: Lazy<Vec<alloc::ThreadAllocator>> = Lazy::new(|| {
   // Use a range of +/- 2 GB for seeking a memory block
-  Mutex::new(alloc::ThreadAllocator::new(arch::meta::DETOUR_RANGE))
+  (0..SHARDS)
+    .map(|_| alloc::ThreadAllocator::new(arch::meta::DETOUR_RANGE))
+    .collect()
 });
 
+/// Locks serializing memory protection changes, indexed by page.
+static PAGES: Lazy<Vec<Mutex<()>>> = Lazy::new(|| (0..SHARDS).map(|_| Mutex::default()).collect());
+
+/// Returns the allocator of the current thread.
+///
+/// Threads are assigned an allocator in turn, so detours can be created in
+/// parallel. Memory is always released to the allocator it came from.
+pub fn pool() -> &'static alloc::ThreadAllocator {
+  static NEXT: AtomicUsize = AtomicUsize::new(0);
+  thread_local! {
+    static SHARD: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+  }
+
+  &POOLS[SHARD.try_with(|shard| *shard).unwrap_or(0)]
+}
+
+/// Returns every allocator.
+pub fn pools() -> &'static [alloc::ThreadAllocator] {
+  &POOLS
+}
+
+/// An area made writable, until dropped.
+pub struct Writable {
+  _handle: region::ProtectGuard,
+  _pages: Vec<MutexGuard<'static, ()>>,
+}
+
+/// Makes an area writable, whilst no other thread changes the protection of
+/// its pages.
+///
+/// Without serialization, a thread restoring the protection of a page could
+/// make it read-only whilst another thread is writing to it.
+pub unsafe fn protect(area: &[u8]) -> Result<Writable> {
+  let start = area.as_ptr() as usize;
+  let first = region::page::floor(start as *const ()) as usize / region::page::size();
+  let last = region::page::floor((start + area.len().max(1) - 1) as *const ()) as usize
+    / region::page::size();
+
+  // Locks are always acquired in ascending order
+  let mut shards = (first..=last).map(|page| page % SHARDS).collect::<Vec<_>>();
+  shards.sort_unstable();
+  shards.dedup();
+
+  let pages = shards
+    .into_iter()
+    .map(|shard| PAGES[shard].lock().unwrap())
+    .collect();
+
+  // Runtime code is by default only read-execute
+  let handle = region::protect_with_handle(
+    area.as_ptr(),
+    area.len(),
+    region::Protection::READ_WRITE_EXECUTE,
+  )
+  .map_err(RegionError::map(RegionOperation::Protect, area.as_ptr() as *const ()))?;
+
+  Ok(Writable {
+    _handle: handle,
+    _pages: pages,
+  })
+}
+
 /// Allocates PIC code at the specified address.
 pub fn allocate_pic(
-  pool: &mut alloc::ThreadAllocator,
+  pool: &alloc::ThreadAllocator,
   emitter: &pic::CodeEmitter,
   origin: *const (),
 ) -> Result<alloc::ExecutableMemory> {
@@ -23,3 +135,45 @@ pub fn allocate_pic(
     memory
   })
 }
+
+/// Makes the pages of several areas writable, with one change per page.
+///
+/// The global lock must be held exclusively.
+pub unsafe fn protect_areas<'a, I: IntoIterator<Item = &'a [u8]>>(
+  areas: I,
+) -> Result<Vec<region::ProtectGuard>> {
+  let page_size = region::page::size();
+  let mut ranges = areas
+    .into_iter()
+    // The areas of detached targets are empty, and never written
+    .filter(|area| !area.is_empty())
+    .map(|area| {
+      let start = area.as_ptr() as usize;
+      region::page::floor(start as *const ()) as usize
+        ..region::page::ceil((start + area.len()) as *const ()) as usize
+    })
+    .collect::<Vec<Range<usize>>>();
+
+  // Merge overlapping and adjacent pages into continuous ranges
+  ranges.sort_by_key(|range| range.start);
+  let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+  for range in ranges {
+    match merged.last_mut() {
+      Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+      _ => merged.push(range),
+    }
+  }
+
+  merged
+    .into_iter()
+    .map(|range| {
+      debug_assert_eq!(range.len() % page_size, 0);
+      region::protect_with_handle(
+        range.start as *const u8,
+        range.len(),
+        region::Protection::READ_WRITE_EXECUTE,
+      )
+      .map_err(RegionError::map(RegionOperation::Protect, range.start as *const ()))
+    })
+    .collect()
+}
@@ -2,16 +2,31 @@
 ///
 /// The current implementation requires a module to expose some functionality:
 ///
-/// - A standalone `relay_builder` function.
-/// This function creates a relay for targets with large displacement, that
-/// requires special attention. An example would be detours further away than
-/// 2GB on x64. A relative jump is not enough, so the `relay_builder`
-/// generates an absolute jump that the relative jump can reach. If it's
-/// needless, `None` can   be returned.
+/// - A standalone `relay_builder` function, and its `relay_slot` companion.
+/// This function creates a relay with an absolute destination, that can be
+/// swapped atomically. Every patched target jumps to such a relay, which
+/// allows for destinations further away than 2GB on x64, and for the links
+/// of a detour chain to be updated without modifying the target.
+///
+/// - A standalone `jump_destination` function, which decodes unconditional
+/// jumps for resolving stubs in front of a function.
 ///
 /// - A `Patcher`, modifies a target in-memory.
 /// - A `Trampoline`, generates a callable address to the target.
-pub use self::detour::Detour;
+#[cfg(feature = "std")]
+pub use self::chain::is_detoured;
+#[cfg(feature = "std")]
+pub use self::detour::{Detour, Hook, Options};
+#[cfg(feature = "std")]
+pub use self::dispatch::{caller, CallerFilter, Destination};
+#[cfg(all(feature = "std", unix))]
+pub use self::fork::{set_fork_policy, ForkPolicy};
+#[cfg(feature = "std")]
+pub use self::integrity::{Integrity, Watchdog};
+#[cfg(feature = "std")]
+pub use self::reclaim::wait_for_quiescence;
+#[cfg(feature = "std")]
+pub use self::stats::Stats;
 
 use cfg_if::cfg_if;
 
@@ -20,17 +35,77 @@ use cfg_if::cfg_if;
 cfg_if! {
     if #[cfg(any(target_arch = "x86", target_arch = "x86_64"))] {
         mod x86;
-        use self::x86::{Patcher, Trampoline, meta};
+        pub use self::x86::{meta, patcher, Trampoline};
+        #[cfg(feature = "std")]
+        pub use self::x86::Patcher;
+        pub use self::x86::meta::DETOUR_RANGE;
     } else {
         // TODO: Implement ARM/AARCH64/MIPS support!
     }
 }
 
+#[cfg(feature = "std")]
+mod chain;
+#[cfg(feature = "std")]
+pub mod crash;
+#[cfg(feature = "std")]
 mod detour;
-mod memory;
+#[cfg(feature = "std")]
+mod dispatch;
+#[cfg(all(feature = "std", unix))]
+mod fork;
+#[cfg(feature = "std")]
+mod gate;
+#[cfg(feature = "std")]
+mod integrity;
+#[cfg(feature = "std")]
+pub mod memory;
+#[cfg(feature = "std")]
+mod modules;
+#[cfg(feature = "std")]
+pub mod reclaim;
+#[cfg(feature = "std")]
+pub mod registry;
+#[cfg(feature = "std")]
+mod relay;
+#[cfg(feature = "std")]
+mod stats;
 
 /// Returns true if the displacement is within a certain range.
 pub fn is_within_range(displacement: isize) -> bool {
   let range = meta::DETOUR_RANGE as i64;
   (-range..range).contains(&(displacement as i64))
 }
+
+/// Returns the destination of an unconditional jump at an address, if any.
+#[cfg(feature = "std")]
+pub unsafe fn jump_destination(address: *const ()) -> Option<*const ()> {
+  meta::jump_destination(address).map(|destination| destination as *const ())
+}
+
+/// Analyzes how a target would be detoured, without modifying it.
+#[cfg(feature = "std")]
+pub unsafe fn analyze(target: *const (), options: &Options) -> crate::HookPlan {
+  let mut plan = crate::HookPlan {
+    target,
+    instructions: Vec::new(),
+    patch: None,
+    chained: is_detoured(target),
+    error: None,
+  };
+
+  let result = crate::util::is_executable_address(target).and_then(|executable| {
+    if !executable {
+      Err(crate::Error::NotExecutable)?;
+    }
+
+    let margin = options.margin.unwrap_or_else(|| meta::prolog_margin(target));
+    let (instructions, trampoline) = Trampoline::analyze(target, margin);
+    plan.instructions = instructions;
+    plan.patch = Some(Patcher::patch_kind(target, trampoline?.prolog_size(), options)?);
+    Ok(())
+  });
+
+  plan.error = result.err();
+  plan
+}
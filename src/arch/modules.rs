@@ -0,0 +1,223 @@
+//! The modules containing the targets, which may be unloaded.
+//!
+//! The module of each target is determined once its chain is created, using
+//! the loaded shared objects (i.e `dl_iterate_phdr`) and, for any other file
+//! mapping, `/proc/self/maps`. Once a module has been unloaded, writing the
+//! original code back to its target would corrupt whatever is mapped at the
+//! address now, so the chains of its targets are detached instead (see
+//! [detach_unloaded](super::registry::detach_unloaded)).
+//!
+//! Modules can only be determined on Linux.
+use super::chain::Chain;
+use super::memory;
+use crate::util;
+use std::ops::Range;
+
+/// A module containing a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Module {
+  /// A shared object, identified by its name and load address.
+  Shared { name: String, base: usize },
+  /// Any other file mapping, identified by its path.
+  Mapped { path: String },
+}
+
+/// A loaded shared object.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Object {
+  name: String,
+  base: usize,
+  segments: Vec<Range<usize>>,
+}
+
+impl Module {
+  /// Returns the module containing an address, unless it cannot be unloaded
+  /// (i.e the executable) or cannot be determined.
+  pub fn of(address: *const ()) -> Option<Module> {
+    let address = address as usize;
+    let object = objects()
+      .into_iter()
+      .find(|object| object.segments.iter().any(|segment| segment.contains(&address)));
+
+    if let Some(object) = object {
+      // The executable is listed without a name, and is never unloaded
+      if object.name.is_empty() {
+        return None;
+      }
+
+      return Some(Module::Shared {
+        name: object.name,
+        base: object.base,
+      });
+    }
+
+    // Pseudo paths such as '[heap]' are not modules
+    util::mappings()?
+      .into_iter()
+      .find(|(range, _)| range.contains(&address))
+      .and_then(|(_, path)| path)
+      .filter(|path| !path.starts_with('['))
+      .map(|path| Module::Mapped { path })
+  }
+
+  /// Returns the module of a shared object opened using `dlopen`.
+  #[cfg(all(target_os = "linux", target_env = "gnu"))]
+  unsafe fn of_handle(handle: *mut std::os::raw::c_void) -> Option<Module> {
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+
+    /// The leading fields of glibc's `struct link_map`.
+    #[repr(C)]
+    struct LinkMap {
+      l_addr: usize,
+      l_name: *const c_char,
+    }
+
+    let mut map: *const LinkMap = std::ptr::null();
+    let info = &mut map as *mut *const LinkMap;
+    if libc::dlinfo(handle, libc::RTLD_DI_LINKMAP, info.cast()) != 0 || map.is_null() {
+      return None;
+    }
+
+    let map = &*map;
+    if map.l_name.is_null() {
+      return None;
+    }
+
+    Some(Module::Shared {
+      name: CStr::from_ptr(map.l_name).to_string_lossy().into_owned(),
+      base: map.l_addr,
+    })
+  }
+
+  /// Returns the module of a shared object opened using `dlopen`.
+  #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+  unsafe fn of_handle(_handle: *mut std::os::raw::c_void) -> Option<Module> {
+    None
+  }
+
+  /// Returns whether the module is still loaded.
+  fn is_loaded(&self, objects: &[Object], mappings: &mut Mappings) -> bool {
+    match self {
+      Module::Shared { name, base } => objects
+        .iter()
+        .any(|object| object.base == *base && object.name == *name),
+      Module::Mapped { path } => mappings
+        .get_or_insert_with(|| util::mappings().unwrap_or_default())
+        .iter()
+        .any(|(_, other)| other.as_deref() == Some(path.as_str())),
+    }
+  }
+}
+
+/// Detaches the detours of every target whose module has been unloaded,
+/// returning their targets.
+pub fn detach_unloaded() -> Vec<*const ()> {
+  let objects = objects();
+  let mut mappings = None;
+
+  // Detours of the affected targets are not toggled meanwhile
+  let _guard = memory::LOCK.read().unwrap();
+  let targets = Chain::detach_unloaded(|module| module.is_loaded(&objects, &mut mappings));
+
+  for &target in &targets {
+    event!("detached target", target = target);
+  }
+  targets
+}
+
+/// File mappings, read once required.
+type Mappings = Option<Vec<(Range<usize>, Option<String>)>>;
+
+/// Returns every loaded shared object.
+#[cfg(target_os = "linux")]
+fn objects() -> Vec<Object> {
+  use std::ffi::CStr;
+  use std::os::raw::{c_int, c_void};
+
+  unsafe extern "C" fn visit(info: *mut libc::dl_phdr_info, _size: usize, data: *mut c_void) -> c_int {
+    let info = &*info;
+    let objects = &mut *(data as *mut Vec<Object>);
+    let name = if info.dlpi_name.is_null() {
+      String::new()
+    } else {
+      CStr::from_ptr(info.dlpi_name).to_string_lossy().into_owned()
+    };
+
+    let base = info.dlpi_addr as usize;
+    let segments = (0..info.dlpi_phnum as usize)
+      .map(|index| &*info.dlpi_phdr.add(index))
+      .filter(|header| header.p_type == libc::PT_LOAD)
+      .map(|header| {
+        let start = base + header.p_vaddr as usize;
+        start..start + header.p_memsz as usize
+      })
+      .collect();
+
+    objects.push(Object { name, base, segments });
+    0
+  }
+
+  let mut objects = Vec::new();
+  unsafe { libc::dl_iterate_phdr(Some(visit), &mut objects as *mut Vec<Object> as *mut c_void) };
+  objects
+}
+
+#[cfg(not(target_os = "linux"))]
+fn objects() -> Vec<Object> {
+  Vec::new()
+}
+
+/// Detours `dlclose`, so detours are detached once their target's module is
+/// unloaded. This is only done once.
+///
+/// The detours of the closed shared object's targets can neither be toggled
+/// nor dropped until `dlclose` returns, since the object may be unmapped
+/// meanwhile. Its destructors must therefore not toggle or drop them, which
+/// would deadlock. The objects unloaded along with it (i.e its dependencies)
+/// are detached once `dlclose` returns.
+#[cfg(unix)]
+pub fn install() -> crate::error::Result<()> {
+  use super::Detour;
+  use once_cell::sync::OnceCell;
+  use std::os::raw::{c_int, c_void};
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static HOOK: OnceCell<Detour> = OnceCell::new();
+  static ORIGINAL: AtomicUsize = AtomicUsize::new(0);
+
+  unsafe extern "C" fn dlclose(handle: *mut c_void) -> c_int {
+    let original: unsafe extern "C" fn(*mut c_void) -> c_int =
+      std::mem::transmute(ORIGINAL.load(Ordering::SeqCst));
+
+    // The chains are locked as when toggling a detour, until detached
+    let module = Module::of_handle(handle);
+    let chains = module.as_ref().map(Chain::of_module).unwrap_or_default();
+    let result = {
+      let _guard = memory::LOCK.read().unwrap();
+      let _states = chains.iter().map(|chain| chain.lock()).collect::<Vec<_>>();
+      let result = original(handle);
+
+      if result == 0 && module.map_or(false, |module| !module.is_loaded(&objects(), &mut None)) {
+        for chain in &chains {
+          Chain::detach(chain);
+          event!("detached target", target = chain.target());
+        }
+      }
+      result
+    };
+
+    if result == 0 {
+      detach_unloaded();
+    }
+    result
+  }
+
+  HOOK.get_or_try_init(|| unsafe {
+    let hook = Detour::new(libc::dlclose as *const (), dlclose as *const ())?;
+    ORIGINAL.store(hook.trampoline() as *const () as usize, Ordering::SeqCst);
+    hook.enable()?;
+    Ok(hook)
+  })?;
+  Ok(())
+}
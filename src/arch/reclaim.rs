@@ -0,0 +1,251 @@
+use super::memory;
+use crate::error::{Error, Result};
+use crate::{alloc, threads};
+use once_cell::sync::Lazy;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use std::{io, mem, thread};
+
+/// Code that has been released, but may still be executed by other threads.
+static RETIRED: Lazy<Mutex<Vec<Retired>>> = Lazy::new(Default::default);
+
+/// A counter of calls in progress through a chain's code.
+#[derive(Clone, Default)]
+pub struct Calls(Arc<AtomicUsize>);
+
+impl Calls {
+  /// Marks a call as in progress until the guard is dropped.
+  pub fn enter(&self) -> CallGuard<'_> {
+    self.begin();
+    CallGuard(&self.0)
+  }
+
+  /// Marks a call as in progress, until explicitly ended.
+  pub fn begin(&self) {
+    self.0.fetch_add(1, Ordering::SeqCst);
+  }
+
+  /// Marks a call, which was explicitly begun, as completed.
+  pub fn end(&self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+
+  /// Returns whether no calls are in progress.
+  pub fn is_idle(&self) -> bool {
+    self.0.load(Ordering::SeqCst) == 0
+  }
+}
+
+/// A call in progress through a detour's trampoline.
+///
+/// Code of a chain is not reclaimed whilst any such call is in progress.
+pub struct CallGuard<'a>(&'a AtomicUsize);
+
+impl<'a> Drop for CallGuard<'a> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// Executable memory that is retired, rather than released, once dropped.
+pub struct Code {
+  memory: Option<alloc::ExecutableMemory>,
+  calls: Calls,
+  owned: Option<Box<dyn Send>>,
+}
+
+impl Code {
+  /// Wraps allocated memory, accounted for by the calls of its chain.
+  pub fn new(memory: alloc::ExecutableMemory, calls: Calls) -> Self {
+    Code {
+      memory: Some(memory),
+      calls,
+      owned: None,
+    }
+  }
+
+  /// Keeps data referenced by the code alive for as long as the code.
+  pub fn with_owned(mut self, owned: Box<dyn Send>) -> Self {
+    self.owned = Some(owned);
+    self
+  }
+
+  /// Returns the address of the code.
+  pub fn as_ptr(&self) -> *const u8 {
+    self.memory.as_ref().expect("retired code").as_ptr()
+  }
+
+  /// Returns the bytes of the code.
+  pub fn bytes(&self) -> &[u8] {
+    self.memory.as_ref().expect("retired code")
+  }
+
+  /// Returns whether an address resides within the code.
+  pub fn contains(&self, address: usize) -> bool {
+    let start = self.as_ptr() as usize;
+    (start..start + self.bytes().len()).contains(&address)
+  }
+}
+
+impl Drop for Code {
+  /// Retires the memory, which is reclaimed once no thread can execute it.
+  fn drop(&mut self) {
+    if let Some(memory) = self.memory.take() {
+      RETIRED.lock().unwrap().push(Retired {
+        memory,
+        calls: self.calls.clone(),
+        _owned: self.owned.take(),
+      });
+    }
+  }
+}
+
+/// Retired code awaiting reclamation.
+struct Retired {
+  memory: alloc::ExecutableMemory,
+  calls: Calls,
+  _owned: Option<Box<dyn Send>>,
+}
+
+impl Retired {
+  /// Returns the address range of the code.
+  fn range(&self) -> Range<usize> {
+    let start = self.memory.as_ptr() as usize;
+    start..start + self.memory.len()
+  }
+
+  /// Returns whether an address resides within the code.
+  fn contains(&self, address: usize) -> bool {
+    self.range().contains(&address)
+  }
+}
+
+/// Returns whether an address resides within retired code, unless the
+/// retired code is locked.
+pub(crate) fn try_is_retired(address: usize) -> Option<bool> {
+  let retired = RETIRED.try_lock().ok()?;
+  Some(retired.iter().any(|code| code.contains(address)))
+}
+
+/// Locks the retired code until the returned guard is dropped.
+pub(crate) fn lock() -> impl Drop {
+  RETIRED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Waits until no thread can be executing the code of any dropped detour,
+/// and reclaims its memory.
+///
+/// The trampolines and relays of a dropped detour are not released
+/// immediately, since other threads may still be executing them, or return
+/// into them (a relocated `call` leaves a return address within the
+/// trampoline). Instead they are retired, and reclaimed whenever a detour is
+/// created or dropped once no thread can be executing them. This function
+/// waits until all of them are reclaimed, which is required before unloading
+/// a library that contains detours, or was detoured.
+///
+/// Code is reclaimed once no calls through `call` (or the trampoline of a
+/// static detour) are in progress and, on platforms which support suspending
+/// threads, no thread is executing it. The stacks of other threads are not
+/// inspected, so calls made directly through a raw trampoline are not
+/// accounted for: callers of raw trampolines must ensure that none of their
+/// calls are in progress (e.g blocked in a function called by the original
+/// code) once the detour is dropped. Returns whether all retired code was
+/// reclaimed before the timeout.
+pub fn wait_for_quiescence(timeout: Duration) -> Result<bool> {
+  let deadline = Instant::now() + timeout;
+
+  loop {
+    if reclaim()? {
+      return Ok(true);
+    }
+
+    if Instant::now() >= deadline {
+      return Ok(false);
+    }
+
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+/// Reclaims retired code which no thread can be executing, without waiting.
+///
+/// Threads are only inspected if any retired code has no calls in progress,
+/// and the code remains retired if they cannot be.
+pub(crate) fn reclaim_idle() {
+  let idle = RETIRED.lock().unwrap().iter().any(|code| code.calls.is_idle());
+  if idle {
+    if let Err(error) = reclaim() {
+      event!("failed to reclaim retired code", error = error);
+    }
+  }
+}
+
+/// Reclaims all retired code which no thread can be executing, returning
+/// whether no retired code remains.
+///
+/// If the threads cannot be inspected, all code remains retired.
+fn reclaim() -> Result<bool> {
+  let retired = mem::take(&mut *RETIRED.lock().unwrap());
+  let (idle, mut busy) = retired
+    .into_iter()
+    .partition::<Vec<_>, _>(|code| code.calls.is_idle());
+
+  let ranges = idle.iter().map(Retired::range).collect::<Vec<_>>();
+  let executing = match executing_threads(&ranges) {
+    Ok(executing) => executing,
+    Err(error) => {
+      let mut retired = RETIRED.lock().unwrap();
+      retired.extend(idle);
+      retired.extend(busy);
+      return Err(error);
+    },
+  };
+
+  let (executed, reclaimable) = idle
+    .into_iter()
+    .zip(executing)
+    .partition::<Vec<_>, _>(|(_, executing)| *executing);
+  mem::drop(reclaimable);
+
+  busy.extend(executed.into_iter().map(|(code, _)| code));
+  let reclaimed = busy.is_empty();
+  RETIRED.lock().unwrap().extend(busy);
+  Ok(reclaimed)
+}
+
+/// Returns whether any thread is executing within each of the address ranges.
+///
+/// Threads can only be inspected on platforms which support suspending them,
+/// elsewhere the code is assumed to be unused once no calls are in progress.
+pub(crate) fn executing_threads(ranges: &[Range<usize>]) -> Result<Vec<bool>> {
+  if ranges.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  // Nothing may be allocated whilst the threads are suspended
+  let executing = ranges
+    .iter()
+    .map(|_| AtomicBool::new(false))
+    .collect::<Vec<_>>();
+
+  // Threads are only suspended by one caller at a time
+  let _guard = memory::THREADS.lock().unwrap();
+  match unsafe { threads::suspend() } {
+    Ok(threads) => unsafe {
+      threads.relocate(|address| {
+        for (range, executing) in ranges.iter().zip(&executing) {
+          if range.contains(&address) {
+            executing.store(true, Ordering::SeqCst);
+          }
+        }
+        None
+      })
+    },
+    Err(Error::ThreadSafety(error)) if error.kind() == io::ErrorKind::Unsupported => (),
+    Err(error) => return Err(error),
+  }
+
+  Ok(executing.into_iter().map(AtomicBool::into_inner).collect())
+}
@@ -0,0 +1,342 @@
+//! A process-wide registry of every detour.
+//!
+//! Each detour is registered once created, and unregistered once dropped,
+//! regardless of whether it was created by this crate or a dependency. Static
+//! detours are never dropped, and remain registered once initialized.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use retour::Result;
+//! use retour::{registry, RawDetour};
+//!
+//! #[inline(never)]
+//! extern "C" fn add5(val: i32) -> i32 {
+//!   unsafe { std::ptr::read_volatile(&val) + 5 }
+//! }
+//!
+//! extern "C" fn add10(val: i32) -> i32 {
+//!   val + 10
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! let hook = unsafe { RawDetour::new(add5 as *const (), add10 as *const ())? };
+//! hook.set_name("add5");
+//! hook.set_group("math");
+//!
+//! unsafe { registry::enable_group("math")? };
+//! assert!(registry::find("add5").unwrap().enabled);
+//! # Ok(())
+//! # }
+//! ```
+use super::detour::Hook;
+use super::integrity::Integrity;
+use super::{memory, modules};
+use crate::error::{Result, RollbackError};
+use once_cell::sync::Lazy;
+use std::panic;
+use std::sync::{Arc, Mutex, Once, PoisonError};
+
+/// All detours of the process, in the order they were created.
+static HOOKS: Lazy<Mutex<Vec<Arc<Hook>>>> = Lazy::new(Default::default);
+
+/// A description of a registered detour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookInfo {
+  /// The name of the detour, if any.
+  pub name: Option<String>,
+  /// The group of the detour, if any.
+  pub group: Option<String>,
+  /// The detoured function.
+  pub target: *const (),
+  /// The function called in place of the target.
+  pub detour: *const (),
+  /// Whether the detour is enabled or not.
+  pub enabled: bool,
+  /// Whether the target's module has been unloaded.
+  pub detached: bool,
+}
+
+impl HookInfo {
+  fn new(hook: &Hook) -> Self {
+    HookInfo {
+      name: hook.name(),
+      group: hook.group(),
+      target: hook.target(),
+      detour: hook.destination(),
+      enabled: hook.is_enabled(),
+      detached: hook.is_detached(),
+    }
+  }
+}
+
+/// Locks the registry until the returned guard is dropped.
+pub(crate) fn lock() -> impl Drop {
+  HOOKS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Registers a detour.
+pub(crate) fn register(hook: Arc<Hook>) {
+  HOOKS.lock().unwrap().push(hook);
+}
+
+/// Unregisters a detour.
+pub(crate) fn unregister(hook: &Arc<Hook>) {
+  HOOKS
+    .lock()
+    .unwrap()
+    .retain(|other| !Arc::ptr_eq(other, hook));
+}
+
+/// Finds a detour matching a predicate, unless the registry is locked.
+pub(crate) fn try_find<R>(mut f: impl FnMut(&Hook) -> Option<R>) -> Option<R> {
+  let hooks = HOOKS.try_lock().ok()?;
+  hooks.iter().find_map(|hook| f(hook))
+}
+
+/// Returns a description of every detour, in the order they were created.
+pub fn hooks() -> Vec<HookInfo> {
+  HOOKS.lock().unwrap().iter().map(|hook| HookInfo::new(hook)).collect()
+}
+
+/// Returns a description of the first detour with a name.
+pub fn find(name: &str) -> Option<HookInfo> {
+  HOOKS
+    .lock()
+    .unwrap()
+    .iter()
+    .find(|hook| hook.name().as_deref() == Some(name))
+    .map(|hook| HookInfo::new(hook))
+}
+
+/// Returns each detour whose target has been modified by another party.
+pub fn verify_all() -> Vec<(HookInfo, Integrity)> {
+  unsafe { check(false) }
+}
+
+/// Returns each detour whose target has been modified by another party,
+/// optionally patching the target again.
+pub(crate) unsafe fn check(reapply: bool) -> Vec<(HookInfo, Integrity)> {
+  let _guard = memory::LOCK.write().unwrap();
+  let hooks = HOOKS.lock().unwrap();
+  let modified = hooks
+    .iter()
+    .map(|hook| (hook, hook.chain_integrity()))
+    .filter(|(_, integrity)| !matches!(integrity, Integrity::Intact | Integrity::Detached))
+    .collect::<Vec<_>>();
+
+  if reapply {
+    for (hook, _) in &modified {
+      // Detours sharing a target are only repaired once
+      let _ = hook.repair();
+    }
+  }
+
+  modified
+    .into_iter()
+    .map(|(hook, integrity)| (HookInfo::new(hook), integrity))
+    .collect()
+}
+
+/// Disables every enabled detour, restoring the original code of each
+/// target.
+///
+/// The detours which were disabled can be enabled again using
+/// [restore_all]. Every detour is attempted, and the first error (if any) is
+/// returned.
+///
+/// # Safety
+///
+/// The same requirements as for disabling each individual detour apply.
+pub unsafe fn disable_all() -> Result<()> {
+  let _guard = memory::LOCK.write().unwrap();
+  suspend(&HOOKS.lock().unwrap())
+}
+
+/// Enables the detours which were disabled by [disable_all].
+///
+/// Every detour is attempted, and the first error (if any) is returned.
+///
+/// # Safety
+///
+/// The same requirements as for enabling each individual detour apply.
+pub unsafe fn restore_all() -> Result<()> {
+  let _guard = memory::LOCK.write().unwrap();
+  let hooks = HOOKS.lock().unwrap();
+  let suspended = hooks
+    .iter()
+    .filter(|hook| hook.set_suspended(false) && !hook.is_detached())
+    .map(|hook| &**hook)
+    .collect::<Vec<_>>();
+  write_all(&suspended, true)
+}
+
+/// Enables every detour of a group.
+///
+/// The detours are enabled together, and if any fails, those already enabled
+/// are disabled again (see [Error::RollbackFailed](crate::Error::RollbackFailed)).
+///
+/// # Safety
+///
+/// The same requirements as for enabling each individual detour apply.
+pub unsafe fn enable_group(group: &str) -> Result<()> {
+  set_group_enabled(group, true)
+}
+
+/// Disables every detour of a group.
+///
+/// The detours are disabled together, and if any fails, those already
+/// disabled are enabled again (see
+/// [Error::RollbackFailed](crate::Error::RollbackFailed)).
+///
+/// # Safety
+///
+/// The same requirements as for disabling each individual detour apply.
+pub unsafe fn disable_group(group: &str) -> Result<()> {
+  set_group_enabled(group, false)
+}
+
+/// Detaches every detour whose target's module (i.e shared object) has been
+/// unloaded, returning a description of each.
+///
+/// Writing the original code back to an unloaded target would corrupt
+/// whatever is mapped at its address now. A detached detour therefore never
+/// accesses its target again: it is reported as disabled (and detached),
+/// disabling or dropping it leaves memory untouched, and enabling it fails
+/// with `ModuleUnloaded`. Its target may be detoured again.
+///
+/// Modules can only be determined on Linux. See [detach_on_unload] for
+/// detaching detours automatically.
+pub fn detach_unloaded() -> Vec<HookInfo> {
+  let targets = modules::detach_unloaded();
+  if targets.is_empty() {
+    return Vec::new();
+  }
+
+  hooks()
+    .into_iter()
+    .filter(|hook| hook.detached && targets.contains(&hook.target))
+    .collect()
+}
+
+/// Detaches detours once their target's module is unloaded using `dlclose`
+/// (see [detach_unloaded]), by detouring `dlclose` itself.
+///
+/// Whilst a shared object is being closed, the detours of its targets can
+/// neither be toggled nor dropped, so its destructors must not toggle or drop
+/// them (which would deadlock). Modules unloaded by other means (e.g
+/// `munmap`) are not detected.
+#[cfg(unix)]
+pub fn detach_on_unload() -> Result<()> {
+  modules::install()
+}
+
+/// Disables every detour once the process exits (e.g when returning from
+/// `main`, or calling `std::process::exit`).
+///
+/// This is required when the process outlives the detours' code, such as
+/// when detouring from a library that may be unloaded during shutdown.
+/// Detours are not disabled if the global lock is held by another thread
+/// whilst exiting.
+pub fn restore_on_exit() {
+  static INSTALL: Once = Once::new();
+  INSTALL.call_once(|| {
+    extern "C" fn on_exit() {
+      teardown();
+    }
+
+    unsafe { libc::atexit(on_exit) };
+  });
+}
+
+/// Disables every detour once any thread panics, before the previous panic
+/// hook is called.
+///
+/// Detours are not disabled if the global lock is held whilst panicking.
+pub fn restore_on_panic() {
+  static INSTALL: Once = Once::new();
+  INSTALL.call_once(|| {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+      teardown();
+      previous(info);
+    }));
+  });
+}
+
+/// Disables every detour, unless the global lock or registry is already
+/// held (possibly by the current thread).
+fn teardown() {
+  if let Ok(_guard) = memory::LOCK.try_write() {
+    if let Ok(hooks) = HOOKS.try_lock() {
+      let _ = unsafe { suspend(&hooks) };
+    }
+  }
+}
+
+/// Disables every enabled detour, marking it as suspended.
+///
+/// The global lock must be held exclusively.
+unsafe fn suspend(hooks: &[Arc<Hook>]) -> Result<()> {
+  let enabled = hooks
+    .iter()
+    .filter(|hook| hook.is_enabled())
+    .map(|hook| &**hook)
+    .collect::<Vec<_>>();
+
+  for hook in &enabled {
+    hook.set_suspended(true);
+  }
+  write_all(&enabled, false)
+}
+
+/// Enables or disables every detour of a group, rolling back if any fails.
+unsafe fn set_group_enabled(group: &str, enabled: bool) -> Result<()> {
+  let _guard = memory::LOCK.write().unwrap();
+  let hooks = HOOKS.lock().unwrap();
+  let members = hooks
+    .iter()
+    .filter(|hook| {
+      hook.group().as_deref() == Some(group) && hook.is_enabled() != enabled && !hook.is_detached()
+    })
+    .map(|hook| &**hook)
+    .collect::<Vec<_>>();
+
+  // Runtime code is by default only read-execute
+  let _handles = memory::protect_areas(members.iter().map(|hook| hook.area()))?;
+
+  for (index, hook) in members.iter().enumerate() {
+    if enabled {
+      hook.clear_thread_scope();
+    }
+
+    if let Err(error) = hook.write(enabled) {
+      // Restore the previous state, in reverse order
+      let mut failed = Vec::new();
+      for hook in members[..index].iter().rev() {
+        if hook.write(!enabled).is_err() {
+          failed.push(hook.target() as usize);
+        }
+      }
+      return Err(RollbackError::check(error, failed));
+    }
+    hook.set_suspended(false);
+  }
+  Ok(())
+}
+
+/// Enables or disables several detours, returning the first error (if any).
+///
+/// The global lock must be held exclusively.
+unsafe fn write_all(hooks: &[&Hook], enabled: bool) -> Result<()> {
+  // Runtime code is by default only read-execute
+  let _handles = memory::protect_areas(hooks.iter().map(|hook| hook.area()))?;
+
+  let mut result = Ok(());
+  for hook in hooks {
+    if let Err(error) = hook.write(enabled) {
+      result = result.and(Err(error));
+    }
+  }
+  result
+}
@@ -0,0 +1,43 @@
+use super::memory;
+use super::reclaim::{Calls, Code};
+use crate::error::Result;
+use crate::{alloc, arch};
+use std::sync::atomic::Ordering;
+
+/// A relay with a destination that can be swapped atomically.
+pub struct Relay(Code);
+
+impl Relay {
+  /// Allocates a relay close to `origin`, accounted for by `calls`.
+  pub fn new(
+    pool: &alloc::ThreadAllocator,
+    origin: *const (),
+    destination: *const (),
+    calls: &Calls,
+  ) -> Result<Self> {
+    let emitter = arch::meta::relay_builder(destination);
+    let memory = memory::allocate_pic(pool, &emitter, origin)?;
+    event!(
+      "allocated relay",
+      relay = memory.as_ptr(),
+      origin = origin,
+      destination = destination,
+    );
+    Ok(Relay(Code::new(memory, calls.clone())))
+  }
+
+  /// Returns the address of the relay.
+  pub fn as_ptr(&self) -> *const () {
+    self.0.as_ptr() as *const ()
+  }
+
+  /// Returns whether an address resides within the relay.
+  pub fn contains(&self, address: usize) -> bool {
+    self.0.contains(address)
+  }
+
+  /// Changes the destination of the relay.
+  pub fn set_destination(&self, destination: *const ()) {
+    unsafe { (*arch::meta::relay_slot(self.as_ptr())).store(destination as usize, Ordering::SeqCst) };
+  }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// Call statistics of a detour.
+///
+/// Statistics are only collected once enabled, using `enable_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+  /// The number of calls which invoked the detour.
+  pub calls: u64,
+  /// The number of calls which were passed on without invoking the detour
+  /// (i.e re-entrant, bypassed, or disabled for the calling thread).
+  pub bypassed: u64,
+  /// The time spent within the detour (including any call to the original
+  /// function), if timed.
+  pub detour_time: Option<Duration>,
+  /// The time spent within the original function, when called through the
+  /// detour's `call` method, if timed.
+  pub original_time: Option<Duration>,
+}
+
+/// Atomic counters, updated by the dispatcher of a detour.
+#[derive(Default)]
+pub struct Counters {
+  mode: AtomicU8,
+  calls: AtomicU64,
+  bypassed: AtomicU64,
+  detour_nanos: AtomicU64,
+  original_nanos: AtomicU64,
+}
+
+const DISABLED: u8 = 0;
+const COUNTED: u8 = 1;
+const TIMED: u8 = 2;
+
+impl Counters {
+  /// Enables collecting statistics, optionally including timings.
+  pub fn enable(&self, timed: bool) {
+    self.mode.store(if timed { TIMED } else { COUNTED }, Ordering::SeqCst);
+  }
+
+  /// Returns whether calls are counted.
+  pub fn is_counted(&self) -> bool {
+    self.mode.load(Ordering::Relaxed) != DISABLED
+  }
+
+  /// Returns the start of a timed call, if calls are timed.
+  pub fn start(&self) -> Option<Instant> {
+    if self.mode.load(Ordering::Relaxed) == TIMED {
+      Some(Instant::now())
+    } else {
+      None
+    }
+  }
+
+  /// Counts a call, which either invoked the detour or not.
+  pub fn count(&self, invoked: bool) {
+    if self.is_counted() {
+      let counter = if invoked { &self.calls } else { &self.bypassed };
+      counter.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  /// Adds the time spent within the detour.
+  pub fn add_detour_time(&self, start: Instant) {
+    self.detour_nanos.fetch_add(nanos(start), Ordering::Relaxed);
+  }
+
+  /// Adds the time spent within the original function.
+  pub fn add_original_time(&self, start: Instant) {
+    self.original_nanos.fetch_add(nanos(start), Ordering::Relaxed);
+  }
+
+  /// Returns a snapshot of the statistics.
+  pub fn snapshot(&self) -> Stats {
+    let timed = self.mode.load(Ordering::SeqCst) == TIMED;
+    let time = |nanos: &AtomicU64| {
+      Some(Duration::from_nanos(nanos.load(Ordering::SeqCst))).filter(|_| timed)
+    };
+
+    Stats {
+      calls: self.calls.load(Ordering::SeqCst),
+      bypassed: self.bypassed.load(Ordering::SeqCst),
+      detour_time: time(&self.detour_nanos),
+      original_time: time(&self.original_nanos),
+    }
+  }
+
+  /// Resets all statistics to zero.
+  pub fn reset(&self) {
+    for counter in [
+      &self.calls,
+      &self.bypassed,
+      &self.detour_nanos,
+      &self.original_nanos,
+    ] {
+      counter.store(0, Ordering::SeqCst);
+    }
+  }
+}
+
+/// Returns the nanoseconds elapsed since an instant.
+fn nanos(start: Instant) -> u64 {
+  start.elapsed().as_nanos() as u64
+}
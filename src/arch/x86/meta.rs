@@ -1,6 +1,13 @@
 use super::thunk;
-use crate::{error::Result, pic};
-use std::mem;
+use crate::pic;
+use core::mem;
+#[cfg(feature = "std")]
+use {
+  crate::util,
+  core::slice,
+  core::sync::atomic::AtomicUsize,
+  iced_x86::{Decoder, DecoderOptions, Mnemonic, OpKind, Register},
+};
 
 /// The furthest distance between a target and its detour (2 GiB).
 pub const DETOUR_RANGE: usize = 0x8000_0000;
@@ -10,15 +17,86 @@ pub fn prolog_margin(_target: *const ()) -> usize {
   mem::size_of::<thunk::x86::JumpRel>()
 }
 
-/// Creates a relay; required for destinations further away than 2GB (on x64).
-pub fn relay_builder(target: *const (), detour: *const ()) -> Result<Option<pic::CodeEmitter>> {
-  let displacement = (target as isize).wrapping_sub(detour as isize);
+/// Creates a relay with an absolute destination, that can be swapped
+/// atomically. This also allows for destinations further away than 2GB (on
+/// x64).
+pub fn relay_builder(destination: *const ()) -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+  emitter.add_thunk(thunk::x86::jmp_indirect(destination as usize));
+  emitter
+}
+
+/// Returns the destination slot of a relay.
+#[cfg(feature = "std")]
+pub fn relay_slot(relay: *const ()) -> *const AtomicUsize {
+  thunk::x86::jmp_indirect_slot(relay as usize) as *const AtomicUsize
+}
+
+/// Creates a dispatch stub, which calls `enter(context, &return_address)`
+/// with all arguments preserved, and jumps to the address it returns.
+///
+/// Calls returning outside of the caller filter (see `thunk::dispatch_filter`)
+/// jump straight to `next` instead.
+#[cfg(feature = "std")]
+pub fn dispatch_builder(context: usize, enter: usize, filter: usize, next: usize) -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+  let bypass = thunk::jmp(next);
+  emitter.add_thunk(thunk::dispatch_filter(filter, bypass.len()));
+  emitter.add_thunk(bypass);
+  emitter.add_thunk(thunk::dispatch_enter(context, enter));
+  emitter
+}
+
+/// Creates a stub for returning from a dispatched detour, which calls
+/// `leave()` with all return values preserved, and jumps to the address it
+/// returns.
+#[cfg(feature = "std")]
+pub fn dispatch_return_builder(leave: usize) -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+  emitter.add_thunk(thunk::dispatch_leave(leave));
+  emitter
+}
+
+/// Returns the destination of an unconditional jump (`jmp rel` or
+/// `jmp [mem]`) at the address, if any. Any leading `endbr` is skipped.
+#[cfg(feature = "std")]
+pub unsafe fn jump_destination(address: *const ()) -> Option<usize> {
+  // Two instructions, each at most 15 bytes, are decoded
+  let code = slice::from_raw_parts(address as *const u8, 30);
+  let decoder = Decoder::with_ip(
+    (mem::size_of::<usize>() * 8) as u32,
+    code,
+    address as u64,
+    DecoderOptions::NONE,
+  );
+
+  let instruction = decoder
+    .into_iter()
+    .find(|instruction| !matches!(instruction.mnemonic(), Mnemonic::Endbr64 | Mnemonic::Endbr32))?;
+
+  if instruction.is_invalid() || instruction.mnemonic() != Mnemonic::Jmp {
+    return None;
+  }
 
-  if cfg!(target_arch = "x86_64") && !crate::arch::is_within_range(displacement) {
-    let mut emitter = pic::CodeEmitter::new();
-    emitter.add_thunk(thunk::jmp(detour as usize));
-    Ok(Some(emitter))
-  } else {
-    Ok(None)
+  match instruction.op0_kind() {
+    OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+      Some(instruction.near_branch_target() as usize)
+    },
+    OpKind::Memory
+      if instruction.memory_index() == Register::None
+        && matches!(
+          instruction.memory_base(),
+          Register::None | Register::RIP | Register::EIP
+        ) =>
+    {
+      // The destination is read from memory (e.g an import table)
+      let slot = instruction.memory_displacement64() as usize;
+      if util::is_readable_address(slot as *const ()).unwrap_or(false) {
+        Some((slot as *const usize).read_unaligned())
+      } else {
+        None
+      }
+    },
+    _ => None,
   }
 }
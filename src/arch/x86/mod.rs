@@ -1,8 +1,9 @@
+#[cfg(feature = "std")]
 pub use self::patcher::Patcher;
 pub use self::trampoline::Trampoline;
 
 pub mod meta;
-mod patcher;
+pub mod patcher;
 mod thunk;
 mod trampoline;
 
@@ -110,7 +111,7 @@ mod tests {
 
     let error =
       unsafe { RawDetour::new(external_loop as *const (), ret10 as *const ()) }.unwrap_err();
-    assert_matches!(error, Error::UnsupportedInstruction);
+    assert_matches!(error, Error::UnsupportedInstruction(ref error) if error.bytes[0] == 0xE2);
   }
 
   #[test]
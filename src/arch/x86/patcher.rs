@@ -1,14 +1,27 @@
 use super::thunk;
-use crate::error::{Error, Result};
-use crate::{pic, util};
-use std::{mem, slice};
+use crate::error::{Error, PatchAreaError, Result};
+use crate::pic;
+use crate::plan::PatchKind;
+use core::{mem, slice};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use {
+  crate::arch::{Integrity, Options},
+  crate::{threads, util},
+  std::ptr,
+  std::sync::atomic::{AtomicU64, Ordering},
+};
 
+#[cfg(feature = "std")]
 pub struct Patcher {
+  target: *const (),
   patch_area: &'static mut [u8],
   original_prolog: Vec<u8>,
   detour_prolog: Vec<u8>,
 }
 
+#[cfg(feature = "std")]
 impl Patcher {
   /// Creates a new detour patcher for an address.
   ///
@@ -17,10 +30,29 @@ impl Patcher {
   /// * `target` - An address that should be hooked.
   /// * `detour` - An address that the target should be redirected to.
   /// * `prolog_size` - The available inline space for the hook.
-  pub unsafe fn new(target: *const (), detour: *const (), prolog_size: usize) -> Result<Patcher> {
+  /// * `options` - Whether padding and hot patch areas may be used.
+  pub unsafe fn new(
+    target: *const (),
+    detour: *const (),
+    prolog_size: usize,
+    options: &Options,
+  ) -> Result<Patcher> {
     // Calculate the patch area (i.e if a short or long jump should be used)
-    let patch_area = Self::patch_area(target, prolog_size)?;
-    let emitter = Self::hook_template(detour, patch_area);
+    let patch_area = patch_area(
+      target,
+      prolog_size,
+      options.padding,
+      options.hot_patch,
+      util::is_executable_address,
+    )?;
+    let emitter = hook_template(detour, patch_area);
+    event!(
+      "selected patch area",
+      target = target,
+      kind = kind_of(target, prolog_size, patch_area),
+      area = patch_area.as_ptr(),
+      size = patch_area.len(),
+    );
 
     let patch_address = patch_area.as_ptr() as *const ();
     let original_prolog = patch_area.to_vec();
@@ -29,6 +61,7 @@ impl Patcher {
       detour_prolog: emitter.emit(patch_address),
       original_prolog,
       patch_area,
+      target,
     })
   }
 
@@ -37,6 +70,45 @@ impl Patcher {
     self.patch_area
   }
 
+  /// Returns the original bytes of the target's patch area.
+  pub fn original(&self) -> &[u8] {
+    &self.original_prolog
+  }
+
+  /// Compares the target's patch area to the code that was written, whether
+  /// the target is patched or not.
+  pub fn verify(&self, patched: bool) -> Integrity {
+    let expected = if patched {
+      &self.detour_prolog
+    } else {
+      &self.original_prolog
+    };
+
+    if self.patch_area == &expected[..] {
+      Integrity::Intact
+    } else if patched && self.patch_area == &self.original_prolog[..] {
+      Integrity::Restored
+    } else {
+      Integrity::Overwritten(self.patch_area.to_vec())
+    }
+  }
+
+  /// Returns how a target would be patched, without modifying it.
+  pub unsafe fn patch_kind(
+    target: *const (),
+    prolog_size: usize,
+    options: &Options,
+  ) -> Result<PatchKind> {
+    let patch_area = patch_area(
+      target,
+      prolog_size,
+      options.padding,
+      options.hot_patch,
+      util::is_executable_address,
+    )?;
+    Ok(kind_of(target, prolog_size, patch_area))
+  }
+
   /// Either patches or unpatches the function.
   pub unsafe fn toggle(&mut self, enable: bool) {
     // Copy either the detour or the original bytes of the function
@@ -47,86 +119,200 @@ impl Patcher {
     });
   }
 
-  /// Returns the patch area for a function, consisting of a long jump and
-  /// possibly a short jump.
-  unsafe fn patch_area(target: *const (), prolog_size: usize) -> Result<&'static mut [u8]> {
-    let jump_rel08_size = mem::size_of::<thunk::x86::JumpShort>();
-    let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
-
-    // Check if there isn't enough space for a relative long jump
-    if !Self::is_patchable(target, prolog_size, jump_rel32_size) {
-      // ... check if a relative small jump fits instead
-      if Self::is_patchable(target, prolog_size, jump_rel08_size) {
-        // A small jump relies on there being a hot patch area above the
-        // function, that consists of at least 5 bytes (a rel32 jump).
-        let hot_patch = target as usize - jump_rel32_size;
-        let hot_patch_area = slice::from_raw_parts(hot_patch as *const u8, jump_rel32_size);
-
-        // Ensure that the hot patch area only contains padding and is executable
-        if !Self::is_code_padding(hot_patch_area)
-          || !util::is_executable_address(hot_patch_area.as_ptr() as *const _)?
-        {
-          Err(Error::NoPatchArea)?;
-        }
-
-        // The range is from the start of the hot patch to the end of the jump
-        let patch_size = jump_rel32_size + jump_rel08_size;
-        Ok(slice::from_raw_parts_mut(hot_patch as *mut u8, patch_size))
-      } else {
-        Err(Error::NoPatchArea)
-      }
+  /// Either patches or unpatches the function, without any thread reaching
+  /// the target meanwhile executing a partially written instruction.
+  ///
+  /// A thread that reaches the target whilst it is being patched is
+  /// redirected to `redirect`, which must be equivalent to the original code.
+  /// A thread that is already executing the prolog past its first
+  /// instruction is not accounted for.
+  pub unsafe fn toggle_breakpoint(&mut self, enable: bool, redirect: *const ()) -> Result<()> {
+    let bytes = if enable {
+      &self.detour_prolog
     } else {
-      // The range is from the start of the function to the end of the jump
-      Ok(slice::from_raw_parts_mut(
-        target as *mut u8,
-        jump_rel32_size,
-      ))
+      &self.original_prolog
+    };
+
+    // A hot patch area preceding the target is never executed, but it must
+    // be in place before the short jump to it is written (and vice versa).
+    let live = self.target as usize - self.patch_area.as_ptr() as usize;
+    if enable {
+      self.patch_area[..live].copy_from_slice(&bytes[..live]);
+    }
+
+    Self::write_live(&mut self.patch_area[live..], &bytes[live..], redirect)?;
+
+    if !enable {
+      self.patch_area[..live].copy_from_slice(&bytes[..live]);
     }
+    Ok(())
   }
 
-  /// Creates a redirect code template for the targetted patch area.
-  fn hook_template(detour: *const (), patch_area: &[u8]) -> pic::CodeEmitter {
-    let mut emitter = pic::CodeEmitter::new();
+  /// Writes bytes to code that may be executing concurrently.
+  unsafe fn write_live(area: &mut [u8], bytes: &[u8], redirect: *const ()) -> Result<()> {
+    const INT3: u8 = 0xCC;
 
-    // Both hot patch and normal detours use a relative long jump
-    emitter.add_thunk(thunk::x86::jmp_rel32(detour as usize));
+    let address = area.as_ptr() as usize;
+    let word = address & !(mem::size_of::<u64>() - 1);
 
-    // The hot patch relies on a small jump to get to the long jump
-    let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
-    let uses_hot_patch = patch_area.len() > jump_rel32_size;
+    if address + area.len() <= word + mem::size_of::<u64>() {
+      // An aligned store cannot be observed partially
+      let atomic = &*(word as *const AtomicU64);
+      let mut value = atomic.load(Ordering::SeqCst).to_ne_bytes();
+      value[address - word..][..bytes.len()].copy_from_slice(bytes);
+      atomic.store(u64::from_ne_bytes(value), Ordering::SeqCst);
+      threads::sync_cores();
+    } else {
+      let _redirect = threads::redirect(address as *const (), redirect)?;
 
-    if uses_hot_patch {
-      let displacement = -(jump_rel32_size as i8);
-      emitter.add_thunk(thunk::x86::jmp_rel8(displacement));
-    }
+      ptr::write_volatile(area.as_mut_ptr(), INT3);
+      threads::sync_cores();
+
+      area[1..].copy_from_slice(&bytes[1..]);
+      threads::sync_cores();
 
-    // Pad leftover bytes with nops
-    while emitter.len() < patch_area.len() {
-      emitter.add_thunk(thunk::x86::nop());
+      ptr::write_volatile(area.as_mut_ptr(), bytes[0]);
+      threads::sync_cores();
     }
 
-    emitter
+    Ok(())
+  }
+}
+
+/// Returns how a target is patched, using a patch area.
+pub fn kind_of(target: *const (), prolog_size: usize, patch_area: &[u8]) -> PatchKind {
+  if (patch_area.as_ptr() as usize) < target as usize {
+    PatchKind::HotPatch
+  } else if prolog_size < patch_area.len() {
+    PatchKind::Padding
+  } else {
+    PatchKind::Jump
   }
+}
+
+/// Returns the patch area for a function, consisting of a long jump and
+/// possibly a short jump.
+pub unsafe fn patch_area<E>(
+  target: *const (),
+  prolog_size: usize,
+  padding: bool,
+  hot_patch: bool,
+  is_executable: E,
+) -> Result<&'static mut [u8]>
+where
+  E: FnOnce(*const ()) -> Result<bool>,
+{
+  let jump_rel08_size = mem::size_of::<thunk::x86::JumpShort>();
+  let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
 
-  /// Returns whether an address can be inline patched or not.
-  unsafe fn is_patchable(target: *const (), prolog_size: usize, patch_size: usize) -> bool {
-    if prolog_size >= patch_size {
-      // If the whole patch fits it's good to go!
-      return true;
+  // Check if there isn't enough space for a relative long jump
+  if !is_patchable(target, prolog_size, jump_rel32_size, padding) {
+    // ... check if a relative small jump fits instead
+    if hot_patch && is_patchable(target, prolog_size, jump_rel08_size, padding) {
+      // A small jump relies on there being a hot patch area above the
+      // function, that consists of at least 5 bytes (a rel32 jump).
+      let hot_patch_address = target as usize - jump_rel32_size;
+      let hot_patch_area = slice::from_raw_parts(hot_patch_address as *const u8, jump_rel32_size);
+
+      // Ensure that the hot patch area only contains padding and is executable
+      if !is_code_padding(hot_patch_area)
+        || !is_executable(hot_patch_area.as_ptr() as *const _)?
+      {
+        Err(no_patch_area(target, prolog_size, jump_rel32_size, padding, true))?;
+      }
+
+      // The range is from the start of the hot patch to the end of the jump
+      let patch_size = jump_rel32_size + jump_rel08_size;
+      Ok(slice::from_raw_parts_mut(hot_patch_address as *mut u8, patch_size))
+    } else {
+      Err(no_patch_area(target, prolog_size, jump_rel32_size, padding, false))
     }
+  } else {
+    // The range is from the start of the function to the end of the jump
+    Ok(slice::from_raw_parts_mut(
+      target as *mut u8,
+      jump_rel32_size,
+    ))
+  }
+}
+
+/// Creates a redirect code template for the targetted patch area.
+pub fn hook_template(detour: *const (), patch_area: &[u8]) -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+
+  // Both hot patch and normal detours use a relative long jump
+  emitter.add_thunk(thunk::x86::jmp_rel32(detour as usize));
+
+  // The hot patch relies on a small jump to get to the long jump
+  let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
+  let uses_hot_patch = patch_area.len() > jump_rel32_size;
 
-    // Otherwise the inline patch relies on padding after the prolog
-    let slice = slice::from_raw_parts(
+  if uses_hot_patch {
+    let displacement = -(jump_rel32_size as i8);
+    emitter.add_thunk(thunk::x86::jmp_rel8(displacement));
+  }
+
+  // Pad leftover bytes with nops
+  while emitter.len() < patch_area.len() {
+    emitter.add_thunk(thunk::x86::nop());
+  }
+
+  emitter
+}
+
+/// Returns whether an address can be inline patched or not.
+unsafe fn is_patchable(target: *const (), prolog_size: usize, patch_size: usize, padding: bool) -> bool {
+  if prolog_size >= patch_size {
+    // If the whole patch fits it's good to go!
+    return true;
+  } else if !padding {
+    return false;
+  }
+
+  // Otherwise the inline patch relies on padding after the prolog
+  let slice = slice::from_raw_parts(
+    (target as usize + prolog_size) as *const u8,
+    patch_size - prolog_size,
+  );
+
+  is_code_padding(slice)
+}
+
+/// Describes the space available for a jump, which is insufficient.
+unsafe fn no_patch_area(
+  target: *const (),
+  prolog_size: usize,
+  patch_size: usize,
+  padding: bool,
+  hot_patch: bool,
+) -> Error {
+  let mut available = prolog_size;
+  let mut tried = vec![PatchKind::Jump];
+
+  if padding {
+    // Only the padding that a jump could have extended into is counted
+    let padding = slice::from_raw_parts(
       (target as usize + prolog_size) as *const u8,
-      patch_size - prolog_size,
+      patch_size.saturating_sub(prolog_size),
     );
-
-    Self::is_code_padding(slice)
+    available += padding.iter().take_while(|&&code| is_code_padding(&[code])).count();
+    tried.push(PatchKind::Padding);
   }
 
-  /// Returns true if the slice only contains code padding.
-  fn is_code_padding(buffer: &[u8]) -> bool {
-    const PADDING: [u8; 3] = [0x00, 0x90, 0xCC];
-    buffer.iter().all(|code| PADDING.contains(code))
+  if hot_patch {
+    tried.push(PatchKind::HotPatch);
   }
+
+  Error::NoPatchArea(PatchAreaError {
+    target: target as usize,
+    available,
+    needed: patch_size,
+    tried,
+  })
+}
+
+/// Returns true if the slice only contains code padding.
+fn is_code_padding(buffer: &[u8]) -> bool {
+  const PADDING: [u8; 3] = [0x00, 0x90, 0xCC];
+  buffer.iter().all(|code| PADDING.contains(code))
 }
@@ -1,5 +1,7 @@
 use crate::pic::Thunkable;
-use std::mem;
+use core::mem;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 #[repr(packed)]
 struct CallAbs {
@@ -76,3 +78,106 @@ pub fn jcc_abs(destination: usize, condition: u8) -> Box<dyn Thunkable> {
   let slice: [u8; 16] = unsafe { mem::transmute(code) };
   Box::new(slice.to_vec())
 }
+
+/// Constructs a caller filter, which skips the following `skip` bytes if the
+/// return address is within `[start, start + length)`, read from `filter`.
+/// All registers and the stack are preserved.
+pub fn dispatch_filter(filter: usize, skip: usize) -> Box<dyn Thunkable> {
+  let mut code = vec![
+    // push rax; push rcx; mov rcx, filter
+    0x50, 0x51, 0x48, 0xB9,
+  ];
+  code.extend_from_slice(&filter.to_le_bytes());
+  code.extend_from_slice(&[
+    // mov rax, [rsp+0x10] (the return address)
+    0x48, 0x8B, 0x44, 0x24, 0x10,
+    // sub rax, [rcx]; cmp rax, [rcx+8]
+    0x48, 0x2B, 0x01, 0x48, 0x3B, 0x41, 0x08,
+    // pop rcx; pop rax; jb skip
+    0x59, 0x58, 0x72, skip as u8,
+  ]);
+  Box::new(code)
+}
+
+/// Constructs a dispatch stub, which calls `enter(context, &return_address)`
+/// whilst preserving all argument registers (of both the System V and
+/// Microsoft ABIs), and then jumps to the address it returns.
+pub fn dispatch_enter(context: usize, enter: usize) -> Box<dyn Thunkable> {
+  let mut code = vec![
+    // push rax, rcx, rdx, rsi, rdi, r8, r9, r10, r11
+    0x50, 0x51, 0x52, 0x56, 0x57, 0x41, 0x50, 0x41, 0x51, 0x41, 0x52, 0x41, 0x53,
+    // sub rsp, 0xA0
+    0x48, 0x81, 0xEC, 0xA0, 0x00, 0x00, 0x00,
+  ];
+
+  // movdqu [rsp+0x20+i*0x10], xmm(i)
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0x44, 0x24, 0x20]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0x4C, 0x24, 0x30]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0x54, 0x24, 0x40]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0x5C, 0x24, 0x50]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0x64, 0x24, 0x60]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0x6C, 0x24, 0x70]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0xB4, 0x24, 0x80, 0x00, 0x00, 0x00]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x7F, 0xBC, 0x24, 0x90, 0x00, 0x00, 0x00]);
+
+  // mov rdi, context; mov rcx, rdi
+  code.extend_from_slice(&[0x48, 0xBF]);
+  code.extend_from_slice(&context.to_le_bytes());
+  code.extend_from_slice(&[0x48, 0x89, 0xF9]);
+
+  // lea rsi, [rsp+0xE8] (the return address); mov rdx, rsi
+  code.extend_from_slice(&[0x48, 0x8D, 0xB4, 0x24, 0xE8, 0x00, 0x00, 0x00]);
+  code.extend_from_slice(&[0x48, 0x89, 0xF2]);
+
+  // mov rax, enter; call rax; mov r11, rax
+  code.extend_from_slice(&[0x48, 0xB8]);
+  code.extend_from_slice(&enter.to_le_bytes());
+  code.extend_from_slice(&[0xFF, 0xD0, 0x49, 0x89, 0xC3]);
+
+  // movdqu xmm(i), [rsp+0x20+i*0x10]
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0x44, 0x24, 0x20]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0x4C, 0x24, 0x30]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0x54, 0x24, 0x40]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0x5C, 0x24, 0x50]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0x64, 0x24, 0x60]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0x6C, 0x24, 0x70]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0xB4, 0x24, 0x80, 0x00, 0x00, 0x00]);
+  code.extend_from_slice(&[0xF3, 0x0F, 0x6F, 0xBC, 0x24, 0x90, 0x00, 0x00, 0x00]);
+
+  code.extend_from_slice(&[
+    // add rsp, 0xA8 (including r11)
+    0x48, 0x81, 0xC4, 0xA8, 0x00, 0x00, 0x00,
+    // pop r10, r9, r8, rdi, rsi, rdx, rcx, rax
+    0x41, 0x5A, 0x41, 0x59, 0x41, 0x58, 0x5F, 0x5E, 0x5A, 0x59, 0x58,
+    // jmp r11
+    0x41, 0xFF, 0xE3,
+  ]);
+  Box::new(code)
+}
+
+/// Constructs a dispatch return stub, which calls `leave()` whilst preserving
+/// all return registers, and then jumps to the address it returns.
+pub fn dispatch_leave(leave: usize) -> Box<dyn Thunkable> {
+  let mut code = vec![
+    // push rbp; mov rbp, rsp; and rsp, -16
+    0x55, 0x48, 0x89, 0xE5, 0x48, 0x83, 0xE4, 0xF0,
+    // push rax; push rdx; sub rsp, 0x40
+    0x50, 0x52, 0x48, 0x83, 0xEC, 0x40,
+    // movdqu [rsp+0x20], xmm0; movdqu [rsp+0x30], xmm1
+    0xF3, 0x0F, 0x7F, 0x44, 0x24, 0x20, 0xF3, 0x0F, 0x7F, 0x4C, 0x24, 0x30,
+    // mov rax, leave
+    0x48, 0xB8,
+  ];
+  code.extend_from_slice(&leave.to_le_bytes());
+  code.extend_from_slice(&[
+    // call rax; mov r11, rax
+    0xFF, 0xD0, 0x49, 0x89, 0xC3,
+    // movdqu xmm0, [rsp+0x20]; movdqu xmm1, [rsp+0x30]
+    0xF3, 0x0F, 0x6F, 0x44, 0x24, 0x20, 0xF3, 0x0F, 0x6F, 0x4C, 0x24, 0x30,
+    // add rsp, 0x40; pop rdx; pop rax; mov rsp, rbp; pop rbp
+    0x48, 0x83, 0xC4, 0x40, 0x5A, 0x58, 0x48, 0x89, 0xEC, 0x5D,
+    // jmp r11
+    0x41, 0xFF, 0xE3,
+  ]);
+  Box::new(code)
+}
@@ -1,6 +1,8 @@
-use crate::pic::{FixedThunk, Thunkable};
+use crate::pic::{FixedThunk, Thunkable, UnsafeThunk};
 use generic_array::{typenum, GenericArray};
-use std::mem;
+use core::mem;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 #[repr(packed)]
 pub struct JumpRel {
@@ -79,6 +81,112 @@ pub fn jmp_rel8(displacement: i8) -> Box<dyn Thunkable> {
   }))
 }
 
+/// The size of an indirect jump's opcode and operand.
+const JUMP_INDIRECT_SIZE: usize = 6;
+
+/// Returns the address of an indirect jump's destination slot.
+pub fn jmp_indirect_slot(address: usize) -> usize {
+  let align = mem::size_of::<usize>();
+  (address + JUMP_INDIRECT_SIZE + align - 1) & !(align - 1)
+}
+
+/// Constructs an indirect jump, through an aligned destination slot
+/// following the instruction. The slot can be updated atomically.
+pub fn jmp_indirect(destination: usize) -> Box<dyn Thunkable> {
+  // The slot is preceded by a varying amount of padding
+  let size = JUMP_INDIRECT_SIZE + mem::size_of::<usize>() * 2 - 1;
+
+  Box::new(unsafe {
+    UnsafeThunk::new(
+      move |address| {
+        let slot = jmp_indirect_slot(address);
+
+        // The operand is RIP relative on x64, but absolute on x86
+        let operand = if cfg!(target_arch = "x86_64") {
+          (slot - (address + JUMP_INDIRECT_SIZE)) as u32
+        } else {
+          slot as u32
+        };
+
+        let mut code = vec![0xCC; size];
+        code[..2].copy_from_slice(&[0xFF, 0x25]);
+        code[2..JUMP_INDIRECT_SIZE].copy_from_slice(&operand.to_ne_bytes());
+        code[slot - address..][..mem::size_of::<usize>()].copy_from_slice(&destination.to_ne_bytes());
+        code
+      },
+      size,
+    )
+  })
+}
+
+/// Constructs a caller filter, which skips the following `skip` bytes if the
+/// return address is within `[start, start + length)`, read from `filter`.
+/// All registers and the stack are preserved.
+pub fn dispatch_filter(filter: usize, skip: usize) -> Box<dyn Thunkable> {
+  let mut code = vec![
+    // push eax; push ecx; mov ecx, filter
+    0x50, 0x51, 0xB9,
+  ];
+  code.extend_from_slice(&(filter as u32).to_le_bytes());
+  code.extend_from_slice(&[
+    // mov eax, [esp+8] (the return address)
+    0x8B, 0x44, 0x24, 0x08,
+    // sub eax, [ecx]; cmp eax, [ecx+4]
+    0x2B, 0x01, 0x3B, 0x41, 0x04,
+    // pop ecx; pop eax; jb skip
+    0x59, 0x58, 0x72, skip as u8,
+  ]);
+  Box::new(code)
+}
+
+/// Constructs a dispatch stub, which calls `enter(context, &return_address)`
+/// whilst preserving all argument registers, and then jumps to the address it
+/// returns.
+pub fn dispatch_enter(context: usize, enter: usize) -> Box<dyn Thunkable> {
+  let mut code = vec![
+    // push eax (the destination); push eax, ecx, edx
+    0x50, 0x50, 0x51, 0x52,
+    // push ebp; mov ebp, esp; and esp, -16; sub esp, 8
+    0x55, 0x89, 0xE5, 0x83, 0xE4, 0xF0, 0x83, 0xEC, 0x08,
+    // lea eax, [ebp+0x14] (the return address); push eax
+    0x8D, 0x45, 0x14, 0x50,
+    // push context
+    0x68,
+  ];
+  code.extend_from_slice(&(context as u32).to_le_bytes());
+  // mov eax, enter
+  code.push(0xB8);
+  code.extend_from_slice(&(enter as u32).to_le_bytes());
+  code.extend_from_slice(&[
+    // call eax; mov [ebp+0x10], eax
+    0xFF, 0xD0, 0x89, 0x45, 0x10,
+    // mov esp, ebp; pop ebp; pop edx, ecx, eax; ret
+    0x89, 0xEC, 0x5D, 0x5A, 0x59, 0x58, 0xC3,
+  ]);
+  Box::new(code)
+}
+
+/// Constructs a dispatch return stub, which calls `leave()` whilst preserving
+/// all return registers, and then jumps to the address it returns.
+pub fn dispatch_leave(leave: usize) -> Box<dyn Thunkable> {
+  let mut code = vec![
+    // push eax (the destination); push eax, edx
+    0x50, 0x50, 0x52,
+    // push ebp; mov ebp, esp; and esp, -16
+    0x55, 0x89, 0xE5, 0x83, 0xE4, 0xF0,
+    // mov eax, leave
+    0xB8,
+  ];
+  code.extend_from_slice(&(leave as u32).to_le_bytes());
+  code.extend_from_slice(&[
+    // call eax; mov [ebp+0xC], eax
+    0xFF, 0xD0, 0x89, 0x45, 0x0C,
+    // mov esp, ebp; pop ebp; pop edx, eax; ret
+    0x89, 0xEC, 0x5D, 0x5A, 0x58, 0xC3,
+  ]);
+  Box::new(code)
+}
+
 /// Calculates the relative displacement for an instruction.
 fn calculate_displacement(source: usize, destination: usize, instruction_size: usize) -> u32 {
   let displacement =
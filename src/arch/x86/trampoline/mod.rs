@@ -1,10 +1,13 @@
 use self::disasm::*;
 use crate::arch::x86::thunk;
-use crate::error::{Error, Result};
+use crate::error::{Error, InstructionError, Result};
 use crate::pic;
-use iced_x86::{Decoder, DecoderOptions, Instruction, OpKind};
-use std::ptr::slice_from_raw_parts;
-use std::{mem, slice};
+use crate::util::Hex;
+use crate::plan::{PlannedInstruction, Relocation};
+use iced_x86::{Decoder, DecoderOptions, FastFormatter, Instruction, OpKind};
+use core::{mem, slice};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 mod disasm;
 
@@ -12,6 +15,8 @@ mod disasm;
 pub struct Trampoline {
   emitter: pic::CodeEmitter,
   prolog_size: usize,
+  #[cfg(feature = "std")]
+  relocations: Vec<(usize, usize)>,
 }
 
 impl Trampoline {
@@ -20,6 +25,21 @@ impl Trampoline {
     Builder::new(target, margin).build()
   }
 
+  /// Constructs a new trampoline for an address, also returning each
+  /// decoded instruction of the prolog (even if construction fails).
+  #[cfg(feature = "std")]
+  pub unsafe fn analyze(target: *const (), margin: usize) -> (Vec<PlannedInstruction>, Result<Trampoline>) {
+    let mut builder = Builder::new(target, margin);
+    let emitter = builder.generate();
+    let instructions = mem::take(&mut builder.instructions);
+    let trampoline = emitter.map(|emitter| Trampoline {
+      prolog_size: builder.total_bytes_disassembled,
+      relocations: builder.relocations,
+      emitter,
+    });
+    (instructions, trampoline)
+  }
+
   /// Returns a reference to the trampoline's code emitter.
   pub fn emitter(&self) -> &pic::CodeEmitter {
     &self.emitter
@@ -29,6 +49,13 @@ impl Trampoline {
   pub fn prolog_size(&self) -> usize {
     self.prolog_size
   }
+
+  /// Returns the offset of each relocated instruction, within the prolog and
+  /// the trampoline respectively.
+  #[cfg(feature = "std")]
+  pub fn relocations(&self) -> &[(usize, usize)] {
+    &self.relocations
+  }
 }
 
 /// A trampoline builder.
@@ -43,6 +70,10 @@ struct Builder {
   finished: bool,
   /// The target the trampoline is adapted for.
   target: *const (),
+  /// The prolog and trampoline offset of each relocated instruction.
+  relocations: Vec<(usize, usize)>,
+  /// Each decoded instruction, and how it was relocated.
+  instructions: Vec<PlannedInstruction>,
 }
 
 impl Builder {
@@ -52,6 +83,8 @@ impl Builder {
       branch_address: None,
       total_bytes_disassembled: 0,
       finished: false,
+      relocations: Vec::new(),
+      instructions: Vec::new(),
       target,
       margin,
     }
@@ -64,6 +97,17 @@ impl Builder {
   /// target..target+margin+15 must be valid to read as a u8 slice or behavior
   /// may be undefined
   pub unsafe fn build(mut self) -> Result<Trampoline> {
+    let emitter = self.generate()?;
+    Ok(Trampoline {
+      prolog_size: self.total_bytes_disassembled,
+      #[cfg(feature = "std")]
+      relocations: self.relocations,
+      emitter,
+    })
+  }
+
+  /// Generates the trampoline's code.
+  unsafe fn generate(&mut self) -> Result<pic::CodeEmitter> {
     let mut emitter = pic::CodeEmitter::new();
 
     // 15 = max size of x64 instruction
@@ -74,7 +118,7 @@ impl Builder {
     // new Decoder before reading every individual instruction? but it'd still need
     // to be given a 15 byte slice to handle any valid x64 instruction
     let target: *const u8 = self.target.cast();
-    let slice = unsafe { slice::from_raw_parts(std::hint::black_box(target), self.margin + 15) };
+    let slice = unsafe { slice::from_raw_parts(core::hint::black_box(target), self.margin + 15) };
     let decoder = Decoder::with_ip(
       (mem::size_of::<usize>() * 8) as u32,
       slice,
@@ -83,21 +127,35 @@ impl Builder {
     );
     for instruction in decoder {
       if instruction.is_invalid() {
-        break;
+        let offset = instruction.ip() as usize - (self.target as usize);
+        let length = instruction.len().max(1);
+        return Err(Error::InvalidCode(self.describe(
+          &instruction,
+          &slice[offset..(offset + length).min(slice.len())],
+        )));
       }
       self.total_bytes_disassembled += instruction.len();
       let instr_offset = instruction.ip() as usize - (self.target as usize);
       let instruction_bytes = &slice[instr_offset..instr_offset + instruction.len()];
-      let thunk = self.process_instruction(&instruction, instruction_bytes)?;
-
-      // If the trampoline displacement is larger than the target
-      // function, all instructions will be displaced, and if there is
-      // internal branching, it will end up at the wrong instructions.
-      if self.is_instruction_in_branch(&instruction) && instruction.len() != thunk.len() {
-        Err(Error::UnsupportedInstruction)?;
-      } else {
-        emitter.add_thunk(thunk);
-      }
+      let processed = self
+        .process_instruction(&instruction, instruction_bytes)
+        .and_then(|(thunk, relocation)| {
+          // If the trampoline displacement is larger than the target
+          // function, all instructions will be displaced, and if there is
+          // internal branching, it will end up at the wrong instructions.
+          if self.is_instruction_in_branch(&instruction) && instruction.len() != thunk.len() {
+            Err(Error::UnsupportedInstruction(self.describe(&instruction, instruction_bytes)))
+          } else {
+            Ok((thunk, relocation))
+          }
+        });
+
+      let relocation = processed.as_ref().map_or(Relocation::Unsupported, |(_, relocation)| *relocation);
+      self.record(&instruction, instr_offset, instruction_bytes, relocation);
+      let (thunk, _) = processed?;
+
+      self.relocations.push((instr_offset, emitter.len()));
+      emitter.add_thunk(thunk);
 
       // Determine whether enough bytes for the margin has been disassembled
       if self.total_bytes_disassembled >= self.margin && !self.finished {
@@ -111,10 +169,50 @@ impl Builder {
       }
     }
 
-    Ok(Trampoline {
-      prolog_size: self.total_bytes_disassembled,
-      emitter,
-    })
+    Ok(emitter)
+  }
+
+  /// Records a decoded instruction, and how it was relocated.
+  fn record(
+    &mut self,
+    instruction: &Instruction,
+    offset: usize,
+    bytes: &[u8],
+    relocation: Relocation,
+  ) {
+    let mut text = String::new();
+    FastFormatter::new().format(instruction, &mut text);
+    event!(
+      "relocated instruction",
+      target = self.target,
+      offset = offset,
+      bytes = Hex(bytes),
+      text = text,
+      relocation = relocation,
+    );
+
+    self.instructions.push(PlannedInstruction {
+      bytes: bytes.to_vec(),
+      offset,
+      text,
+      relocation,
+    });
+  }
+
+  /// Describes an instruction which prevents the target from being detoured.
+  fn describe(&self, instruction: &Instruction, bytes: &[u8]) -> InstructionError {
+    let mnemonic = if instruction.is_invalid() {
+      "invalid".to_string()
+    } else {
+      format!("{:?}", instruction.mnemonic()).to_lowercase()
+    };
+
+    InstructionError {
+      target: self.target as usize,
+      offset: instruction.ip() as usize - self.target as usize,
+      bytes: bytes.to_vec(),
+      mnemonic,
+    }
   }
 
   /// Returns an instruction after analysing and potentially modifies it.
@@ -122,7 +220,7 @@ impl Builder {
     &mut self,
     instruction: &Instruction,
     instruction_bytes: &[u8],
-  ) -> Result<Box<dyn pic::Thunkable>> {
+  ) -> Result<(Box<dyn pic::Thunkable>, Relocation)> {
     if let Some(target) = instruction.rip_operand_target() {
       return self.handle_rip_relative_instruction(instruction, instruction_bytes, target as usize);
     } else if let Some(target) = instruction.relative_branch_target() {
@@ -135,7 +233,7 @@ impl Builder {
 
     // The instruction does not use any position-dependant operands,
     // therefore the bytes can be copied directly from source.
-    Ok(Box::new(instruction_bytes.to_vec()))
+    Ok((Box::new(instruction_bytes.to_vec()), Relocation::Copied))
   }
 
   /// Adjusts the offsets for RIP relative operands. They are only available
@@ -151,7 +249,7 @@ impl Builder {
     instruction: &Instruction,
     instruction_bytes: &[u8],
     target: usize,
-  ) -> Result<Box<dyn pic::Thunkable>> {
+  ) -> Result<(Box<dyn pic::Thunkable>, Relocation)> {
     let displacement = target
       .wrapping_sub(instruction.ip() as usize)
       .wrapping_sub(instruction.len()) as isize;
@@ -160,7 +258,7 @@ impl Builder {
 
     // Nothing should be done if `displacement` is within the prolog.
     if (-(self.total_bytes_disassembled as isize)..0).contains(&displacement) {
-      return Ok(Box::new(instruction_bytes.to_vec()));
+      return Ok((Box::new(instruction_bytes.to_vec()), Relocation::Copied));
     }
 
     // These need to be captured by the closure
@@ -181,7 +279,7 @@ impl Builder {
         }
     }).unwrap_or(0);
 
-    Ok(Box::new(pic::UnsafeThunk::new(
+    let thunk = Box::new(pic::UnsafeThunk::new(
       move |offset| {
         let mut bytes = instruction_bytes.clone();
 
@@ -202,7 +300,8 @@ impl Builder {
         bytes
       },
       instruction.len(),
-    )))
+    ));
+    Ok((thunk, Relocation::Displacement))
   }
 
   /// Processes relative branches (e.g `call`, `loop`, `jne`).
@@ -211,10 +310,10 @@ impl Builder {
     instruction: &Instruction,
     instruction_bytes: &[u8],
     destination_address_abs: usize,
-  ) -> Result<Box<dyn pic::Thunkable>> {
+  ) -> Result<(Box<dyn pic::Thunkable>, Relocation)> {
     if instruction.is_call() {
       // Calls are not an issue since they return to the original address
-      return Ok(thunk::call(destination_address_abs));
+      return Ok((thunk::call(destination_address_abs), Relocation::Call));
     }
 
     let prolog_range = (self.target as usize)..(self.target as usize + self.margin);
@@ -225,15 +324,15 @@ impl Builder {
     if prolog_range.contains(&destination_address_abs) {
       // Keep track of the jump's destination address
       self.branch_address = Some(destination_address_abs);
-      Ok(Box::new(instruction_bytes.to_vec()))
+      Ok((Box::new(instruction_bytes.to_vec()), Relocation::InternalBranch))
     } else if instruction.is_loop() {
       // Loops (e.g 'loopnz', 'jecxz') to the outside are not supported
-      Err(Error::UnsupportedInstruction)
+      Err(Error::UnsupportedInstruction(self.describe(instruction, instruction_bytes)))
     } else if instruction.is_unconditional_jump() {
       // If the function is not in a branch, and it unconditionally jumps
       // a distance larger than the prolog, it's the same as if it terminates.
       self.finished = !self.is_instruction_in_branch(instruction);
-      Ok(thunk::jmp(destination_address_abs))
+      Ok((thunk::jmp(destination_address_abs), Relocation::Jump))
     } else {
       // Conditional jumps (Jcc)
       // To extract the condition, the primary opcode is required. Short
@@ -245,7 +344,7 @@ impl Builder {
 
       // Extract the condition (i.e 0x74 is [jz rel8] ⟶ 0x74 & 0x0F == 4)
       let condition = primary_opcode & 0x0F;
-      Ok(thunk::jcc(destination_address_abs, condition))
+      Ok((thunk::jcc(destination_address_abs, condition), Relocation::ConditionalJump))
     }
   }
 
@@ -0,0 +1,324 @@
+//! Detours using a user-implemented memory backend, which are available
+//! without `std` (e.g in kernels, hypervisors and bare-metal harnesses).
+//!
+//! A bare [Detour] relocates the target's prolog to a trampoline and patches
+//! the target just like any other detour, but the memory services it relies
+//! upon (i.e querying, allocating, protecting and flushing code) are provided
+//! by a [Backend]. In contrast to a [RawDetour](../struct.RawDetour.html), it
+//! does not share its target with other detours, is not registered, and does
+//! not synchronize other threads whilst patching.
+//!
+//! With `std`, the [Native] backend provides the services of the operating
+//! system.
+pub use crate::arch::DETOUR_RANGE;
+
+use crate::arch::{meta, patcher, Trampoline};
+use crate::error::{Error, Result};
+use crate::pic;
+use core::{fmt, ptr};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// The memory services required by a bare [Detour].
+///
+/// # Safety
+///
+/// Memory returned by [allocate_near](Backend::allocate_near) must be
+/// readable, writable and executable, and remain valid until released.
+pub unsafe trait Backend {
+  /// Returns whether the memory at an address is executable.
+  fn is_executable(&self, address: *const ()) -> Result<bool>;
+
+  /// Allocates `size` bytes of readable, writable and executable memory,
+  /// within reach of a relative jump from `origin` (i.e [DETOUR_RANGE]).
+  fn allocate_near(&self, origin: *const (), size: usize) -> Result<*mut u8>;
+
+  /// Releases memory which was allocated by the backend.
+  ///
+  /// # Safety
+  ///
+  /// The memory must not be executing, nor be released more than once.
+  unsafe fn release(&self, memory: *mut u8, size: usize);
+
+  /// Makes code writable, or restores its original protection.
+  ///
+  /// # Safety
+  ///
+  /// The code must be executable.
+  unsafe fn protect(&self, code: *const u8, size: usize, writable: bool) -> Result<()>;
+
+  /// Makes modified code visible to every core (e.g by flushing the
+  /// instruction cache).
+  fn flush(&self, code: *const u8, size: usize);
+}
+
+/// A backend can be shared by several detours.
+unsafe impl<B: Backend + ?Sized> Backend for &B {
+  fn is_executable(&self, address: *const ()) -> Result<bool> {
+    (**self).is_executable(address)
+  }
+
+  fn allocate_near(&self, origin: *const (), size: usize) -> Result<*mut u8> {
+    (**self).allocate_near(origin, size)
+  }
+
+  unsafe fn release(&self, memory: *mut u8, size: usize) {
+    (**self).release(memory, size)
+  }
+
+  unsafe fn protect(&self, code: *const u8, size: usize, writable: bool) -> Result<()> {
+    (**self).protect(code, size, writable)
+  }
+
+  fn flush(&self, code: *const u8, size: usize) {
+    (**self).flush(code, size)
+  }
+}
+
+/// A detour using the memory services of a [Backend].
+///
+/// # Example
+///
+/// ```rust
+/// # use retour::Result;
+/// use retour::bare::{Detour, Native};
+///
+/// #[inline(never)]
+/// fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let mut hook = unsafe { Detour::new(Native::default(), add5 as *const (), add10 as *const ())? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+///
+/// let original: fn(i32) -> i32 = unsafe { std::mem::transmute(hook.trampoline()) };
+/// assert_eq!(original(5), 10);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Detour<B: Backend> {
+  backend: B,
+  target: *const (),
+  trampoline: Code,
+  relay: Code,
+  patch_area: &'static mut [u8],
+  original: Vec<u8>,
+  patch: Vec<u8>,
+  enabled: bool,
+}
+
+/// Code allocated by a backend.
+struct Code {
+  address: *mut u8,
+  size: usize,
+}
+
+impl<B: Backend> Detour<B> {
+  /// Creates a disabled detour of `target` to `detour`, using the memory
+  /// services of `backend`.
+  ///
+  /// # Safety
+  ///
+  /// The target must point to valid, executable code.
+  pub unsafe fn new(backend: B, target: *const (), detour: *const ()) -> Result<Self> {
+    if target == detour {
+      Err(Error::SameAddress)?;
+    }
+
+    if !backend.is_executable(target)? || !backend.is_executable(detour)? {
+      Err(Error::NotExecutable)?;
+    }
+
+    let trampoline = Trampoline::new(target, meta::prolog_margin(target))?;
+    let patch_area = patcher::patch_area(target, trampoline.prolog_size(), true, true, |address| {
+      backend.is_executable(address)
+    })?;
+    event!(
+      "selected patch area",
+      target = target,
+      kind = patcher::kind_of(target, trampoline.prolog_size(), patch_area),
+      area = patch_area.as_ptr(),
+      size = patch_area.len(),
+    );
+
+    // The detour may be out of reach of a relative jump from the target
+    let trampoline = Self::allocate(&backend, trampoline.emitter(), target)?;
+    let relay = match Self::allocate(&backend, &meta::relay_builder(detour), target) {
+      Ok(relay) => relay,
+      Err(error) => {
+        backend.release(trampoline.address, trampoline.size);
+        return Err(error);
+      },
+    };
+
+    let patch = patcher::hook_template(relay.address as *const (), patch_area)
+      .emit(patch_area.as_ptr() as *const ());
+    Ok(Detour {
+      original: patch_area.to_vec(),
+      backend,
+      target,
+      trampoline,
+      relay,
+      patch_area,
+      patch,
+      enabled: false,
+    })
+  }
+
+  /// Enables the detour.
+  ///
+  /// # Safety
+  ///
+  /// No other thread may execute the target's patch area meanwhile.
+  pub unsafe fn enable(&mut self) -> Result<()> {
+    self.toggle(true)
+  }
+
+  /// Disables the detour.
+  ///
+  /// # Safety
+  ///
+  /// No other thread may execute the target's patch area meanwhile.
+  pub unsafe fn disable(&mut self) -> Result<()> {
+    self.toggle(false)
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.enabled
+  }
+
+  /// Returns the address of the detoured target.
+  pub fn target(&self) -> *const () {
+    self.target
+  }
+
+  /// Returns a callable address to the original target.
+  pub fn trampoline(&self) -> *const () {
+    self.trampoline.address as *const ()
+  }
+
+  /// Returns the backend of the detour.
+  pub fn backend(&self) -> &B {
+    &self.backend
+  }
+
+  /// Either patches or unpatches the target.
+  unsafe fn toggle(&mut self, enable: bool) -> Result<()> {
+    if self.enabled == enable {
+      return Ok(());
+    }
+
+    let area = self.patch_area.as_ptr();
+    let size = self.patch_area.len();
+    self.backend.protect(area, size, true)?;
+    self.patch_area.copy_from_slice(if enable { &self.patch } else { &self.original });
+    self.backend.protect(area, size, false)?;
+    self.backend.flush(area, size);
+
+    self.enabled = enable;
+    Ok(())
+  }
+
+  /// Allocates code generated for its allocated address, close to `origin`.
+  unsafe fn allocate(backend: &B, emitter: &pic::CodeEmitter, origin: *const ()) -> Result<Code> {
+    let size = emitter.len();
+    let address = backend.allocate_near(origin, size)?;
+    let code = emitter.emit(address as *const ());
+    ptr::copy_nonoverlapping(code.as_ptr(), address, size);
+    backend.flush(address, size);
+    Ok(Code { address, size })
+  }
+}
+
+impl<B: Backend> Drop for Detour<B> {
+  /// Disables the detour, and releases its code.
+  fn drop(&mut self) {
+    unsafe {
+      // The code is leaked if the target cannot be restored
+      if self.toggle(false).is_ok() {
+        self.backend.release(self.trampoline.address, self.trampoline.size);
+        self.backend.release(self.relay.address, self.relay.size);
+      }
+    }
+  }
+}
+
+impl<B: Backend> fmt::Debug for Detour<B> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Detour")
+      .field("target", &self.target)
+      .field("trampoline", &self.trampoline())
+      .field("enabled", &self.enabled)
+      .finish_non_exhaustive()
+  }
+}
+
+unsafe impl<B: Backend + Send> Send for Detour<B> {}
+unsafe impl<B: Backend + Sync> Sync for Detour<B> {}
+
+/// The memory services of the operating system.
+///
+/// Code is allocated from the same pools as other detours.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct Native {
+  allocations: std::sync::Mutex<Vec<crate::alloc::ExecutableMemory>>,
+}
+
+#[cfg(feature = "std")]
+unsafe impl Backend for Native {
+  fn is_executable(&self, address: *const ()) -> Result<bool> {
+    crate::util::is_executable_address(address)
+  }
+
+  fn allocate_near(&self, origin: *const (), size: usize) -> Result<*mut u8> {
+    let mut memory = crate::arch::memory::pool().allocate(origin, size)?;
+    let address = memory.as_mut_ptr();
+    self.allocations().push(memory);
+    Ok(address)
+  }
+
+  unsafe fn release(&self, memory: *mut u8, _size: usize) {
+    self.allocations().retain(|allocation| !ptr::eq(allocation.as_ptr(), memory));
+  }
+
+  unsafe fn protect(&self, code: *const u8, size: usize, writable: bool) -> Result<()> {
+    use crate::error::{RegionError, RegionOperation};
+
+    let protection = if writable {
+      region::Protection::READ_WRITE_EXECUTE
+    } else {
+      region::Protection::READ_EXECUTE
+    };
+
+    region::protect(code, size, protection)
+      .map_err(RegionError::map(RegionOperation::Protect, code as *const ()))
+  }
+
+  fn flush(&self, _code: *const u8, _size: usize) {
+    crate::threads::sync_cores();
+  }
+}
+
+#[cfg(feature = "std")]
+impl Native {
+  /// Locks the allocations of the backend.
+  fn allocations(&self) -> std::sync::MutexGuard<'_, Vec<crate::alloc::ExecutableMemory>> {
+    self.allocations.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+  }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Native {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Native").finish_non_exhaustive()
+  }
+}
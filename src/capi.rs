@@ -0,0 +1,356 @@
+//! A C interface to raw detours and transactions.
+//!
+//! The functions are exported unmangled, and declared by `include/retour.h`.
+//! Build a library for linking with C or C++ using either crate type:
+//!
+//! ```sh
+//! cargo rustc --release --features capi --crate-type cdylib
+//! cargo rustc --release --features capi --crate-type staticlib
+//! ```
+//!
+//! Each fallible function returns a [Status], which corresponds to a variant
+//! of [Error].
+//!
+//! A panic never unwinds into the caller. It is caught by the function, which
+//! returns [Status::Panicked] (or null, `false` or nothing, if it does not
+//! return a status). This requires the library to be built with `panic =
+//! "unwind"`; otherwise a panic aborts the process.
+use crate::error::Error;
+use crate::{DetourTransaction, RawDetour};
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// The outcome of a fallible function.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+  /// The function succeeded.
+  Ok = 0,
+  /// An argument was a null pointer.
+  NullPointer,
+  /// See [Error::SameAddress].
+  SameAddress,
+  /// See [Error::InvalidCode].
+  InvalidCode,
+  /// See [Error::NoPatchArea].
+  NoPatchArea,
+  /// See [Error::NotExecutable].
+  NotExecutable,
+  /// See [Error::NotInitialized].
+  NotInitialized,
+  /// See [Error::AlreadyInitialized].
+  AlreadyInitialized,
+  /// See [Error::AlreadyHooked].
+  AlreadyHooked,
+  /// See [Error::OutOfMemory].
+  OutOfMemory,
+  /// See [Error::UnsupportedInstruction].
+  UnsupportedInstruction,
+  /// See [Error::RegionFailure].
+  RegionFailure,
+  /// See [Error::ThreadSafety].
+  ThreadSafety,
+  /// See [Error::CodeModified].
+  CodeModified,
+  /// See [Error::UnknownModule].
+  UnknownModule,
+  /// See [Error::LibraryFailure].
+  LibraryFailure,
+  /// See [Error::ModuleUnloaded].
+  ModuleUnloaded,
+  /// See [Error::RollbackFailed].
+  RollbackFailed,
+  /// The function panicked, and the panic was caught.
+  Panicked,
+}
+
+impl From<&Error> for Status {
+  fn from(error: &Error) -> Self {
+    match error {
+      Error::SameAddress => Status::SameAddress,
+      Error::InvalidCode(_) => Status::InvalidCode,
+      Error::NoPatchArea(_) => Status::NoPatchArea,
+      Error::NotExecutable => Status::NotExecutable,
+      Error::NotInitialized => Status::NotInitialized,
+      Error::AlreadyInitialized => Status::AlreadyInitialized,
+      Error::AlreadyHooked => Status::AlreadyHooked,
+      Error::OutOfMemory => Status::OutOfMemory,
+      Error::UnsupportedInstruction(_) => Status::UnsupportedInstruction,
+      Error::RegionFailure(_) => Status::RegionFailure,
+      Error::ThreadSafety(_) => Status::ThreadSafety,
+      Error::CodeModified => Status::CodeModified,
+      Error::UnknownModule => Status::UnknownModule,
+      Error::LibraryFailure(_) => Status::LibraryFailure,
+      Error::ModuleUnloaded => Status::ModuleUnloaded,
+      Error::RollbackFailed(_) => Status::RollbackFailed,
+    }
+  }
+}
+
+impl<T> From<crate::Result<T>> for Status {
+  fn from(result: crate::Result<T>) -> Self {
+    result.as_ref().err().map_or(Status::Ok, Status::from)
+  }
+}
+
+/// Runs the body of an exported function, returning `fallback` if it panics.
+///
+/// A caught panic leaves the library in the same state as it would for a Rust
+/// caller catching it, so unwind safety is asserted.
+fn catch<T>(fallback: T, body: impl FnOnce() -> T) -> T {
+  panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(fallback)
+}
+
+/// An opaque handle to a raw detour.
+pub struct RetourDetour(RawDetour);
+
+/// An opaque handle to a transaction.
+pub struct RetourTransaction {
+  transaction: DetourTransaction<'static>,
+  created: usize,
+}
+
+/// Returns a static, nul-terminated description of a status.
+#[no_mangle]
+pub extern "C" fn retour_status_message(status: Status) -> *const c_char {
+  let message: &'static [u8] = match status {
+    Status::Ok => b"Success\0",
+    Status::NullPointer => b"An argument is a null pointer\0",
+    Status::SameAddress => b"Target and detour address is the same\0",
+    Status::InvalidCode => b"Address contains invalid assembly\0",
+    Status::NoPatchArea => b"Cannot find an inline patch area\0",
+    Status::NotExecutable => b"Address is not executable\0",
+    Status::NotInitialized => b"Detour is not initialized\0",
+    Status::AlreadyInitialized => b"Detour is already initialized\0",
+    Status::AlreadyHooked => b"Target is already detoured by another library\0",
+    Status::OutOfMemory => b"Cannot allocate memory\0",
+    Status::UnsupportedInstruction => b"Address contains an unsupported instruction\0",
+    Status::RegionFailure => b"A memory operation failed\0",
+    Status::ThreadSafety => b"Cannot synchronize threads\0",
+    Status::CodeModified => b"Target code has been modified by another party\0",
+    Status::UnknownModule => b"Address does not belong to a loaded module\0",
+    Status::LibraryFailure => b"Cannot load a library, or find its symbol\0",
+    Status::ModuleUnloaded => b"Target's module has been unloaded\0",
+    Status::RollbackFailed => b"Cannot restore the previous state of some targets\0",
+    Status::Panicked => b"The function panicked\0",
+  };
+  message.as_ptr() as *const c_char
+}
+
+/// Creates a disabled detour of `target` to `detour`, stored in `*out`.
+///
+/// # Safety
+///
+/// The same requirements as for [RawDetour::new] apply, and `out` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn retour_create(
+  target: *const c_void,
+  detour: *const c_void,
+  out: *mut *mut RetourDetour,
+) -> Status {
+  catch(Status::Panicked, || {
+    if target.is_null() || detour.is_null() || out.is_null() {
+      return Status::NullPointer;
+    }
+
+    match RawDetour::new(target as *const (), detour as *const ()) {
+      Ok(hook) => {
+        *out = Box::into_raw(Box::new(RetourDetour(hook)));
+        Status::Ok
+      },
+      Err(error) => Status::from(&error),
+    }
+  })
+}
+
+/// Enables a detour.
+///
+/// # Safety
+///
+/// The detour must have been created by [retour_create], and not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn retour_enable(detour: *mut RetourDetour) -> Status {
+  catch(Status::Panicked, || match detour.as_ref() {
+    Some(detour) => detour.0.enable().into(),
+    None => Status::NullPointer,
+  })
+}
+
+/// Disables a detour.
+///
+/// # Safety
+///
+/// The detour must have been created by [retour_create], and not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn retour_disable(detour: *mut RetourDetour) -> Status {
+  catch(Status::Panicked, || match detour.as_ref() {
+    Some(detour) => detour.0.disable().into(),
+    None => Status::NullPointer,
+  })
+}
+
+/// Returns whether a detour is enabled.
+///
+/// # Safety
+///
+/// The detour must have been created by [retour_create], and not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn retour_is_enabled(detour: *const RetourDetour) -> bool {
+  catch(false, || detour.as_ref().map_or(false, |detour| detour.0.is_enabled()))
+}
+
+/// Returns a callable address to the original target, or null if `detour` is
+/// null.
+///
+/// # Safety
+///
+/// The detour must have been created by [retour_create], and not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn retour_trampoline(detour: *const RetourDetour) -> *const c_void {
+  catch(ptr::null(), || {
+    detour
+      .as_ref()
+      .map_or(ptr::null(), |detour| detour.0.trampoline() as *const () as *const c_void)
+  })
+}
+
+/// Disables and destroys a detour. Null is ignored.
+///
+/// # Safety
+///
+/// The detour must have been created by [retour_create], and not destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn retour_destroy(detour: *mut RetourDetour) {
+  catch((), || {
+    if !detour.is_null() {
+      drop(Box::from_raw(detour));
+    }
+  })
+}
+
+/// Creates a new, empty transaction.
+#[no_mangle]
+pub extern "C" fn retour_transaction_new() -> *mut RetourTransaction {
+  catch(ptr::null_mut(), || {
+    Box::into_raw(Box::new(RetourTransaction {
+      transaction: DetourTransaction::new(),
+      created: 0,
+    }))
+  })
+}
+
+/// Queues the creation of a detour, which is enabled once committed.
+///
+/// # Safety
+///
+/// The transaction must have been created by [retour_transaction_new], and
+/// neither committed nor aborted.
+#[no_mangle]
+pub unsafe extern "C" fn retour_transaction_create(
+  transaction: *mut RetourTransaction,
+  target: *const c_void,
+  detour: *const c_void,
+) -> Status {
+  catch(Status::Panicked, || match transaction.as_mut() {
+    Some(transaction) if !target.is_null() && !detour.is_null() => {
+      transaction.transaction.create(target as *const (), detour as *const ());
+      transaction.created += 1;
+      Status::Ok
+    },
+    _ => Status::NullPointer,
+  })
+}
+
+/// Queues enabling a detour.
+///
+/// # Safety
+///
+/// The transaction must have been created by [retour_transaction_new], and
+/// neither committed nor aborted. The detour must not be destroyed before the
+/// transaction is committed or aborted.
+#[no_mangle]
+pub unsafe extern "C" fn retour_transaction_enable(
+  transaction: *mut RetourTransaction,
+  detour: *const RetourDetour,
+) -> Status {
+  catch(Status::Panicked, || match (transaction.as_mut(), detour.as_ref()) {
+    (Some(transaction), Some(detour)) => {
+      transaction.transaction.enable(&detour.0);
+      Status::Ok
+    },
+    _ => Status::NullPointer,
+  })
+}
+
+/// Queues disabling a detour.
+///
+/// # Safety
+///
+/// The same requirements as for [retour_transaction_enable] apply.
+#[no_mangle]
+pub unsafe extern "C" fn retour_transaction_disable(
+  transaction: *mut RetourTransaction,
+  detour: *const RetourDetour,
+) -> Status {
+  catch(Status::Panicked, || match (transaction.as_mut(), detour.as_ref()) {
+    (Some(transaction), Some(detour)) => {
+      transaction.transaction.disable(&detour.0);
+      Status::Ok
+    },
+    _ => Status::NullPointer,
+  })
+}
+
+/// Applies all operations of a transaction atomically, and destroys it.
+///
+/// The detours created by the transaction are stored in `created`, in the
+/// order they were queued, which must have room for each of them (it may be
+/// null if none were queued). Nothing is stored if the transaction fails.
+///
+/// # Safety
+///
+/// The transaction must have been created by [retour_transaction_new], and
+/// neither committed nor aborted.
+#[no_mangle]
+pub unsafe extern "C" fn retour_transaction_commit(
+  transaction: *mut RetourTransaction,
+  created: *mut *mut RetourDetour,
+) -> Status {
+  catch(Status::Panicked, || {
+    if transaction.is_null() {
+      return Status::NullPointer;
+    }
+
+    let transaction = Box::from_raw(transaction);
+    if created.is_null() && transaction.created > 0 {
+      return Status::NullPointer;
+    }
+
+    match transaction.transaction.commit() {
+      Ok(detours) => {
+        for (index, detour) in detours.into_iter().enumerate() {
+          *created.add(index) = Box::into_raw(Box::new(RetourDetour(detour)));
+        }
+        Status::Ok
+      },
+      Err(error) => Status::from(&error),
+    }
+  })
+}
+
+/// Destroys a transaction without applying it. Null is ignored.
+///
+/// # Safety
+///
+/// The transaction must have been created by [retour_transaction_new], and
+/// neither committed nor aborted.
+#[no_mangle]
+pub unsafe extern "C" fn retour_transaction_abort(transaction: *mut RetourTransaction) {
+  catch((), || {
+    if !transaction.is_null() {
+      drop(Box::from_raw(transaction));
+    }
+  })
+}
@@ -0,0 +1,246 @@
+use crate::arch::{self, Detour, Options};
+use crate::error::Result;
+use crate::resolve;
+use crate::{Arena, CallerFilter, Function, GenericDetour, HookPlan, HookableWith, RawDetour, ThreadSafety};
+use std::marker::PhantomData;
+
+/// A builder for configuring a detour before it is created.
+///
+/// Every setting defaults to the behaviour of `RawDetour::new` and
+/// `GenericDetour::new`. A builder created using [new](#method.new) builds a
+/// [RawDetour], whilst one created using [generic](#method.generic) builds a
+/// [GenericDetour].
+///
+/// Settings concerning how the target is patched (i.e the prolog margin,
+/// padding and hot patching) only apply to the first detour of a target,
+/// since detours sharing a target share its patch.
+///
+/// # Example
+///
+/// ```rust
+/// # use retour::Result;
+/// use retour::{DetourBuilder, ThreadSafety};
+///
+/// #[inline(never)]
+/// fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe {
+///   DetourBuilder::generic(add5 as fn(i32) -> i32, add10 as fn(i32) -> i32)
+///     .allow_hot_patch(false)
+///     .thread_safety(ThreadSafety::Unsynchronized)
+///     .name("add5")
+///     .build()?
+/// };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+/// assert_eq!(hook.name().as_deref(), Some("add5"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DetourBuilder<T = ()> {
+  target: *const (),
+  detour: *const (),
+  options: Options,
+  phantom: PhantomData<T>,
+}
+
+/// When the target of a detour is patched.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PatchStrategy {
+  /// The target is patched whilst any of its detours are enabled (default).
+  OnEnable,
+  /// The target is patched once the detour is created, and remains patched
+  /// until it is dropped. Enabling or disabling the detour only uses atomic
+  /// stores, so it is async-signal-safe (see `RawDetour::new_signal_safe`).
+  Permanent,
+}
+
+impl Default for PatchStrategy {
+  fn default() -> Self {
+    PatchStrategy::OnEnable
+  }
+}
+
+/// How calls are relayed to a detour.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RelayMode {
+  /// The detour is called directly by the relay of its chain (default). A
+  /// dispatch stub is created once a per-thread feature is first used.
+  Direct,
+  /// The detour is called through a dispatch stub, created along with the
+  /// detour. Enabling the detour per thread, guarding against re-entrancy
+  /// and collecting statistics then never allocate.
+  Dispatch,
+}
+
+impl Default for RelayMode {
+  fn default() -> Self {
+    RelayMode::Direct
+  }
+}
+
+impl DetourBuilder {
+  /// Configures a raw detour.
+  pub fn new(target: *const (), detour: *const ()) -> Self {
+    DetourBuilder {
+      target,
+      detour,
+      options: Options::default(),
+      phantom: PhantomData,
+    }
+  }
+
+  /// Creates the raw detour.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for `RawDetour::new` apply.
+  pub unsafe fn build(self) -> Result<RawDetour> {
+    Detour::with_options(self.target, self.detour, &self.options).map(RawDetour)
+  }
+}
+
+impl<T: Function> DetourBuilder<T> {
+  /// Configures a type-safe detour.
+  pub fn generic<D>(target: T, detour: D) -> Self
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    DetourBuilder {
+      target: target.to_ptr(),
+      detour: detour.to_ptr(),
+      options: Options::default(),
+      phantom: PhantomData,
+    }
+  }
+
+  /// Creates the type-safe detour.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for `GenericDetour::new` apply.
+  pub unsafe fn build(self) -> Result<GenericDetour<T>> {
+    Detour::with_options(self.target, self.detour, &self.options).map(|detour| GenericDetour {
+      phantom: PhantomData,
+      detour,
+    })
+  }
+}
+
+impl<T> DetourBuilder<T> {
+  /// Sets when the target is patched.
+  pub fn patch(mut self, strategy: PatchStrategy) -> Self {
+    self.options.signal_safe = strategy == PatchStrategy::Permanent;
+    self
+  }
+
+  /// Sets how calls are relayed to the detour.
+  pub fn relay(mut self, mode: RelayMode) -> Self {
+    self.options.dispatch = mode == RelayMode::Dispatch;
+    self
+  }
+
+  /// Sets the callers the detour applies to (see
+  /// `RawDetour::set_caller_filter`), which relays it through a dispatch
+  /// stub.
+  pub fn caller_filter(mut self, filter: CallerFilter) -> Self {
+    self.options.caller_filter = Some(filter);
+    self
+  }
+
+  /// Follows up to `max_depth` jump stubs in front of the target (see
+  /// [resolve](./fn.resolve.html)).
+  ///
+  /// Building fails with `AlreadyHooked` if the target seems to have been
+  /// detoured by another library.
+  pub fn resolve(mut self, max_depth: usize) -> Self {
+    self.options.resolve = Some(max_depth);
+    self
+  }
+
+  /// Sets the minimum number of bytes relocated from the target's prolog.
+  ///
+  /// The default is the size of a relative jump. A smaller margin relies on
+  /// padding or a hot patch area, whilst a larger one relocates more
+  /// instructions than required.
+  pub fn prolog_margin(mut self, bytes: usize) -> Self {
+    self.options.margin = Some(bytes);
+    self
+  }
+
+  /// Sets the furthest distance between the target and the code allocated
+  /// for the detour.
+  ///
+  /// The distance can only be decreased from the default, which is the range
+  /// of a relative jump (2 GiB).
+  pub fn max_distance(mut self, bytes: usize) -> Self {
+    self.options.max_distance = bytes;
+    self
+  }
+
+  /// Allocates the code of the detour from an arena, reserved in advance.
+  ///
+  /// The arena must be within the maximum distance of the target.
+  pub fn arena(mut self, arena: &Arena) -> Self {
+    self.options.arena = Some(arena.clone());
+    self
+  }
+
+  /// Sets whether padding following a short prolog may be patched (default).
+  pub fn allow_padding(mut self, allow: bool) -> Self {
+    self.options.padding = allow;
+    self
+  }
+
+  /// Sets whether a hot patch area preceding the target may be patched,
+  /// reached by a short jump (default).
+  pub fn allow_hot_patch(mut self, allow: bool) -> Self {
+    self.options.hot_patch = allow;
+    self
+  }
+
+  /// Sets how other threads are treated whilst the detour is toggled.
+  pub fn thread_safety(mut self, mode: ThreadSafety) -> Self {
+    self.options.thread_safety = mode;
+    self
+  }
+
+  /// Sets the name of the detour, as listed by the
+  /// [registry](./registry/index.html).
+  pub fn name(mut self, name: &str) -> Self {
+    self.options.name = Some(name.to_string());
+    self
+  }
+
+  /// Sets the group of the detour (see
+  /// [enable_group](./registry/fn.enable_group.html)).
+  pub fn group(mut self, group: &str) -> Self {
+    self.options.group = Some(group.to_string());
+    self
+  }
+
+  /// Analyzes how the target would be detoured using these settings, without
+  /// modifying it.
+  ///
+  /// # Safety
+  ///
+  /// The target must point to valid, executable code.
+  pub unsafe fn plan(&self) -> HookPlan {
+    let target = self
+      .options
+      .resolve
+      .and_then(|max_depth| resolve::resolve(self.target, max_depth).ok())
+      .map_or(self.target, |resolution| resolution.address);
+    arch::analyze(target, &self.options)
+  }
+}
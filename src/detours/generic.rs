@@ -1,5 +1,6 @@
 use crate::arch::Detour;
 use crate::error::Result;
+use crate::{CallerFilter, Integrity, Stats, ThreadSafety};
 use crate::{Function, HookableWith};
 use std::marker::PhantomData;
 
@@ -14,6 +15,9 @@ use std::marker::PhantomData;
 /// fn call(&self, T::Arguments) -> T::Output
 /// ```
 ///
+/// If several detours share the same target, `call` invokes the next enabled
+/// detour of the target instead (see `set_priority`).
+///
 /// # Example
 ///
 /// ```rust
@@ -47,8 +51,8 @@ use std::marker::PhantomData;
 /// ```
 #[derive(Debug)]
 pub struct GenericDetour<T: Function> {
-  phantom: PhantomData<T>,
-  detour: Detour,
+  pub(crate) phantom: PhantomData<T>,
+  pub(crate) detour: Detour,
 }
 
 impl<T: Function> GenericDetour<T> {
@@ -65,6 +69,54 @@ impl<T: Function> GenericDetour<T> {
     })
   }
 
+  /// Create a new hook which can be enabled and disabled from a signal
+  /// handler.
+  ///
+  /// The target is patched once constructed, and remains patched until the
+  /// detour is dropped. Enabling or disabling the detour only swaps the
+  /// destination of a relay using atomic stores, without taking any locks,
+  /// allocating or changing memory protection. `enable`, `disable` and
+  /// `is_enabled` are therefore async-signal-safe, and may be used whilst the
+  /// global lock is held. Whilst disabled, every call to the target
+  /// passes through an additional indirect jump.
+  ///
+  /// # Safety
+  ///
+  /// The target must point to valid, executable code, which is patched
+  /// immediately.
+  pub unsafe fn new_signal_safe<D>(target: T, detour: D) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    Detour::new_signal_safe(target.to_ptr(), detour.to_ptr()).map(|detour| GenericDetour {
+      phantom: PhantomData,
+      detour,
+    })
+  }
+
+  /// Create a new hook, after following any jump stubs in front of the
+  /// target, up to `max_depth` jumps (see [resolve](./fn.resolve.html)).
+  ///
+  /// Fails with `AlreadyHooked` if the target seems to have been detoured by
+  /// another library.
+  ///
+  /// # Safety
+  ///
+  /// The target must point to valid, executable code.
+  pub unsafe fn new_resolved<D>(target: T, detour: D, max_depth: usize) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    Detour::new_resolved(target.to_ptr(), detour.to_ptr(), max_depth).map(|detour| {
+      GenericDetour {
+        phantom: PhantomData,
+        detour,
+      }
+    })
+  }
+
   /// Enables the detour.
   pub unsafe fn enable(&self) -> Result<()> {
     self.detour.enable()
@@ -75,12 +127,154 @@ impl<T: Function> GenericDetour<T> {
     self.detour.disable()
   }
 
+  /// Enables the detour for the current thread only.
+  ///
+  /// If the detour is not already enabled, other threads keep calling the
+  /// original function unless they enable the detour as well. Calling
+  /// `enable` enables it for all threads again.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for `enable` apply.
+  pub unsafe fn enable_for_current_thread(&self) -> Result<()> {
+    self.detour.enable_for_current_thread()
+  }
+
+  /// Disables the detour for the current thread only, without modifying the
+  /// target.
+  pub fn disable_for_current_thread(&self) -> Result<()> {
+    self.detour.disable_for_current_thread()
+  }
+
+  /// Calls a closure whilst the detour is bypassed on the current thread.
+  ///
+  /// Any call to the target made by the closure (on the current thread)
+  /// calls the next detour sharing the target, or the original function.
+  pub fn bypass_on_current_thread<R, F: FnOnce() -> R>(&self, closure: F) -> Result<R> {
+    self.detour.bypass_on_current_thread(closure)
+  }
+
+  /// Sets whether re-entrant calls bypass the detour.
+  ///
+  /// If the detour (directly or indirectly) calls its own target, the call
+  /// is passed on to the next detour, or the original function. This is
+  /// enabled by default once the detour is relayed through a dispatch stub
+  /// (see [RelayMode::Dispatch](./enum.RelayMode.html)). A detour called
+  /// directly by its relay is never guarded, since a guard requires the stub.
+  pub fn set_reentrancy_guard(&self, enabled: bool) -> Result<()> {
+    self.detour.set_reentrancy_guard(enabled)
+  }
+
+  /// Sets the callers the detour applies to, if limited.
+  ///
+  /// Calls returning elsewhere are passed on to the next detour, or the
+  /// original function, by the detour's dispatch stub without entering any
+  /// Rust code. Such calls are not counted by the statistics.
+  pub fn set_caller_filter(&self, filter: Option<CallerFilter>) -> Result<()> {
+    self.detour.set_caller_filter(filter)
+  }
+
+  /// Enables collecting call statistics, optionally including timings.
+  ///
+  /// Calls are counted by the detour's dispatch stub, without any changes
+  /// to the detour function itself.
+  pub fn enable_stats(&self, timed: bool) -> Result<()> {
+    self.detour.enable_stats(timed)
+  }
+
+  /// Returns the call statistics of the detour.
+  pub fn stats(&self) -> Stats {
+    self.detour.stats()
+  }
+
+  /// Resets the call statistics of the detour.
+  pub fn reset_stats(&self) {
+    self.detour.reset_stats()
+  }
+
   /// Returns whether the detour is enabled or not.
   pub fn is_enabled(&self) -> bool {
     self.detour.is_enabled()
   }
 
+  /// Returns whether the target's module has been unloaded (see
+  /// [RawDetour::is_detached](./struct.RawDetour.html#method.is_detached)).
+  pub fn is_detached(&self) -> bool {
+    self.detour.is_detached()
+  }
+
+  /// Returns how other threads are treated whilst the detour is toggled.
+  pub fn thread_safety(&self) -> ThreadSafety {
+    self.detour.thread_safety()
+  }
+
+  /// Sets how other threads are treated whilst the detour is toggled.
+  pub fn set_thread_safety(&self, mode: ThreadSafety) {
+    self.detour.set_thread_safety(mode)
+  }
+
+  /// Compares the target's code to the code written by this library, to
+  /// detect whether another party has modified it.
+  ///
+  /// A detour is never disabled whilst its target contains unrecognized
+  /// code, failing with `CodeModified` instead.
+  pub fn verify(&self) -> Integrity {
+    self.detour.verify()
+  }
+
+  /// Returns the name of the detour, if any.
+  pub fn name(&self) -> Option<String> {
+    self.detour.name()
+  }
+
+  /// Sets the name of the detour, as listed by the
+  /// [registry](./registry/index.html).
+  pub fn set_name(&self, name: &str) {
+    self.detour.set_name(Some(name.to_string()))
+  }
+
+  /// Returns the group of the detour, if any.
+  pub fn group(&self) -> Option<String> {
+    self.detour.group()
+  }
+
+  /// Sets the group of the detour, which can be toggled together with the
+  /// other detours of the group (see [enable_group](./registry/fn.enable_group.html)).
+  pub fn set_group(&self, group: &str) {
+    self.detour.set_group(Some(group.to_string()))
+  }
+
+  /// Returns the detour's priority among detours sharing its target.
+  pub fn priority(&self) -> i32 {
+    self.detour.priority()
+  }
+
+  /// Sets the detour's priority among detours sharing its target.
+  ///
+  /// Enabled detours with a higher priority are called first, and detours
+  /// with the same priority are called in the reverse order of being
+  /// enabled. The default priority is zero.
+  pub fn set_priority(&self, priority: i32) {
+    self.detour.set_priority(priority)
+  }
+
+  /// Changes the detour function, without modifying the target.
+  ///
+  /// The target always jumps through a relay with an atomically swappable
+  /// destination, so this may be done whilst the detour is enabled.
+  pub fn set_detour<D>(&self, detour: D) -> Result<()>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    unsafe { self.detour.set_detour(detour.to_ptr()) }
+  }
+
   /// Returns a reference to the generated trampoline.
+  ///
+  /// Unlike calls through `call`, calls made through it must have returned
+  /// before the detour is dropped (see
+  /// [wait_for_quiescence](crate::wait_for_quiescence)).
   pub fn trampoline(&self) -> &() {
     self.detour.trampoline()
   }
@@ -1,10 +1,14 @@
 use cfg_if::cfg_if;
 
+mod builder;
 mod generic;
 mod raw;
+mod transaction;
 
+pub use self::builder::*;
 pub use self::generic::*;
 pub use self::raw::*;
+pub use self::transaction::*;
 
 cfg_if! {
     if #[cfg(feature = "static-detour")] {
@@ -1,5 +1,6 @@
 use crate::arch::Detour;
 use crate::error::Result;
+use crate::{CallerFilter, Integrity, Stats, ThreadSafety};
 
 /// A raw detour.
 ///
@@ -38,9 +39,8 @@ use crate::error::Result;
 /// # }
 /// ```
 #[derive(Debug)]
-pub struct RawDetour(Detour);
+pub struct RawDetour(pub(crate) Detour);
 
-// TODO: stop all threads in target during patch?
 impl RawDetour {
   /// Constructs a new inline detour patcher.
   ///
@@ -53,6 +53,39 @@ impl RawDetour {
     Detour::new(target, detour).map(RawDetour)
   }
 
+  /// Constructs a detour which can be enabled and disabled from a signal
+  /// handler.
+  ///
+  /// The target is patched once constructed, and remains patched until the
+  /// detour is dropped. Enabling or disabling the detour only swaps the
+  /// destination of a relay using atomic stores, without taking any locks,
+  /// allocating or changing memory protection. `enable`, `disable` and
+  /// `is_enabled` are therefore async-signal-safe, and may be used whilst the
+  /// global lock is held. Whilst disabled, every call to the target
+  /// passes through an additional indirect jump.
+  ///
+  /// # Safety
+  ///
+  /// The target must point to valid, executable code, which is patched
+  /// immediately.
+  pub unsafe fn new_signal_safe(target: *const (), detour: *const ()) -> Result<Self> {
+    Detour::new_signal_safe(target, detour).map(RawDetour)
+  }
+
+  /// Constructs a new inline detour patcher, after following any jump stubs
+  /// in front of the target, up to `max_depth` jumps (see
+  /// [resolve](./fn.resolve.html)).
+  ///
+  /// Fails with `AlreadyHooked` if the target seems to have been detoured by
+  /// another library.
+  ///
+  /// # Safety
+  ///
+  /// The target must point to valid, executable code.
+  pub unsafe fn new_resolved(target: *const (), detour: *const (), max_depth: usize) -> Result<Self> {
+    Detour::new_resolved(target, detour, max_depth).map(RawDetour)
+  }
+
   /// Enables the detour.
   pub unsafe fn enable(&self) -> Result<()> {
     self.0.enable()
@@ -63,12 +96,156 @@ impl RawDetour {
     self.0.disable()
   }
 
+  /// Enables the detour for the current thread only.
+  ///
+  /// If the detour is not already enabled, other threads keep calling the
+  /// original function unless they enable the detour as well. Calling
+  /// `enable` enables it for all threads again.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for `enable` apply.
+  pub unsafe fn enable_for_current_thread(&self) -> Result<()> {
+    self.0.enable_for_current_thread()
+  }
+
+  /// Disables the detour for the current thread only, without modifying the
+  /// target.
+  pub fn disable_for_current_thread(&self) -> Result<()> {
+    self.0.disable_for_current_thread()
+  }
+
+  /// Calls a closure whilst the detour is bypassed on the current thread.
+  ///
+  /// Any call to the target made by the closure (on the current thread)
+  /// calls the next detour sharing the target, or the original function.
+  pub fn bypass_on_current_thread<R, F: FnOnce() -> R>(&self, closure: F) -> Result<R> {
+    self.0.bypass_on_current_thread(closure)
+  }
+
+  /// Sets whether re-entrant calls bypass the detour.
+  ///
+  /// If the detour (directly or indirectly) calls its own target, the call
+  /// is passed on to the next detour, or the original function. This is
+  /// enabled by default once the detour is relayed through a dispatch stub
+  /// (see [RelayMode::Dispatch](./enum.RelayMode.html)). A detour called
+  /// directly by its relay is never guarded, since a guard requires the stub.
+  pub fn set_reentrancy_guard(&self, enabled: bool) -> Result<()> {
+    self.0.set_reentrancy_guard(enabled)
+  }
+
+  /// Sets the callers the detour applies to, if limited.
+  ///
+  /// Calls returning elsewhere are passed on to the next detour, or the
+  /// original function, by the detour's dispatch stub without entering any
+  /// Rust code. Such calls are not counted by the statistics.
+  pub fn set_caller_filter(&self, filter: Option<CallerFilter>) -> Result<()> {
+    self.0.set_caller_filter(filter)
+  }
+
+  /// Enables collecting call statistics, optionally including timings.
+  ///
+  /// Calls are counted by the detour's dispatch stub, without any changes
+  /// to the detour function itself.
+  pub fn enable_stats(&self, timed: bool) -> Result<()> {
+    self.0.enable_stats(timed)
+  }
+
+  /// Returns the call statistics of the detour.
+  pub fn stats(&self) -> Stats {
+    self.0.stats()
+  }
+
+  /// Resets the call statistics of the detour.
+  pub fn reset_stats(&self) {
+    self.0.reset_stats()
+  }
+
   /// Returns whether the detour is enabled or not.
   pub fn is_enabled(&self) -> bool {
     self.0.is_enabled()
   }
 
+  /// Returns whether the target's module has been unloaded, in which case
+  /// the detour is never enabled, and no longer accesses the target (see
+  /// [detach_unloaded](./registry/fn.detach_unloaded.html)).
+  pub fn is_detached(&self) -> bool {
+    self.0.is_detached()
+  }
+
+  /// Returns how other threads are treated whilst the detour is toggled.
+  pub fn thread_safety(&self) -> ThreadSafety {
+    self.0.thread_safety()
+  }
+
+  /// Sets how other threads are treated whilst the detour is toggled.
+  pub fn set_thread_safety(&self, mode: ThreadSafety) {
+    self.0.set_thread_safety(mode)
+  }
+
+  /// Compares the target's code to the code written by this library, to
+  /// detect whether another party has modified it.
+  ///
+  /// A detour is never disabled whilst its target contains unrecognized
+  /// code, failing with `CodeModified` instead.
+  pub fn verify(&self) -> Integrity {
+    self.0.verify()
+  }
+
+  /// Returns the name of the detour, if any.
+  pub fn name(&self) -> Option<String> {
+    self.0.name()
+  }
+
+  /// Sets the name of the detour, as listed by the
+  /// [registry](./registry/index.html).
+  pub fn set_name(&self, name: &str) {
+    self.0.set_name(Some(name.to_string()))
+  }
+
+  /// Returns the group of the detour, if any.
+  pub fn group(&self) -> Option<String> {
+    self.0.group()
+  }
+
+  /// Sets the group of the detour, which can be toggled together with the
+  /// other detours of the group (see [enable_group](./registry/fn.enable_group.html)).
+  pub fn set_group(&self, group: &str) {
+    self.0.set_group(Some(group.to_string()))
+  }
+
+  /// Returns the detour's priority among detours sharing its target.
+  pub fn priority(&self) -> i32 {
+    self.0.priority()
+  }
+
+  /// Sets the detour's priority among detours sharing its target.
+  ///
+  /// Enabled detours with a higher priority are called first, and detours
+  /// with the same priority are called in the reverse order of being
+  /// enabled. The default priority is zero.
+  pub fn set_priority(&self, priority: i32) {
+    self.0.set_priority(priority)
+  }
+
+  /// Changes the detour function, without modifying the target.
+  ///
+  /// The target always jumps through a relay with an atomically swappable
+  /// destination, so this may be done whilst the detour is enabled.
+  ///
+  /// # Safety
+  ///
+  /// The detour must share the target's calling convention and prototype.
+  pub unsafe fn set_detour(&self, detour: *const ()) -> Result<()> {
+    self.0.set_detour(detour)
+  }
+
   /// Returns a reference to the generated trampoline.
+  ///
+  /// It calls the next detour sharing the target, or the original function.
+  /// Calls made through it are not accounted for once the detour is dropped,
+  /// so they must have returned before the detour is dropped (see
+  /// [wait_for_quiescence](crate::wait_for_quiescence)).
   pub fn trampoline(&self) -> &() {
     self.0.trampoline()
   }
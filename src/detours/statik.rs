@@ -1,5 +1,6 @@
+use crate::arch::Detour;
 use crate::error::{Error, Result};
-use crate::{Function, GenericDetour};
+use crate::{CallerFilter, Function, GenericDetour, Integrity, Stats, ThreadSafety, Transactable};
 use std::marker::Tuple;
 use std::sync::atomic::{AtomicPtr, Ordering};
 use std::{mem, ptr};
@@ -146,6 +147,66 @@ impl<T: Function> StaticDetour<T> {
       .disable()
   }
 
+  /// Enables the detour for the current thread only (see
+  /// [GenericDetour::enable_for_current_thread]).
+  pub unsafe fn enable_for_current_thread(&self) -> Result<()> {
+    self
+      .detour
+      .load(Ordering::SeqCst)
+      .as_ref()
+      .ok_or(Error::NotInitialized)?
+      .enable_for_current_thread()
+  }
+
+  /// Disables the detour for the current thread only.
+  pub fn disable_for_current_thread(&self) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .disable_for_current_thread()
+  }
+
+  /// Calls a closure whilst the detour is bypassed on the current thread.
+  pub fn bypass_on_current_thread<R, F: FnOnce() -> R>(&self, closure: F) -> Result<R> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .bypass_on_current_thread(closure)
+  }
+
+  /// Sets whether re-entrant calls bypass the detour.
+  pub fn set_reentrancy_guard(&self, enabled: bool) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .set_reentrancy_guard(enabled)
+  }
+
+  /// Sets the callers the detour applies to, if limited.
+  pub fn set_caller_filter(&self, filter: Option<CallerFilter>) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .set_caller_filter(filter)
+  }
+
+  /// Enables collecting call statistics, optionally including timings.
+  pub fn enable_stats(&self, timed: bool) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .enable_stats(timed)
+  }
+
+  /// Returns the call statistics of the detour.
+  pub fn stats(&self) -> Stats {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .map(|detour| detour.stats())
+      .unwrap_or_default()
+  }
+
+  /// Resets the call statistics of the detour.
+  pub fn reset_stats(&self) {
+    if let Some(detour) = unsafe { self.detour.load(Ordering::SeqCst).as_ref() } {
+      detour.reset_stats();
+    }
+  }
+
   /// Returns whether the detour is enabled or not.
   pub fn is_enabled(&self) -> bool {
     unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
@@ -153,6 +214,61 @@ impl<T: Function> StaticDetour<T> {
       .unwrap_or(false)
   }
 
+  /// Returns whether the target's module has been unloaded (see
+  /// [RawDetour::is_detached](./struct.RawDetour.html#method.is_detached)).
+  pub fn is_detached(&self) -> bool {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .map(|detour| detour.is_detached())
+      .unwrap_or(false)
+  }
+
+  /// Sets how other threads are treated whilst the detour is toggled.
+  pub fn set_thread_safety(&self, mode: ThreadSafety) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .set_thread_safety(mode);
+    Ok(())
+  }
+
+  /// Compares the target's code to the code written by this library, to
+  /// detect whether another party has modified it.
+  ///
+  /// A detour is never disabled whilst its target contains unrecognized
+  /// code, failing with `CodeModified` instead.
+  pub fn verify(&self) -> Result<Integrity> {
+    Ok(
+      unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+        .ok_or(Error::NotInitialized)?
+        .verify(),
+    )
+  }
+
+  /// Sets the name of the detour, as listed by the
+  /// [registry](./registry/index.html).
+  pub fn set_name(&self, name: &str) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .set_name(name);
+    Ok(())
+  }
+
+  /// Sets the group of the detour, which can be toggled together with the
+  /// other detours of the group (see [enable_group](./registry/fn.enable_group.html)).
+  pub fn set_group(&self, group: &str) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .set_group(group);
+    Ok(())
+  }
+
+  /// Sets the detour's priority among detours sharing its target.
+  pub fn set_priority(&self, priority: i32) -> Result<()> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
+      .ok_or(Error::NotInitialized)?
+      .set_priority(priority);
+    Ok(())
+  }
+
   /// Changes the detour, regardless of whether the hook is enabled or not.
   pub fn set_detour<C>(&self, closure: C)
   where
@@ -176,16 +292,29 @@ impl<T: Function> StaticDetour<T> {
     )
   }
 
-  /// Returns a transient reference to the active detour.
+  /// Marks a call through the trampoline as in progress.
+  pub(crate) fn enter(&self) -> Option<impl Drop + '_> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }.map(|detour| detour.detour.enter())
+  }
+
+  /// Returns a transient reference to the active detour, if any.
+  ///
+  /// This is called whenever the detour is invoked, so it must neither
+  /// allocate nor lock (e.g when detouring an allocator).
   #[doc(hidden)]
-  pub fn __detour(&self) -> &dyn Fn<T::Arguments, Output = T::Output>
+  pub fn __detour(&self) -> Option<&dyn Fn<T::Arguments, Output = T::Output>>
   where
     <T as Function>::Arguments: Tuple,
   {
     // TODO: This is not 100% thread-safe in case the thread is stopped
-    unsafe { self.closure.load(Ordering::SeqCst).as_ref() }
-      .ok_or(Error::NotInitialized)
-      .expect("retrieving detour closure")
+    unsafe { self.closure.load(Ordering::SeqCst).as_ref() }.map(|closure| &**closure as _)
+  }
+}
+
+impl<T: Function> super::transaction::private::Sealed for StaticDetour<T> {}
+impl<T: Function> Transactable for StaticDetour<T> {
+  fn __detour(&self) -> Option<&Detour> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }.and_then(|detour| detour.__detour())
   }
 }
 
@@ -0,0 +1,216 @@
+use crate::arch::{memory, Detour, Hook};
+use crate::error::{Error, Result, RollbackError};
+use crate::{Function, GenericDetour, RawDetour};
+
+/// A batch of detour operations that are applied atomically.
+///
+/// All operations are performed whilst holding the global lock exclusively,
+/// and the memory protection of each affected page is only changed once.
+/// If any detour uses
+/// [ThreadSafety::SuspendThreads](crate::ThreadSafety::SuspendThreads), other
+/// threads are suspended once for all targets, so they never observe a
+/// partially applied transaction.
+/// If any operation fails, all previous operations are rolled back and any
+/// detours created by the transaction are released. Should rolling back fail
+/// as well, [Error::RollbackFailed] reports the targets left inconsistent.
+///
+/// # Example
+///
+/// ```rust
+/// # use retour::Result;
+/// use retour::{DetourTransaction, GenericDetour};
+///
+/// #[inline(never)]
+/// fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// #[inline(never)]
+/// fn sub5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) - 5 }
+/// }
+///
+/// fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { GenericDetour::<fn(i32) -> i32>::new(add5, add10)? };
+///
+/// let mut transaction = DetourTransaction::new();
+/// transaction
+///   .enable(&hook)
+///   .create(sub5 as *const (), add10 as *const ());
+///
+/// // Detours created by the transaction are enabled once committed
+/// let created = unsafe { transaction.commit()? };
+///
+/// assert!(hook.is_enabled());
+/// assert!(created[0].is_enabled());
+/// assert_eq!(add5(5), 15);
+/// assert_eq!(sub5(5), 15);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct DetourTransaction<'a> {
+  operations: Vec<Operation<'a>>,
+}
+
+/// A queued transaction operation.
+enum Operation<'a> {
+  Create(*const (), *const ()),
+  Toggle(Option<&'a Detour>, bool),
+}
+
+impl<'a> DetourTransaction<'a> {
+  /// Creates a new, empty transaction.
+  pub fn new() -> Self {
+    DetourTransaction::default()
+  }
+
+  /// Queues the creation of a detour, which is enabled once committed.
+  pub fn create(&mut self, target: *const (), detour: *const ()) -> &mut Self {
+    self.operations.push(Operation::Create(target, detour));
+    self
+  }
+
+  /// Queues enabling a detour.
+  pub fn enable<D: Transactable>(&mut self, detour: &'a D) -> &mut Self {
+    self
+      .operations
+      .push(Operation::Toggle(detour.__detour(), true));
+    self
+  }
+
+  /// Queues disabling a detour.
+  pub fn disable<D: Transactable>(&mut self, detour: &'a D) -> &mut Self {
+    self
+      .operations
+      .push(Operation::Toggle(detour.__detour(), false));
+    self
+  }
+
+  /// Returns the number of queued operations.
+  pub fn len(&self) -> usize {
+    self.operations.len()
+  }
+
+  /// Returns whether there are no queued operations.
+  pub fn is_empty(&self) -> bool {
+    self.operations.is_empty()
+  }
+
+  /// Applies all operations, returning the detours that were created.
+  ///
+  /// The created detours are returned in the order they were queued.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for creating and enabling each individual
+  /// detour apply.
+  pub unsafe fn commit(self) -> Result<Vec<RawDetour>> {
+    let guard = memory::LOCK.write().unwrap();
+    let mut created = Vec::new();
+
+    let result = self
+      .operations
+      .iter()
+      .filter_map(|operation| match *operation {
+        Operation::Create(target, detour) => Some((target, detour)),
+        Operation::Toggle(..) => None,
+      })
+      .try_for_each(|(target, detour)| {
+        created.push(Detour::with_pool(memory::pool(), target, detour)?);
+        Ok(())
+      })
+      .and_then(|_| Self::apply(&self.operations, &created));
+
+    // Detours may only be released once the global lock is released
+    drop(guard);
+    result.map(|_| created.into_iter().map(RawDetour).collect())
+  }
+
+  /// Toggles the detours, rolling back if any fails.
+  unsafe fn apply(operations: &[Operation], created: &[Detour]) -> Result<()> {
+    let mut created = created.iter();
+    let toggles = operations
+      .iter()
+      .map(|operation| match *operation {
+        Operation::Create(..) => Ok((created.next().unwrap(), true)),
+        Operation::Toggle(detour, enabled) => Ok((detour.ok_or(Error::NotInitialized)?, enabled)),
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    // Runtime code is by default only read-execute
+    let _handles = memory::protect_areas(toggles.iter().map(|(detour, _)| detour.area()))?;
+
+    // Every detour is toggled before any target is patched, so other threads
+    // are suspended at most once for the whole transaction.
+    let mut applied: Vec<(&Detour, bool)> = Vec::with_capacity(toggles.len());
+    for (detour, enabled) in toggles {
+      // Enabling applies to all threads, as with 'enable'
+      if enabled {
+        detour.clear_thread_scope();
+      }
+
+      if detour.is_enabled() == enabled {
+        continue;
+      }
+
+      if let Err(error) = detour.write_deferred(enabled) {
+        return Err(RollbackError::check(error, Self::rollback(&applied)));
+      }
+      applied.push((detour, enabled));
+    }
+
+    let hooks = applied.iter().map(|(detour, _)| &***detour).collect::<Vec<_>>();
+    if let Err(error) = Hook::patch_chains(&hooks) {
+      return Err(RollbackError::check(error, Self::rollback(&applied)));
+    }
+    Ok(())
+  }
+
+  /// Restores the previous state of toggled detours, in reverse order,
+  /// returning the targets of those which could not be restored.
+  ///
+  /// No target has been patched by the transaction at this point.
+  unsafe fn rollback(applied: &[(&Detour, bool)]) -> Vec<usize> {
+    let mut failed = Vec::new();
+    for &(detour, enabled) in applied.iter().rev() {
+      if detour.write_deferred(!enabled).is_err() {
+        failed.push(detour.target() as usize);
+      }
+    }
+    failed
+  }
+}
+
+/// A detour that can be part of a [DetourTransaction].
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait Transactable: private::Sealed {
+  /// Returns the underlying detour, if initialized.
+  #[doc(hidden)]
+  fn __detour(&self) -> Option<&Detour>;
+}
+
+pub(crate) mod private {
+  /// Prevents [Transactable](super::Transactable) from being implemented
+  /// outside of this crate.
+  pub trait Sealed {}
+}
+
+impl private::Sealed for RawDetour {}
+impl Transactable for RawDetour {
+  fn __detour(&self) -> Option<&Detour> {
+    Some(&self.0)
+  }
+}
+
+impl<T: Function> private::Sealed for GenericDetour<T> {}
+impl<T: Function> Transactable for GenericDetour<T> {
+  fn __detour(&self) -> Option<&Detour> {
+    Some(&self.detour)
+  }
+}
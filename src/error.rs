@@ -1,10 +1,15 @@
 //! Error types and utilities.
 
+use crate::plan::PatchKind;
+use crate::util::Hex;
+use core::fmt;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
 use std::error::Error as StdError;
-use std::fmt;
 
 /// The result of a detour operation.
-pub type Result<T> = ::std::result::Result<T, Error>;
+pub type Result<T> = ::core::result::Result<T, Error>;
 
 /// A representation of all possible errors.
 #[derive(Debug)]
@@ -12,29 +17,51 @@ pub enum Error {
   /// The address for the target and detour are identical
   SameAddress,
   /// The address does not contain valid instructions.
-  InvalidCode,
+  InvalidCode(InstructionError),
   /// The address has no available area for patching.
-  NoPatchArea,
+  NoPatchArea(PatchAreaError),
   /// The address is not executable memory.
   NotExecutable,
   /// The detour is not initialized.
   NotInitialized,
   /// The detour is already initialized.
   AlreadyInitialized,
+  /// The target seems to have been detoured by another library.
+  AlreadyHooked,
   /// The system is out of executable memory.
   OutOfMemory,
   /// The address contains an instruction that prevents detouring.
-  UnsupportedInstruction,
+  UnsupportedInstruction(InstructionError),
   /// A memory operation failed.
-  RegionFailure(region::Error),
+  #[cfg(feature = "std")]
+  RegionFailure(RegionError),
+  /// Other threads could not be synchronized with the patch.
+  #[cfg(feature = "std")]
+  ThreadSafety(std::io::Error),
+  /// The target's code has been modified by another party, and is not
+  /// overwritten.
+  CodeModified,
+  /// The address does not belong to a loaded module.
+  UnknownModule,
+  /// The target's module has been unloaded, so the detour is detached.
+  ModuleUnloaded,
+  /// A library could not be loaded, or lacks a symbol.
+  #[cfg(feature = "std")]
+  LibraryFailure(LibraryError),
+  /// An operation on several detours failed, and so did restoring the
+  /// previous state of some of them.
+  #[cfg(feature = "std")]
+  RollbackFailed(RollbackError),
 }
 
+#[cfg(feature = "std")]
 impl StdError for Error {
   fn source(&self) -> Option<&(dyn StdError + 'static)> {
-    if let Error::RegionFailure(error) = self {
-      Some(error)
-    } else {
-      None
+    match self {
+      Error::RegionFailure(error) => Some(&error.error),
+      Error::ThreadSafety(error) => Some(error),
+      Error::RollbackFailed(error) => Some(&*error.error),
+      _ => None,
     }
   }
 }
@@ -43,20 +70,193 @@ impl fmt::Display for Error {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
       Error::SameAddress => write!(f, "Target and detour address is the same"),
-      Error::InvalidCode => write!(f, "Address contains invalid assembly"),
-      Error::NoPatchArea => write!(f, "Cannot find an inline patch area"),
+      Error::InvalidCode(ref error) => write!(f, "Address contains invalid assembly ({})", error),
+      Error::NoPatchArea(ref error) => write!(f, "Cannot find an inline patch area ({})", error),
       Error::NotExecutable => write!(f, "Address is not executable"),
       Error::NotInitialized => write!(f, "Detour is not initialized"),
       Error::AlreadyInitialized => write!(f, "Detour is already initialized"),
+      Error::AlreadyHooked => write!(f, "Target is already detoured by another library"),
       Error::OutOfMemory => write!(f, "Cannot allocate memory"),
-      Error::UnsupportedInstruction => write!(f, "Address contains an unsupported instruction"),
+      Error::UnsupportedInstruction(ref error) => {
+        write!(f, "Address contains an unsupported instruction ({})", error)
+      },
+      #[cfg(feature = "std")]
       Error::RegionFailure(ref error) => write!(f, "{}", error),
+      #[cfg(feature = "std")]
+      Error::ThreadSafety(ref error) => write!(f, "Cannot synchronize threads: {}", error),
+      Error::CodeModified => write!(f, "Target code has been modified by another party"),
+      Error::UnknownModule => write!(f, "Address does not belong to a loaded module"),
+      Error::ModuleUnloaded => write!(f, "Target's module has been unloaded"),
+      #[cfg(feature = "std")]
+      Error::LibraryFailure(ref error) => write!(f, "{}", error),
+      #[cfg(feature = "std")]
+      Error::RollbackFailed(ref error) => write!(f, "{}", error),
+    }
+  }
+}
+
+/// An instruction of a target's prolog, which prevents detouring it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionError {
+  /// The address of the target.
+  pub target: usize,
+  /// The offset of the instruction, relative to the target.
+  pub offset: usize,
+  /// The raw bytes of the instruction.
+  pub bytes: Vec<u8>,
+  /// The decoded mnemonic of the instruction (e.g `loop`), or `invalid`.
+  pub mnemonic: String,
+}
+
+impl fmt::Display for InstructionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} [{:?}] at {:#x}+{:#x}",
+      self.mnemonic,
+      Hex(&self.bytes),
+      self.target,
+      self.offset
+    )
+  }
+}
+
+/// The space available for patching a target, which is insufficient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchAreaError {
+  /// The address of the target.
+  pub target: usize,
+  /// The bytes available for an inline jump, including any padding.
+  pub available: usize,
+  /// The bytes required for an inline jump.
+  pub needed: usize,
+  /// The strategies that were tried, in order.
+  pub tried: Vec<PatchKind>,
+}
+
+impl fmt::Display for PatchAreaError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} of {} bytes available at {:#x}, tried {:?}",
+      self.available, self.needed, self.target, self.tried
+    )
+  }
+}
+
+/// A memory operation that failed.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionOperation {
+  /// Querying the protection of memory.
+  Query,
+  /// Changing the protection of memory.
+  Protect,
+  /// Searching for free memory to allocate.
+  Allocate,
+}
+
+/// A failed memory operation, and the address it applied to.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct RegionError {
+  /// The operation that failed.
+  pub operation: RegionOperation,
+  /// The address the operation applied to.
+  pub address: usize,
+  /// The underlying error.
+  pub error: region::Error,
+}
+
+#[cfg(feature = "std")]
+impl RegionError {
+  /// Returns a closure converting a `region` error of an operation.
+  pub(crate) fn map(operation: RegionOperation, address: *const ()) -> impl FnOnce(region::Error) -> Error {
+    move |error| {
+      Error::RegionFailure(RegionError {
+        operation,
+        address: address as usize,
+        error,
+      })
     }
   }
 }
 
-impl From<region::Error> for Error {
-  fn from(error: region::Error) -> Self {
-    Error::RegionFailure(error)
+#[cfg(feature = "std")]
+impl fmt::Display for RegionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let operation = match self.operation {
+      RegionOperation::Query => "query",
+      RegionOperation::Protect => "protect",
+      RegionOperation::Allocate => "allocate",
+    };
+    write!(f, "Cannot {} memory at {:#x}: {}", operation, self.address, self.error)
+  }
+}
+
+/// A library that could not be loaded, or lacks a symbol.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryError {
+  /// The path of the library.
+  pub path: std::path::PathBuf,
+  /// The symbol that could not be found, if the library was loaded.
+  pub symbol: Option<String>,
+  /// The reason reported by the dynamic loader.
+  pub message: String,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for LibraryError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.symbol {
+      Some(ref symbol) => write!(
+        f,
+        "Cannot find symbol `{}` in {}: {}",
+        symbol,
+        self.path.display(),
+        self.message
+      ),
+      None => write!(f, "Cannot load {}: {}", self.path.display(), self.message),
+    }
+  }
+}
+
+/// An operation on several detours which failed, leaving some of their
+/// targets inconsistent with the rest.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct RollbackError {
+  /// The error which caused the operation to be rolled back.
+  pub error: Box<Error>,
+  /// The targets whose previous state could not be restored.
+  pub targets: Vec<usize>,
+}
+
+#[cfg(feature = "std")]
+impl RollbackError {
+  /// Returns the error of a rolled back operation, unless restoring any of
+  /// the targets failed.
+  pub(crate) fn check(error: Error, targets: Vec<usize>) -> Error {
+    if targets.is_empty() {
+      return error;
+    }
+
+    event!("failed to roll back", targets = &targets, error = &error);
+    Error::RollbackFailed(RollbackError {
+      error: Box::new(error),
+      targets,
+    })
+  }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for RollbackError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}, and cannot restore targets:", self.error)?;
+    for target in &self.targets {
+      write!(f, " {:#x}", target)?;
+    }
+    Ok(())
   }
 }
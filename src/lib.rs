@@ -1,4 +1,5 @@
 #![recursion_limit = "1024"]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 #![cfg_attr(
   feature = "static-detour",
@@ -26,6 +27,33 @@
 //! - Detects NOP-padding.
 //! - Relay for large offsets (>2GB).
 //! - Supports hot patching.
+//! - Chains detours sharing the same target, in any order.
+//! - Optionally resolves jump stubs and detects foreign detours.
+//! - Optionally guards against re-entrancy, and enables or bypasses detours
+//!   per thread.
+//! - Optionally counts and times the calls of each detour.
+//! - Optionally limits detours to certain callers (e.g a module), and exposes
+//!   the [caller](./fn.caller.html) of a detour.
+//! - Optionally suspends other threads whilst patching (Linux only).
+//! - Optionally patches using breakpoints, similar to Linux's `text_poke_bp`.
+//! - Optionally toggles detours using atomic stores only, from signal handlers.
+//! - The call path of an enabled detour neither allocates nor locks, so
+//!   allocators can be detoured, and code may be reserved in advance using an
+//!   [Arena](./struct.Arena.html).
+//! - Dry-run analysis of how a target would be detoured.
+//! - A process-wide [registry](./registry/index.html) of named and grouped
+//!   detours.
+//! - Verifies that patches are intact, optionally using a background
+//!   [Watchdog](./struct.Watchdog.html).
+//! - Fork safe, with a configurable policy for the detours of forked children
+//!   (Unix only).
+//! - Detaches the detours of unloaded modules, so their targets are never
+//!   accessed again (Linux only), see
+//!   [detach_unloaded](./registry/fn.detach_unloaded.html).
+//! - Hot-reloadable detour libraries, see [reload](./reload/index.html)
+//!   (Unix only).
+//! - Optionally attributes crashes within trampolines, relays and detours to
+//!   their detour (Linux only), see [crash](./crash/index.html).
 //!
 //! ## Detours
 //!
@@ -43,6 +71,20 @@
 //!   others types abstract upon. It has no type-safety and interacts with raw
 //!   pointers. It should be avoided unless any types are references, or not
 //!   known until runtime.
+//!
+//! Raw and generic detours can also be configured before being created, using
+//! a [DetourBuilder](./struct.DetourBuilder.html).
+//!
+//! Several detours can be created, enabled and disabled atomically using a
+//! [DetourTransaction](./struct.DetourTransaction.html).
+//!
+//! The code generated for a detour is not released immediately once it is
+//! dropped, since other threads may still be executing it. It is reclaimed
+//! whenever a detour is created or dropped, once no thread can be executing
+//! it, and [wait_for_quiescence](./fn.wait_for_quiescence.html) waits until
+//! all of it is reclaimed (e.g before unloading a library). Calls made
+//! directly through a raw trampoline must have returned before its detour is
+//! dropped.
 //! 
 //! ## Supported Versions
 //! This crate, with default features, will support the MSRV in `Cargo.toml` 
@@ -58,6 +100,20 @@
 //! - **thiscall-abi**: Required for hooking functions that use the "thiscall" ABI. *Requires 1.73.0 or greater*
 //! - **28-args**: Allows for detouring functions up to 28 arguments (default is 14)
 //! - **42-args**: Allows for detouring functions up to 42 arguments
+//! - **log**: Emits debug events, using the `log` crate, for each decision made
+//!   whilst detouring (e.g the relocation of each instruction, the patch area
+//!   and the placement of allocated code).
+//! - **tracing**: Emits the same events using the `tracing` crate instead, with
+//!   each detail as a field.
+//! - **std** *(default)*: Required for everything but [bare](./bare/index.html)
+//!   detours.
+//! - **alloc**: Builds without `std` (but with `alloc`), in which case only
+//!   [bare](./bare/index.html) detours are available, using the memory
+//!   services of a user-implemented [Backend](./bare/trait.Backend.html).
+//!   Requires the default features to be disabled, since the disassembler
+//!   cannot be built with both.
+//! - **capi**: Exports a C interface to raw detours and transactions, see
+//!   [capi](./capi/index.html) and `include/retour.h`.
 //!
 //! ## Platforms
 //!
@@ -105,28 +161,77 @@
 //! 
 //! For various injection methods, see the [README in the GitHub repo](https://github.com/Hpmason/retour-rs)
 
+#[cfg(all(feature = "std", feature = "alloc"))]
+compile_error!("the `std` and `alloc` features are mutually exclusive");
+#[cfg(not(any(feature = "std", feature = "alloc")))]
+compile_error!("either the `std` or `alloc` feature must be enabled");
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
 // Re-exports
-pub use detours::*;
-pub use error::{Error, Result};
-pub use traits::{Function, HookableWith};
+pub use error::{Error, InstructionError, PatchAreaError, Result};
+pub use plan::{PatchKind, PlannedInstruction, Relocation};
+
+#[cfg(feature = "std")]
+pub use self::{
+  alloc::Arena,
+  arch::{caller, crash, registry, wait_for_quiescence, CallerFilter, Integrity, Stats, Watchdog},
+  detours::*,
+  error::{LibraryError, RegionError, RegionOperation, RollbackError},
+  plan::HookPlan,
+  resolve::{resolve, Resolution},
+  threads::ThreadSafety,
+  traits::{Function, HookableWith},
+};
+#[cfg(all(feature = "std", unix))]
+pub use arch::{set_fork_policy, ForkPolicy};
 
 #[macro_use]
 mod macros;
 
 // Modules
-mod alloc;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub mod bare;
+
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(all(feature = "std", unix))]
+pub mod reload;
+
 mod arch;
-mod detours;
 mod error;
 mod pic;
-mod traits;
+mod plan;
 mod util;
 
-#[cfg(test)]
+#[cfg(feature = "std")]
+mod alloc;
+#[cfg(feature = "std")]
+mod detours;
+#[cfg(feature = "std")]
+mod resolve;
+#[cfg(feature = "std")]
+mod threads;
+#[cfg(feature = "std")]
+mod traits;
+
+/// The items of the standard prelude, which are imported from `alloc`
+/// without `std`.
+#[cfg(not(feature = "std"))]
+mod prelude {
+  pub use alloc::boxed::Box;
+  pub use alloc::string::{String, ToString};
+  pub use alloc::vec::Vec;
+  pub use alloc::{format, vec};
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
   use super::*;
   use crate::Result;
   use matches::assert_matches;
+  use std::mem;
 
   #[test]
   fn detours_share_target() -> Result<()> {
@@ -160,6 +265,78 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  fn detours_share_target_out_of_order() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    extern "C" fn sub(x: i32, y: i32) -> i32 {
+      x - y
+    }
+
+    extern "C" fn mul(x: i32, y: i32) -> i32 {
+      x * y
+    }
+
+    let hook1 = unsafe { GenericDetour::<extern "C" fn(i32, i32) -> i32>::new(add, sub)? };
+    let hook2 = unsafe { GenericDetour::<extern "C" fn(i32, i32) -> i32>::new(add, mul)? };
+
+    unsafe { hook1.enable()? };
+    unsafe { hook2.enable()? };
+    assert_eq!(add(10, 5), 50);
+    assert_eq!(hook2.call(10, 5), 5);
+
+    // Removing the inner hook relinks the outer one to the original
+    unsafe { hook1.disable()? };
+    assert_eq!(add(10, 5), 50);
+    assert_eq!(hook2.call(10, 5), 15);
+
+    unsafe { hook1.enable()? };
+    assert_eq!(add(10, 5), 5);
+    assert_eq!(hook1.call(10, 5), 50);
+
+    mem::drop(hook1);
+    assert_eq!(add(10, 5), 50);
+    assert_eq!(hook2.call(10, 5), 15);
+
+    mem::drop(hook2);
+    assert_eq!(add(10, 5), 15);
+    Ok(())
+  }
+
+  #[test]
+  fn detours_share_target_priority() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    extern "C" fn sub(x: i32, y: i32) -> i32 {
+      x - y
+    }
+
+    extern "C" fn mul(x: i32, y: i32) -> i32 {
+      x * y
+    }
+
+    let hook1 = unsafe { GenericDetour::<extern "C" fn(i32, i32) -> i32>::new(add, sub)? };
+    let hook2 = unsafe { GenericDetour::<extern "C" fn(i32, i32) -> i32>::new(add, mul)? };
+    hook1.set_priority(10);
+
+    unsafe { hook1.enable()? };
+    unsafe { hook2.enable()? };
+    assert_eq!(add(10, 5), 5);
+    assert_eq!(hook1.call(10, 5), 50);
+    assert_eq!(hook2.call(10, 5), 15);
+
+    hook2.set_priority(20);
+    assert_eq!(add(10, 5), 50);
+    assert_eq!(hook2.call(10, 5), 5);
+    Ok(())
+  }
+
   #[test]
   fn same_detour_and_target() {
     #[inline(never)]
@@ -129,8 +129,12 @@ macro_rules! static_detour {
         #[allow(unused_unsafe)]
         $($modifier) * fn __ffi_detour(
             $($argument_name: $argument_type),*) -> $return_type {
+          // The original is called until a closure has been set
           #[allow(unused_unsafe)]
-          ($name.__detour())($($argument_name),*)
+          match $name.__detour() {
+            Some(closure) => closure($($argument_name),*),
+            None => unsafe { $name.call($($argument_name),*) },
+          }
         }
 
         $crate::StaticDetour::__new(__ffi_detour)
@@ -167,6 +171,7 @@ macro_rules! static_detour {
   };
 }
 
+#[cfg(feature = "std")]
 macro_rules! impl_hookable {
   (@recurse () ($($nm:ident : $ty:ident),*)) => {
     impl_hookable!(@impl_all ($($nm : $ty),*));
@@ -212,6 +217,7 @@ macro_rules! impl_hookable {
     impl<Ret: 'static, $($ty: 'static),*> $crate::StaticDetour<$target> {
       #[doc(hidden)]
       pub unsafe fn call(&self, $($nm : $ty),*) -> Ret {
+        let _guard = self.enter();
         let original: $target = ::std::mem::transmute(self.trampoline().expect("calling detour trampoline"));
         original($($nm),*)
       }
@@ -220,6 +226,7 @@ macro_rules! impl_hookable {
     impl<Ret: 'static, $($ty: 'static),*> $crate::GenericDetour<$target> {
       #[doc(hidden)]
       pub unsafe fn call(&self, $($nm : $ty),*) -> Ret {
+        let _guard = self.detour.enter();
         let original: $target = ::std::mem::transmute(self.trampoline());
         original($($nm),*)
       }
@@ -232,6 +239,7 @@ macro_rules! impl_hookable {
       #[doc(hidden)]
       pub fn call(&self, $($nm : $ty),*) -> Ret {
         unsafe {
+          let _guard = self.enter();
           let original: $fn_type = ::std::mem::transmute(self.trampoline().expect("calling detour trampoline"));
           original($($nm),*)
         }
@@ -242,6 +250,7 @@ macro_rules! impl_hookable {
       #[doc(hidden)]
       pub fn call(&self, $($nm : $ty),*) -> Ret {
         unsafe {
+          let _guard = self.detour.enter();
           let original: $fn_type = ::std::mem::transmute(self.trampoline());
           original($($nm),*)
         }
@@ -268,3 +277,21 @@ macro_rules! impl_hookable {
     impl_hookable!(@recurse ($($nm : $ty),*) ());
   };
 }
+
+/// Emits a debug event describing a decision made whilst detouring, with
+/// each field formatted using `Debug`.
+///
+/// Events are emitted using `tracing` or `log`, depending on the enabled
+/// feature, and are otherwise compiled out.
+macro_rules! event {
+  ($message:literal $(, $field:ident = $value:expr)* $(,)?) => {{
+    #[cfg(feature = "tracing")]
+    tracing::debug!($($field = ?$value,)* $message);
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::debug!(concat!($message $(, " ", stringify!($field), "={:?}")*), $($value),*);
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    if false {
+      $(let _ = &$value;)*
+    }
+  }};
+}
@@ -1,4 +1,6 @@
 use super::Thunkable;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 /// An interface for generating PIC.
 pub struct CodeEmitter {
@@ -1,6 +1,9 @@
 pub use self::emitter::CodeEmitter;
 pub use self::thunk::{FixedThunk, UnsafeThunk};
 
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
 mod emitter;
 mod thunk;
 
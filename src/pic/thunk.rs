@@ -1,5 +1,7 @@
 use super::Thunkable;
 use generic_array::{ArrayLength, GenericArray};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 /// A closure that generates a thunk.
 pub struct FixedThunk<N: ArrayLength<u8>>(Box<dyn Fn(usize) -> GenericArray<u8, N>>);
@@ -0,0 +1,138 @@
+//! Dry-run analysis of how a target would be detoured.
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use {
+  crate::arch,
+  crate::error::Error,
+  crate::util::Hex,
+  std::fmt,
+};
+
+/// A report of how a target would be detoured, without modifying it.
+///
+/// The same analysis is performed as when creating a detour, but no memory is
+/// allocated nor written.
+///
+/// # Example
+///
+/// ```rust
+/// use retour::HookPlan;
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// let plan = unsafe { HookPlan::analyze(add5 as *const ()) };
+/// assert!(plan.is_hookable());
+/// assert!(!plan.instructions.is_empty());
+/// println!("{}", plan);
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct HookPlan {
+  /// The analyzed target.
+  pub target: *const (),
+  /// Each decoded instruction of the prolog, which is relocated to the
+  /// trampoline.
+  pub instructions: Vec<PlannedInstruction>,
+  /// How the target would be patched, if possible.
+  pub patch: Option<PatchKind>,
+  /// Whether the target is already detoured by this library, in which case
+  /// the detour would share its trampoline.
+  pub chained: bool,
+  /// The error that would occur when creating the detour, if any.
+  pub error: Option<Error>,
+}
+
+/// A decoded instruction of a target's prolog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedInstruction {
+  /// The offset of the instruction, relative to the target.
+  pub offset: usize,
+  /// The raw bytes of the instruction.
+  pub bytes: Vec<u8>,
+  /// The disassembled instruction.
+  pub text: String,
+  /// How the instruction is relocated to the trampoline.
+  pub relocation: Relocation,
+}
+
+/// How an instruction is relocated to a trampoline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Relocation {
+  /// The instruction is copied as is.
+  Copied,
+  /// The instruction is copied, with its RIP-relative operand adjusted.
+  Displacement,
+  /// A branch within the prolog, which is copied as is.
+  InternalBranch,
+  /// A relative call, which is replaced by an absolute call.
+  Call,
+  /// A relative jump, which is replaced by an absolute jump.
+  Jump,
+  /// A conditional jump, which is replaced by an absolute conditional jump.
+  ConditionalJump,
+  /// The instruction cannot be relocated.
+  Unsupported,
+}
+
+/// How a target is patched to jump to its detour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PatchKind {
+  /// A relative jump replaces the prolog.
+  Jump,
+  /// A relative jump replaces the prolog, and the padding following it.
+  Padding,
+  /// A short jump to a relative jump, placed in the hot patch area preceding
+  /// the target.
+  HotPatch,
+}
+
+#[cfg(feature = "std")]
+impl HookPlan {
+  /// Analyzes how a target would be detoured.
+  ///
+  /// # Safety
+  ///
+  /// The target must point to valid, executable code.
+  pub unsafe fn analyze(target: *const ()) -> HookPlan {
+    arch::analyze(target, &arch::Options::default())
+  }
+
+  /// Returns whether the target can be detoured.
+  pub fn is_hookable(&self) -> bool {
+    self.error.is_none()
+  }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for HookPlan {
+  /// Outputs a readable report of the analysis.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Target {:p}: ", self.target)?;
+    match (&self.error, self.patch) {
+      (Some(error), _) => writeln!(f, "not hookable ({})", error)?,
+      (None, Some(patch)) => writeln!(f, "hookable ({:?} patch)", patch)?,
+      (None, None) => writeln!(f, "hookable")?,
+    }
+
+    if self.chained {
+      writeln!(f, "  Shares the trampoline of existing detours")?;
+    }
+
+    for instruction in &self.instructions {
+      writeln!(
+        f,
+        "  +{:<4x} {:<32} {:<40} {:?}",
+        instruction.offset,
+        format!("{:?}", Hex(&instruction.bytes)),
+        instruction.text,
+        instruction.relocation
+      )?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,383 @@
+//! Detours whose functions reside in a library that can be rebuilt and
+//! reloaded, without restarting the process.
+//!
+//! A [ReloadManager] owns the detours (and therefore the patched targets and
+//! trampolines), whilst only the detour functions reside in the library. Once
+//! reloaded, every detour is re-pointed to the function of the same name in
+//! the new library, and the previous library is unloaded once no thread is
+//! executing it.
+//!
+//! Each library is loaded from a temporary copy, so the original file can be
+//! overwritten by a build meanwhile, and the same path can be reloaded.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # use retour::Result;
+//! use retour::reload::ReloadManager;
+//! use std::time::Duration;
+//!
+//! #[inline(never)]
+//! extern "C" fn add5(val: i32) -> i32 {
+//!   unsafe { std::ptr::read_volatile(&val) + 5 }
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! let mut manager = unsafe { ReloadManager::new("target/debug/libdetours.so")? };
+//! let hook = unsafe { manager.hook(add5 as *const (), "add10")? };
+//! unsafe { hook.enable()? };
+//!
+//! // Once the library has been rebuilt
+//! unsafe { manager.reload("target/debug/libdetours.so", Duration::from_secs(1))? };
+//! # Ok(())
+//! # }
+//! ```
+use crate::arch::reclaim::{self, Calls};
+use crate::arch::{memory, Destination};
+use crate::error::{Error, LibraryError, Result};
+use crate::{threads, util, DetourBuilder, RawDetour, RelayMode};
+use std::ffi::{CStr, CString};
+use std::mem::ManuallyDrop;
+use std::ops::Range;
+use std::os::raw::c_void;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io, mem, process, thread};
+
+/// A loaded library.
+pub struct Library {
+  handle: NonNull<c_void>,
+  path: PathBuf,
+}
+
+impl Library {
+  /// Loads a library from a temporary copy of a file.
+  ///
+  /// # Safety
+  ///
+  /// The library's initializers are run.
+  pub unsafe fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+    let path = path.as_ref();
+    let failure = |message: String| {
+      Error::LibraryFailure(LibraryError {
+        path: path.to_path_buf(),
+        symbol: None,
+        message,
+      })
+    };
+
+    // The loader returns the same library for a path that is already loaded
+    let name = path.file_name().ok_or_else(|| failure("Not a file".to_string()))?;
+    let copy = std::env::temp_dir().join(format!(
+      "retour-{}-{}-{}",
+      process::id(),
+      SEQUENCE.fetch_add(1, Ordering::SeqCst),
+      name.to_string_lossy()
+    ));
+    fs::copy(path, &copy).map_err(|error| failure(error.to_string()))?;
+
+    let copy_name = CString::new(copy.as_os_str().as_bytes()).map_err(|error| failure(error.to_string()))?;
+    let handle = libc::dlopen(copy_name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+
+    // The copy remains mapped once loaded
+    let _ = fs::remove_file(&copy);
+    match NonNull::new(handle) {
+      Some(handle) => Ok(Library {
+        handle,
+        path: path.to_path_buf(),
+      }),
+      None => Err(failure(last_error())),
+    }
+  }
+
+  /// Returns the path the library was loaded from.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Returns the address of an exported symbol.
+  pub fn symbol(&self, name: &str) -> Result<*const ()> {
+    let failure = |message: String| {
+      Error::LibraryFailure(LibraryError {
+        path: self.path.clone(),
+        symbol: Some(name.to_string()),
+        message,
+      })
+    };
+
+    let symbol = CString::new(name).map_err(|error| failure(error.to_string()))?;
+    let address = unsafe { libc::dlsym(self.handle.as_ptr(), symbol.as_ptr()) };
+    if address.is_null() {
+      Err(failure(last_error()))?;
+    }
+    Ok(address as *const ())
+  }
+}
+
+impl Drop for Library {
+  /// Unloads the library.
+  fn drop(&mut self) {
+    unsafe { libc::dlclose(self.handle.as_ptr()) };
+  }
+}
+
+impl fmt::Debug for Library {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Library").field("path", &self.path).finish()
+  }
+}
+
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+/// Returns the most recent error of the dynamic loader.
+fn last_error() -> String {
+  let message = unsafe { libc::dlerror() };
+  if message.is_null() {
+    "Unknown error".to_string()
+  } else {
+    unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+  }
+}
+
+/// A library that has been replaced, but may still be executing.
+struct Retired {
+  /// The library, which is unloaded once dropped.
+  _library: Library,
+  /// The address range of the library, which is empty if unknown.
+  range: Range<usize>,
+  /// The calls to the library's detour functions, per detour.
+  calls: Vec<Calls>,
+}
+
+impl Retired {
+  /// Returns whether no call to any of the library's detour functions is in
+  /// progress.
+  fn is_idle(&self) -> bool {
+    self.calls.iter().all(Calls::is_idle)
+  }
+}
+
+/// Detours whose functions are loaded from a reloadable library.
+///
+/// Every detour is relayed through a dispatch stub, so the calls in progress
+/// within the library can be accounted for. Unwinding through a detour is
+/// therefore not supported.
+pub struct ReloadManager {
+  hooks: Vec<(String, RawDetour)>,
+  /// The current library, which is only unloaded once idle.
+  library: ManuallyDrop<Library>,
+  retired: Vec<Retired>,
+}
+
+impl ReloadManager {
+  /// Loads the library containing the detour functions.
+  ///
+  /// # Safety
+  ///
+  /// The library's initializers are run.
+  pub unsafe fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+    Ok(ReloadManager {
+      hooks: Vec::new(),
+      library: ManuallyDrop::new(Library::load(path)?),
+      retired: Vec::new(),
+    })
+  }
+
+  /// Returns the library currently in use.
+  pub fn library(&self) -> &Library {
+    &self.library
+  }
+
+  /// Creates a disabled detour of `target` to the library's function named
+  /// `symbol`, which is looked up again whenever the library is reloaded.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for [RawDetour::new] apply, for every version
+  /// of the library.
+  pub unsafe fn hook(&mut self, target: *const (), symbol: &str) -> Result<&RawDetour> {
+    let detour = self.library.symbol(symbol)?;
+    let hook = DetourBuilder::new(target, detour)
+      .relay(RelayMode::Dispatch)
+      .build()?;
+
+    self.hooks.push((symbol.to_string(), hook));
+    Ok(&self.hooks[self.hooks.len() - 1].1)
+  }
+
+  /// Returns the detour of a function, if any.
+  pub fn get(&self, symbol: &str) -> Option<&RawDetour> {
+    self
+      .hooks
+      .iter()
+      .find(|(name, _)| name == symbol)
+      .map(|(_, hook)| hook)
+  }
+
+  /// Loads a new version of the library, and re-points every detour to its
+  /// functions. Returns whether the previous library was unloaded before the
+  /// timeout (see [wait_for_unload](#method.wait_for_unload)).
+  ///
+  /// If the new library cannot be loaded, or lacks any function, the
+  /// detours are left as is.
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for [hook](#method.hook) apply.
+  pub unsafe fn reload<P: AsRef<Path>>(&mut self, path: P, timeout: Duration) -> Result<bool> {
+    self.reload_with(path, timeout, |_| Ok(()))
+  }
+
+  /// Loads a new version of the library, and calls `init` before any detour
+  /// is re-pointed to its functions (e.g to pass along the trampolines, which
+  /// remain the same).
+  ///
+  /// # Safety
+  ///
+  /// The same requirements as for [hook](#method.hook) apply.
+  pub unsafe fn reload_with<P, F>(&mut self, path: P, timeout: Duration, init: F) -> Result<bool>
+  where
+    P: AsRef<Path>,
+    F: FnOnce(&Library) -> Result<()>,
+  {
+    let library = Library::load(path)?;
+    let detours = self
+      .hooks
+      .iter()
+      .map(|(symbol, _)| {
+        let detour = library.symbol(symbol)?;
+        if !util::is_executable_address(detour)? {
+          Err(Error::NotExecutable)?;
+        }
+        Ok(detour)
+      })
+      .collect::<Result<Vec<_>>>()?;
+    init(&library)?;
+
+    let range = self.range();
+    let calls = self.swap(&detours)?;
+    event!(
+      "reloaded library",
+      path = library.path(),
+      detours = detours.len(),
+    );
+
+    let previous = mem::replace(&mut self.library, ManuallyDrop::new(library));
+    self.retired.push(Retired {
+      _library: ManuallyDrop::into_inner(previous),
+      range,
+      calls,
+    });
+    self.wait_for_unload(timeout)
+  }
+
+  /// Returns the address range of the current library, which is empty
+  /// unless it contains any detour function, and can be determined.
+  fn range(&self) -> Range<usize> {
+    self
+      .hooks
+      .first()
+      .and_then(|(_, hook)| util::module_range(hook.0.destination()))
+      .unwrap_or(0..0)
+  }
+
+  /// Re-points every detour at once, whilst other threads are suspended (if
+  /// supported), so no thread observes a mix of libraries. Returns the calls
+  /// to the previous detour functions.
+  unsafe fn swap(&self, detours: &[*const ()]) -> Result<Vec<Calls>> {
+    let destinations = detours.iter().map(|&detour| Destination::new(detour)).collect::<Vec<_>>();
+    let mut calls = Vec::with_capacity(destinations.len());
+
+    let _guard = memory::LOCK.write().unwrap();
+    let _threads = memory::THREADS.lock().unwrap();
+    let _suspended = match threads::suspend() {
+      Ok(suspended) => Some(suspended),
+      Err(Error::ThreadSafety(error)) if error.kind() == io::ErrorKind::Unsupported => None,
+      Err(error) => return Err(error),
+    };
+
+    // Nothing may be allocated whilst the threads are suspended
+    for ((_, hook), destination) in self.hooks.iter().zip(destinations) {
+      calls.push(hook.0.swap_dispatched_detour(destination)?);
+    }
+    Ok(calls)
+  }
+
+  /// Waits until no thread is executing any previous library, and unloads
+  /// them. Returns whether every previous library was unloaded before the
+  /// timeout.
+  ///
+  /// A library is unloaded once no call to any of its detour functions is
+  /// in progress and, on platforms which support suspending threads, no
+  /// thread is executing within it. Calls to the libraries loaded since are
+  /// not waited for. Libraries which could not be unloaded are retried by
+  /// any later call.
+  pub fn wait_for_unload(&mut self, timeout: Duration) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+      let idle = self.retired.iter().map(Retired::is_idle).collect::<Vec<_>>();
+      let ranges = self
+        .retired
+        .iter()
+        .zip(&idle)
+        .filter(|(_, &idle)| idle)
+        .map(|(retired, _)| retired.range.clone())
+        .collect::<Vec<_>>();
+
+      let mut executing = reclaim::executing_threads(&ranges)?.into_iter();
+      let mut idle = idle.into_iter();
+      self.retired.retain(|_| !idle.next().unwrap_or(false) || executing.next().unwrap_or(true));
+      if self.retired.is_empty() {
+        return Ok(true);
+      }
+
+      if Instant::now() >= deadline {
+        return Ok(false);
+      }
+
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
+}
+
+impl Drop for ReloadManager {
+  /// Drops every detour, and unloads the libraries unless any thread may
+  /// still be executing them, in which case they remain loaded.
+  fn drop(&mut self) {
+    let range = self.range();
+
+    // No call invokes a detour once disabled
+    let disabled = self
+      .hooks
+      .iter()
+      .all(|(_, hook)| unsafe { hook.disable() }.is_ok());
+    let idle = disabled
+      && self.hooks.iter().all(|(_, hook)| !hook.0.is_detour_active())
+      && matches!(self.wait_for_unload(Duration::ZERO), Ok(true))
+      && matches!(reclaim::executing_threads(&[range]).as_deref(), Ok([false]));
+
+    self.hooks.clear();
+    if idle {
+      unsafe { ManuallyDrop::drop(&mut self.library) };
+    } else {
+      mem::forget(mem::take(&mut self.retired));
+    }
+  }
+}
+
+impl fmt::Debug for ReloadManager {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("ReloadManager")
+      .field("library", &self.library)
+      .field("hooks", &self.hooks)
+      .field("retired", &self.retired.len())
+      .finish()
+  }
+}
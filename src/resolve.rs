@@ -0,0 +1,91 @@
+//! Resolution of jump stubs in front of functions.
+
+use crate::error::Result;
+use crate::{arch, util};
+
+/// A description of how a target was resolved.
+///
+/// See [resolve](./fn.resolve.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+  /// The address of the function body, after following all jumps.
+  pub address: *const (),
+  /// The address of each jump that was followed, starting with the target.
+  pub jumps: Vec<*const ()>,
+  /// The destination of a jump that seems to have been placed by another
+  /// hooking library (i.e it leads outside of any loaded module). It is not
+  /// followed.
+  pub foreign_hook: Option<*const ()>,
+}
+
+impl Resolution {
+  /// Returns whether the target seems to be detoured by another library.
+  pub fn is_hooked(&self) -> bool {
+    self.foreign_hook.is_some()
+  }
+}
+
+/// Follows unconditional jumps in front of a function, up to `max_depth`.
+///
+/// An address may refer to a jump stub, rather than the function itself. This
+/// is for example the case with PLT stubs (`jmp [rip+x]`), import thunks and
+/// incremental linking thunks (`jmp rel32`). Detouring such a stub only
+/// affects callers using that specific stub.
+///
+/// Any jump leading outside of a loaded module is not followed, since it is
+/// most likely a detour placed by another library. Targets already detoured
+/// by this library are not resolved any further, since detours sharing a
+/// target are chained. Detecting foreign detours is only supported on Linux.
+///
+/// Lazily bound PLT stubs resolve to the dynamic linker's stub, until the
+/// function has been called once.
+///
+/// # Safety
+///
+/// The target must point to valid, executable code.
+///
+/// # Example
+///
+/// ```rust
+/// # use retour::Result;
+/// use retour::resolve;
+///
+/// #[inline(never)]
+/// fn add5(val: i32) -> i32 {
+///   val + 5
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let resolution = unsafe { resolve(add5 as *const (), 8)? };
+/// assert!(!resolution.is_hooked());
+/// # Ok(())
+/// # }
+/// ```
+pub unsafe fn resolve(target: *const (), max_depth: usize) -> Result<Resolution> {
+  let mut resolution = Resolution {
+    address: target,
+    jumps: Vec::new(),
+    foreign_hook: None,
+  };
+
+  while resolution.jumps.len() < max_depth && !arch::is_detoured(resolution.address) {
+    let destination = match arch::jump_destination(resolution.address) {
+      Some(destination) => destination,
+      None => break,
+    };
+
+    if !util::is_executable_address(destination).unwrap_or(false) {
+      break;
+    }
+
+    if !util::is_module_address(destination) {
+      resolution.foreign_hook = Some(destination);
+      break;
+    }
+
+    resolution.jumps.push(resolution.address);
+    resolution.address = destination;
+  }
+
+  Ok(resolution)
+}
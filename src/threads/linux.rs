@@ -0,0 +1,374 @@
+use crate::error::{Error, Result};
+use once_cell::sync::{Lazy, OnceCell};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::os::raw::{c_int, c_void};
+use std::{fs, io, mem, ptr, thread};
+
+/// The maximum time to wait for all threads to be suspended.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Index of the instruction pointer within the saved registers.
+#[cfg(target_arch = "x86_64")]
+const REG_IP: usize = libc::REG_RIP as usize;
+#[cfg(target_arch = "x86")]
+const REG_IP: usize = 14; // REG_EIP
+
+/// Whether a suspension is in progress.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Whether suspended threads may continue.
+static RESUME: AtomicBool = AtomicBool::new(false);
+/// The number of threads executing the signal handler.
+static ENTERED: AtomicUsize = AtomicUsize::new(0);
+/// The number of threads that have stored their context.
+static PARKED: AtomicUsize = AtomicUsize::new(0);
+/// The current suspension, which each signal is tagged with.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+/// The breakpoint address and its redirection, whilst a patch is written.
+static BREAKPOINT: AtomicUsize = AtomicUsize::new(0);
+static BREAKPOINT_REDIRECT: AtomicUsize = AtomicUsize::new(0);
+/// The most recent breakpoint address, kept after it has been removed.
+static LAST_BREAKPOINT: AtomicUsize = AtomicUsize::new(0);
+/// The SIGTRAP handler that was installed before ours.
+static mut PREVIOUS_TRAP_HANDLER: Option<libc::sigaction> = None;
+/// Storage for the context of each suspended thread.
+static SLOTS: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+static SLOT_COUNT: AtomicUsize = AtomicUsize::new(0);
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+/// The saved state of a suspended thread.
+struct Slot {
+  tid: AtomicI32,
+  context: AtomicPtr<libc::ucontext_t>,
+}
+
+/// The leading fields of a queued signal's information (i.e `_sigqueue`).
+#[repr(C)]
+struct QueueInfo {
+  signo: c_int,
+  errno: c_int,
+  code: c_int,
+  fields: QueueFields,
+}
+
+#[repr(C)]
+struct QueueFields {
+  pid: libc::pid_t,
+  uid: libc::uid_t,
+  value: usize,
+}
+
+/// A guard for suspended threads, which are resumed once dropped.
+pub struct Suspended {
+  slots: Vec<Slot>,
+}
+
+/// Suspends all threads of the process, except the calling one.
+///
+/// Once the first thread has been suspended, nothing may allocate or take any
+/// locks until the returned guard is dropped, since a suspended thread may
+/// own them.
+pub unsafe fn suspend() -> Result<Suspended> {
+  static HANDLER: OnceCell<()> = OnceCell::new();
+  HANDLER.get_or_try_init(|| install_handler())?;
+
+  let current = gettid();
+  let mut threads = fs::read_dir("/proc/self/task")
+    .map_err(Error::ThreadSafety)?
+    .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<libc::pid_t>().ok())
+    .filter(|tid| *tid != current)
+    .collect::<Vec<_>>();
+
+  let slots = threads
+    .iter()
+    .map(|_| Slot {
+      tid: AtomicI32::new(0),
+      context: AtomicPtr::new(ptr::null_mut()),
+    })
+    .collect::<Vec<_>>();
+
+  SLOTS.store(slots.as_ptr() as *mut _, Ordering::SeqCst);
+  SLOT_COUNT.store(slots.len(), Ordering::SeqCst);
+  NEXT_SLOT.store(0, Ordering::SeqCst);
+  PARKED.store(0, Ordering::SeqCst);
+  RESUME.store(false, Ordering::SeqCst);
+  let generation = GENERATION.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+  ACTIVE.store(true, Ordering::SeqCst);
+  let suspended = Suspended { slots };
+
+  // Threads that have exited in the meantime can safely be ignored
+  threads.retain(|&tid| queue_signal(tid, generation));
+
+  let start = Instant::now();
+  while PARKED.load(Ordering::SeqCst) < threads.len() {
+    if start.elapsed() > TIMEOUT {
+      // Remove any thread that has exited before it could handle the signal
+      threads.retain(|&tid| suspended.is_parked(tid) || signal(tid, 0));
+
+      if PARKED.load(Ordering::SeqCst) < threads.len() {
+        // The signals still queued are tagged with this suspension, so once
+        // they arrive they are acknowledged and ignored (see 'handle_suspend').
+        // Resuming threads and creating the error does not allocate.
+        mem::drop(suspended);
+        return Err(Error::ThreadSafety(io::ErrorKind::TimedOut.into()));
+      }
+    }
+
+    thread::yield_now();
+  }
+
+  Ok(suspended)
+}
+
+impl Suspended {
+  /// Relocates the instruction pointers of all suspended threads.
+  ///
+  /// The closure receives the current instruction pointer of a thread, and
+  /// may return a new address which the thread will continue from.
+  pub unsafe fn relocate<F: Fn(usize) -> Option<usize>>(&self, relocate: F) {
+    for slot in &self.slots {
+      if let Some(context) = slot.context.load(Ordering::SeqCst).as_mut() {
+        let registers = &mut context.uc_mcontext.gregs;
+        if let Some(address) = relocate(registers[REG_IP] as usize) {
+          registers[REG_IP] = address as _;
+        }
+      }
+    }
+  }
+
+  /// Returns whether a thread has stored its context.
+  fn is_parked(&self, tid: libc::pid_t) -> bool {
+    self
+      .slots
+      .iter()
+      .any(|slot| slot.tid.load(Ordering::SeqCst) == tid)
+  }
+}
+
+impl Drop for Suspended {
+  /// Resumes all suspended threads.
+  fn drop(&mut self) {
+    ACTIVE.store(false, Ordering::SeqCst);
+    RESUME.store(true, Ordering::SeqCst);
+
+    // The slots may not be released whilst any handler can access them
+    while ENTERED.load(Ordering::SeqCst) > 0 {
+      thread::yield_now();
+    }
+
+    SLOTS.store(ptr::null_mut(), Ordering::SeqCst);
+    SLOT_COUNT.store(0, Ordering::SeqCst);
+  }
+}
+
+/// Parks the receiving thread until the suspension is over.
+extern "C" fn handle_suspend(_: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+  ENTERED.fetch_add(1, Ordering::SeqCst);
+
+  // The signal may arrive after its suspension has timed out, possibly once
+  // another suspension is in progress, in which case it is only acknowledged
+  let info = unsafe { &*info.cast::<QueueInfo>() };
+  let is_current = info.code == libc::SI_QUEUE && info.fields.value == GENERATION.load(Ordering::SeqCst);
+
+  if is_current && ACTIVE.load(Ordering::SeqCst) {
+    let index = NEXT_SLOT.fetch_add(1, Ordering::SeqCst);
+    let slots = SLOTS.load(Ordering::SeqCst);
+
+    if index < SLOT_COUNT.load(Ordering::SeqCst) && !slots.is_null() {
+      let slot = unsafe { &*slots.add(index) };
+      slot.context.store(context.cast(), Ordering::SeqCst);
+      slot.tid.store(gettid(), Ordering::SeqCst);
+    }
+
+    PARKED.fetch_add(1, Ordering::SeqCst);
+    while !RESUME.load(Ordering::SeqCst) {
+      unsafe { libc::sched_yield() };
+    }
+  }
+
+  ENTERED.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// A guard for a breakpoint redirection, which is removed once dropped.
+pub struct Redirect(());
+
+/// Redirects any thread hitting a breakpoint (`int3`) at `address` to
+/// `destination`, until the returned guard is dropped.
+///
+/// Only one redirection may be active at a time. The SIGTRAP handler is not
+/// removed once the guard is dropped, since a thread that hit the breakpoint
+/// may only report it afterwards (see `handle_trap`).
+pub unsafe fn redirect(address: *const (), destination: *const ()) -> Result<Redirect> {
+  static HANDLER: OnceCell<()> = OnceCell::new();
+  HANDLER.get_or_try_init(|| install_trap_handler())?;
+
+  BREAKPOINT_REDIRECT.store(destination as usize, Ordering::SeqCst);
+  LAST_BREAKPOINT.store(address as usize, Ordering::SeqCst);
+  BREAKPOINT.store(address as usize, Ordering::SeqCst);
+  Ok(Redirect(()))
+}
+
+impl Drop for Redirect {
+  fn drop(&mut self) {
+    BREAKPOINT.store(0, Ordering::SeqCst);
+  }
+}
+
+/// Serializes the instruction stream of all cores executing this process.
+///
+/// This ensures that no core executes stale instructions after code has been
+/// modified. If the kernel lacks support, it relies on the cache coherency
+/// of the processor instead.
+pub fn sync_cores() {
+  const MEMBARRIER_CMD_PRIVATE_EXPEDITED: c_int = 1 << 3;
+  const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: c_int = 1 << 4;
+  const MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE: c_int = 1 << 5;
+  const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE: c_int = 1 << 6;
+
+  static COMMAND: Lazy<Option<c_int>> = Lazy::new(|| {
+    let register = |command: c_int| unsafe { libc::syscall(libc::SYS_membarrier, command, 0) == 0 };
+
+    // Interrupting the cores is serializing on x86, even without 'SYNC_CORE'
+    if register(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE) {
+      Some(MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE)
+    } else if register(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED) {
+      Some(MEMBARRIER_CMD_PRIVATE_EXPEDITED)
+    } else {
+      None
+    }
+  });
+
+  if let Some(command) = *COMMAND {
+    unsafe { libc::syscall(libc::SYS_membarrier, command, 0) };
+  }
+}
+
+/// Redirects threads hitting the active breakpoint.
+extern "C" fn handle_trap(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+  const INT3: u8 = 0xCC;
+
+  let registers = unsafe { &mut (*context.cast::<libc::ucontext_t>()).uc_mcontext.gregs };
+  let address = (registers[REG_IP] as usize).wrapping_sub(1);
+
+  // Breakpoint instructions are reported by the kernel itself
+  let is_breakpoint = unsafe { (*info).si_code } == libc::SI_KERNEL;
+
+  if is_breakpoint && address == BREAKPOINT.load(Ordering::SeqCst) {
+    registers[REG_IP] = BREAKPOINT_REDIRECT.load(Ordering::SeqCst) as _;
+  } else if is_breakpoint
+    && address == LAST_BREAKPOINT.load(Ordering::SeqCst)
+    && unsafe { *(address as *const u8) } != INT3
+  {
+    // The breakpoint was removed after it was hit, so execute the
+    // instruction that has replaced it instead.
+    registers[REG_IP] = address as _;
+  } else {
+    unsafe { chain_trap_handler(signal, info, context) };
+  }
+}
+
+/// Forwards a signal to the SIGTRAP handler that preceded ours.
+unsafe fn chain_trap_handler(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+  if let Some(previous) = PREVIOUS_TRAP_HANDLER {
+    forward_signal(&previous, signal, info, context);
+  }
+}
+
+/// Returns the instruction pointer of a signal's context.
+pub unsafe fn instruction_pointer(context: *mut c_void) -> usize {
+  (*context.cast::<libc::ucontext_t>()).uc_mcontext.gregs[REG_IP] as usize
+}
+
+/// Forwards a signal to a handler that preceded ours.
+pub unsafe fn forward_signal(
+  previous: &libc::sigaction,
+  signal: c_int,
+  info: *mut libc::siginfo_t,
+  context: *mut c_void,
+) {
+  match previous.sa_sigaction {
+    libc::SIG_IGN => (),
+    libc::SIG_DFL => {
+      // Let the default action take place once the handler returns
+      libc::sigaction(signal, previous, ptr::null_mut());
+      libc::raise(signal);
+    },
+    action if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+      let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+        mem::transmute(action);
+      handler(signal, info, context);
+    },
+    action => {
+      let handler: extern "C" fn(c_int) = mem::transmute(action);
+      handler(signal);
+    },
+  }
+}
+
+/// Installs the signal handler used for breakpoint redirection, which
+/// forwards any trap it does not own to the previous handler.
+unsafe fn install_trap_handler() -> Result<()> {
+  let mut action: libc::sigaction = mem::zeroed();
+  action.sa_sigaction = handle_trap as extern "C" fn(_, _, _) as usize;
+  action.sa_flags = libc::SA_SIGINFO;
+  libc::sigemptyset(&mut action.sa_mask);
+
+  // The previous handler must be known before any signal can be received
+  let mut previous: libc::sigaction = mem::zeroed();
+  if libc::sigaction(libc::SIGTRAP, ptr::null(), &mut previous) != 0 {
+    Err(Error::ThreadSafety(io::Error::last_os_error()))?;
+  }
+
+  PREVIOUS_TRAP_HANDLER = Some(previous);
+  if libc::sigaction(libc::SIGTRAP, &action, ptr::null_mut()) != 0 {
+    Err(Error::ThreadSafety(io::Error::last_os_error()))?;
+  }
+
+  Ok(())
+}
+
+/// Installs the signal handler used for suspending threads.
+unsafe fn install_handler() -> Result<()> {
+  let mut action: libc::sigaction = mem::zeroed();
+  action.sa_sigaction = handle_suspend as extern "C" fn(_, _, _) as usize;
+  action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+  libc::sigemptyset(&mut action.sa_mask);
+
+  if libc::sigaction(signal_number(), &action, ptr::null_mut()) != 0 {
+    Err(Error::ThreadSafety(io::Error::last_os_error()))?;
+  }
+
+  Ok(())
+}
+
+/// Returns the signal used for suspending threads.
+fn signal_number() -> c_int {
+  libc::SIGRTMIN() + 5
+}
+
+/// Returns the kernel ID of the calling thread.
+fn gettid() -> libc::pid_t {
+  unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t }
+}
+
+/// Queues the suspension signal to a thread, tagged with a suspension's
+/// generation, returning whether the thread exists.
+fn queue_signal(tid: libc::pid_t, generation: usize) -> bool {
+  unsafe {
+    let mut info: libc::siginfo_t = mem::zeroed();
+    let queued = &mut *(&mut info as *mut libc::siginfo_t).cast::<QueueInfo>();
+    queued.signo = signal_number();
+    queued.code = libc::SI_QUEUE;
+    queued.fields.pid = libc::getpid();
+    queued.fields.uid = libc::getuid();
+    queued.fields.value = generation;
+
+    let info = &info as *const libc::siginfo_t;
+    libc::syscall(libc::SYS_rt_tgsigqueueinfo, libc::getpid(), tid, signal_number(), info) == 0
+  }
+}
+
+/// Sends a signal to a thread, returning whether the thread exists.
+fn signal(tid: libc::pid_t, signal: c_int) -> bool {
+  unsafe { libc::syscall(libc::SYS_tgkill, libc::getpid(), tid, signal) == 0 }
+}
@@ -0,0 +1,78 @@
+//! Synchronization of other threads whilst a detour is being toggled.
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        mod linux;
+        pub use self::linux::{forward_signal, instruction_pointer, redirect, suspend, sync_cores};
+    } else {
+        mod unsupported;
+        pub use self::unsupported::{redirect, suspend, sync_cores};
+    }
+}
+
+/// Determines how other threads are treated whilst a detour is toggled.
+///
+/// A thread that is executing the prolog of a target as it is being patched
+/// may end up executing a mix of the old and the new instructions. This is
+/// rarely an issue, but hot functions called from many threads may crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThreadSafety {
+  /// The patch is written whilst other threads keep running (default).
+  Unsynchronized,
+  /// All other threads are stopped whilst the patch is written, and any
+  /// thread that is executing the prolog is moved to the equivalent
+  /// instruction in the trampoline (and back again once disabled).
+  ///
+  /// This is currently only supported on Linux. Threads are stopped by
+  /// sending them a real-time signal (`SIGRTMIN + 5`), so threads blocking
+  /// that signal will cause toggling to time out.
+  SuspendThreads,
+  /// The patch is written so that no thread reaching the target meanwhile
+  /// executes a partially written instruction (modeled after Linux's
+  /// `text_poke_bp`).
+  ///
+  /// If the patch fits within an aligned 8-byte word, it is written using a
+  /// single atomic store. Otherwise a breakpoint is written first, followed
+  /// by the tail of the patch and finally its first byte. Any thread hitting
+  /// the breakpoint meanwhile is redirected to the trampoline (Linux only).
+  ///
+  /// Other threads are not stopped, so a thread that is already executing
+  /// the prolog past its first instruction (e.g one that was preempted
+  /// mid-prolog) may still execute the new bytes. This mode is therefore
+  /// only fully safe if the patch replaces a single instruction; otherwise
+  /// use [SuspendThreads](ThreadSafety::SuspendThreads).
+  ///
+  /// The SIGTRAP handler is installed once first needed and then remains
+  /// installed, since a thread may report a breakpoint after it has been
+  /// removed. Any trap it does not own is forwarded to the handler that was
+  /// installed before it.
+  Breakpoint,
+}
+
+impl Default for ThreadSafety {
+  fn default() -> Self {
+    ThreadSafety::Unsynchronized
+  }
+}
+
+impl ThreadSafety {
+  /// Converts the mode into a representation for atomic storage.
+  pub(crate) fn to_u8(self) -> u8 {
+    match self {
+      ThreadSafety::Unsynchronized => 0,
+      ThreadSafety::SuspendThreads => 1,
+      ThreadSafety::Breakpoint => 2,
+    }
+  }
+
+  /// Converts the atomic representation back into a mode.
+  pub(crate) fn from_u8(value: u8) -> Self {
+    match value {
+      1 => ThreadSafety::SuspendThreads,
+      2 => ThreadSafety::Breakpoint,
+      _ => ThreadSafety::Unsynchronized,
+    }
+  }
+}
@@ -0,0 +1,26 @@
+use crate::error::{Error, Result};
+use std::io;
+
+/// Stopping other threads is not available on this platform.
+pub unsafe fn suspend() -> Result<Suspended> {
+  Err(Error::ThreadSafety(io::ErrorKind::Unsupported.into()))
+}
+
+/// A placeholder for platforms without thread suspension.
+pub struct Suspended(());
+
+impl Suspended {
+  /// Relocates the instruction pointers of all suspended threads.
+  pub unsafe fn relocate<F: Fn(usize) -> Option<usize>>(&self, _relocate: F) {}
+}
+
+/// A placeholder for platforms without breakpoint redirection.
+pub struct Redirect(());
+
+/// Redirecting breakpoints is not available on this platform.
+pub unsafe fn redirect(_address: *const (), _destination: *const ()) -> Result<Redirect> {
+  Err(Error::ThreadSafety(io::ErrorKind::Unsupported.into()))
+}
+
+/// Relies on the cache coherency of the processor.
+pub fn sync_cores() {}
@@ -1,10 +1,103 @@
-use crate::error::Result;
+use core::fmt;
+#[cfg(feature = "std")]
+use {
+  crate::error::{RegionError, RegionOperation, Result},
+  std::ops::Range,
+};
 
 /// Returns true if an address is executable.
+#[cfg(feature = "std")]
 pub fn is_executable_address(address: *const ()) -> Result<bool> {
   Ok(
-    region::query(address as *const _)?
+    region::query(address as *const _)
+      .map_err(RegionError::map(RegionOperation::Query, address))?
       .protection()
       .contains(region::Protection::EXECUTE),
   )
 }
+
+/// Returns true if an address is readable.
+#[cfg(feature = "std")]
+pub fn is_readable_address(address: *const ()) -> Result<bool> {
+  Ok(
+    region::query(address as *const _)
+      .map_err(RegionError::map(RegionOperation::Query, address))?
+      .protection()
+      .contains(region::Protection::READ),
+  )
+}
+
+/// Returns true if an address belongs to a loaded module (i.e it's backed by
+/// a file), as opposed to dynamically allocated memory.
+///
+/// This can only be determined on Linux, and is assumed otherwise.
+#[cfg(feature = "std")]
+pub fn is_module_address(address: *const ()) -> bool {
+  let maps = match mappings() {
+    Some(maps) => maps,
+    None => return true,
+  };
+
+  // Pseudo paths such as '[heap]' are not modules, except for the vDSO
+  let address = address as usize;
+  maps.iter().any(|(range, path)| {
+    range.contains(&address)
+      && path.as_deref().map_or(false, |path| !path.starts_with('[') || path == "[vdso]" || path == "[vsyscall]")
+  })
+}
+
+/// Returns the address range of the loaded module containing an address.
+///
+/// This can only be determined on Linux.
+#[cfg(feature = "std")]
+pub fn module_range(address: *const ()) -> Option<Range<usize>> {
+  let maps = mappings()?;
+  let address = address as usize;
+  let (_, module) = maps
+    .iter()
+    .find(|(range, path)| range.contains(&address) && path.is_some())?;
+
+  // A module is mapped using several segments, each with its own protection
+  maps
+    .iter()
+    .filter(|(_, path)| path == module)
+    .map(|(range, _)| range.clone())
+    .reduce(|all, range| all.start.min(range.start)..all.end.max(range.end))
+}
+
+/// Returns the memory mappings of the process, and the path of each, if any.
+#[cfg(feature = "std")]
+pub fn mappings() -> Option<Vec<(Range<usize>, Option<String>)>> {
+  let maps = match std::fs::read_to_string("/proc/self/maps") {
+    Ok(maps) if cfg!(target_os = "linux") => maps,
+    _ => return None,
+  };
+
+  // Each line is formatted as 'start-end perms offset dev inode [path]'
+  Some(
+    maps
+      .lines()
+      .filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let (start, end) = fields.next()?.split_once('-')?;
+        let range = usize::from_str_radix(start, 16).ok()?..usize::from_str_radix(end, 16).ok()?;
+        Some((range, fields.nth(4).map(str::to_string)))
+      })
+      .collect(),
+  )
+}
+
+/// Formats bytes as space separated hexadecimal pairs.
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl<'a> fmt::Debug for Hex<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (index, byte) in self.0.iter().enumerate() {
+      if index > 0 {
+        write!(f, " ")?;
+      }
+      write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,148 @@
+//! Drives bare detours using a backend over an mmap'd arena, as a kernel or
+//! bare-metal harness would. This is also built with the `alloc` feature.
+#![cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+use retour::bare::{Backend, Detour, DETOUR_RANGE};
+use retour::{Error, Result};
+use std::cell::Cell;
+use std::{mem, ptr};
+
+/// A backend allocating code from a fixed arena, close to the targets.
+struct ArenaBackend {
+  arena: *mut u8,
+  size: usize,
+  used: Cell<usize>,
+  released: Cell<usize>,
+  flushes: Cell<usize>,
+}
+
+impl ArenaBackend {
+  /// Maps an arena of `size` bytes within reach of `origin`.
+  unsafe fn new(origin: *const (), size: usize) -> Self {
+    let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+    let base = (origin as usize & !(page - 1)) + 0x10_0000;
+
+    let arena = (0..64)
+      .map(|index| base + index * 0x100_0000)
+      .map(|address| {
+        libc::mmap(
+          address as *mut _,
+          size,
+          libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+          libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+          -1,
+          0,
+        )
+      })
+      .find(|&arena| arena != libc::MAP_FAILED)
+      .expect("mapping an arena");
+
+    ArenaBackend {
+      arena: arena.cast(),
+      size,
+      used: Cell::new(0),
+      released: Cell::new(0),
+      flushes: Cell::new(0),
+    }
+  }
+}
+
+impl Drop for ArenaBackend {
+  fn drop(&mut self) {
+    unsafe { libc::munmap(self.arena.cast(), self.size) };
+  }
+}
+
+unsafe impl Backend for ArenaBackend {
+  fn is_executable(&self, address: *const ()) -> Result<bool> {
+    Ok(!address.is_null())
+  }
+
+  fn allocate_near(&self, origin: *const (), size: usize) -> Result<*mut u8> {
+    let used = self.used.get();
+    if used + size > self.size {
+      return Err(Error::OutOfMemory);
+    }
+
+    let address = unsafe { self.arena.add(used) };
+    assert!(((address as usize).wrapping_sub(origin as usize) as isize).unsigned_abs() < DETOUR_RANGE);
+    self.used.set(used + size);
+    Ok(address)
+  }
+
+  unsafe fn release(&self, _memory: *mut u8, size: usize) {
+    self.released.set(self.released.get() + size);
+  }
+
+  unsafe fn protect(&self, code: *const u8, size: usize, writable: bool) -> Result<()> {
+    let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+    let start = code as usize & !(page - 1);
+    let protection = if writable {
+      libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC
+    } else {
+      libc::PROT_READ | libc::PROT_EXEC
+    };
+
+    if libc::mprotect(start as *mut _, code as usize + size - start, protection) != 0 {
+      return Err(Error::NotExecutable);
+    }
+    Ok(())
+  }
+
+  fn flush(&self, _code: *const u8, _size: usize) {
+    self.flushes.set(self.flushes.get() + 1);
+  }
+}
+
+#[inline(never)]
+extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x) + y }
+}
+
+extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+#[test]
+fn detour() -> Result<()> {
+  unsafe {
+    let backend = ArenaBackend::new(add as *const (), 0x1000);
+    let mut hook = Detour::new(&backend, add as *const (), sub as *const ())?;
+    let used = backend.used.get();
+    assert!(used > 0);
+
+    assert_eq!(add(10, 5), 15);
+    hook.enable()?;
+    assert!(hook.is_enabled());
+    assert_eq!(add(10, 5), 5);
+
+    let original: extern "C" fn(i32, i32) -> i32 = mem::transmute(hook.trampoline());
+    assert_eq!(original(10, 5), 15);
+
+    hook.disable()?;
+    assert_eq!(add(10, 5), 15);
+    assert!(backend.flushes.get() >= 2);
+
+    // The target is restored and the code released once dropped
+    hook.enable()?;
+    mem::drop(hook);
+    assert_eq!(add(10, 5), 15);
+    assert_eq!(backend.released.get(), used);
+  }
+  Ok(())
+}
+
+#[test]
+fn out_of_memory() {
+  unsafe {
+    // The trampoline fits, but not its relay
+    let backend = ArenaBackend::new(add as *const (), 0x1000);
+    backend.used.set(backend.size - 0x20);
+    let error = Detour::new(&backend, add as *const (), sub as *const ()).unwrap_err();
+    assert!(matches!(error, Error::OutOfMemory));
+    assert!(backend.released.get() > 0);
+    assert_eq!(backend.released.get(), backend.used.get() - (backend.size - 0x20));
+
+    let error = Detour::new(&backend, add as *const (), add as *const ()).unwrap_err();
+    assert!(matches!(error, Error::SameAddress));
+  }
+}
@@ -0,0 +1,57 @@
+//! Builds the library as a static library, and hooks a local function
+//! through the C interface from a C program (see `tests/capi/hook.c`).
+#![cfg(all(
+  feature = "capi",
+  target_os = "linux",
+  any(target_arch = "x86", target_arch = "x86_64")
+))]
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn hook_from_c() {
+  let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("capi");
+
+  // A separate target directory avoids contending for the outer build's lock
+  let output = Command::new(env!("CARGO"))
+    .current_dir(root)
+    .args(["rustc", "--lib", "--features", "capi", "--crate-type", "staticlib"])
+    .arg("--target-dir")
+    .arg(&target_dir)
+    .args(["--", "--print", "native-static-libs"])
+    .output()
+    .expect("running cargo");
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(output.status.success(), "building the static library:\n{}", stderr);
+
+  let native_libs = stderr
+    .lines()
+    .find_map(|line| line.split("native-static-libs:").nth(1))
+    .expect("native static libraries")
+    .split_whitespace()
+    .map(str::to_owned)
+    .collect::<Vec<_>>();
+
+  let program = target_dir.join("hook");
+  let status = Command::new("cc")
+    .arg("-O0")
+    .arg("-I")
+    .arg(root.join("include"))
+    .arg(root.join("tests/capi/hook.c"))
+    .arg(target_dir.join("debug/libretour.a"))
+    .args(&native_libs)
+    .arg("-o")
+    .arg(&program)
+    .status()
+    .expect("running cc");
+  assert!(status.success());
+
+  let output = Command::new(&program).output().expect("running the program");
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
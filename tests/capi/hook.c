@@ -0,0 +1,98 @@
+// Hooks a local function through the C interface, see `tests/capi.rs`.
+#include <retour.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define CHECK(expression)                                                  \
+  do {                                                                     \
+    if (!(expression)) {                                                   \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,     \
+              #expression);                                                \
+      exit(1);                                                             \
+    }                                                                      \
+  } while (0)
+
+typedef int (*binary_fn)(int, int);
+
+__attribute__((noinline)) int add(int x, int y) {
+  volatile int result = x + y;
+  return result;
+}
+
+__attribute__((noinline)) int mul(int x, int y) {
+  volatile int result = x * y;
+  return result;
+}
+
+static int sub(int x, int y) { return x - y; }
+
+// Calls through a volatile pointer, so calls are not inlined nor folded
+static int call(binary_fn volatile function, int x, int y) {
+  return function(x, y);
+}
+
+static void detour(void) {
+  retour_detour *hook = NULL;
+  CHECK(retour_create((const void *)add, (const void *)sub, &hook) == RETOUR_STATUS_OK);
+  CHECK(!retour_is_enabled(hook));
+
+  CHECK(call(add, 10, 5) == 15);
+  CHECK(retour_enable(hook) == RETOUR_STATUS_OK);
+  CHECK(retour_is_enabled(hook));
+  CHECK(call(add, 10, 5) == 5);
+
+  binary_fn original = (binary_fn)retour_trampoline(hook);
+  CHECK(original != NULL);
+  CHECK(call(original, 10, 5) == 15);
+
+  CHECK(retour_disable(hook) == RETOUR_STATUS_OK);
+  CHECK(call(add, 10, 5) == 15);
+  retour_destroy(hook);
+}
+
+static void transaction(void) {
+  retour_detour *hook = NULL;
+  CHECK(retour_create((const void *)add, (const void *)sub, &hook) == RETOUR_STATUS_OK);
+
+  retour_transaction *tx = retour_transaction_new();
+  CHECK(retour_transaction_enable(tx, hook) == RETOUR_STATUS_OK);
+  CHECK(retour_transaction_create(tx, (const void *)mul, (const void *)sub) == RETOUR_STATUS_OK);
+
+  retour_detour *created[1] = {NULL};
+  CHECK(retour_transaction_commit(tx, created) == RETOUR_STATUS_OK);
+  CHECK(created[0] != NULL && retour_is_enabled(created[0]));
+  CHECK(call(add, 10, 5) == 5);
+  CHECK(call(mul, 10, 5) == 5);
+
+  // Aborted transactions are not applied
+  tx = retour_transaction_new();
+  CHECK(retour_transaction_disable(tx, hook) == RETOUR_STATUS_OK);
+  retour_transaction_abort(tx);
+  CHECK(call(add, 10, 5) == 5);
+
+  retour_destroy(created[0]);
+  retour_destroy(hook);
+  CHECK(call(add, 10, 5) == 15);
+  CHECK(call(mul, 10, 5) == 50);
+}
+
+static void errors(void) {
+  retour_detour *hook = NULL;
+  retour_status status = retour_create((const void *)add, (const void *)add, &hook);
+  CHECK(status == RETOUR_STATUS_SAME_ADDRESS);
+  CHECK(hook == NULL);
+  CHECK(retour_status_message(status) != NULL);
+
+  CHECK(retour_create(NULL, (const void *)sub, &hook) == RETOUR_STATUS_NULL_POINTER);
+  CHECK(retour_enable(NULL) == RETOUR_STATUS_NULL_POINTER);
+  CHECK(retour_trampoline(NULL) == NULL);
+  CHECK(retour_transaction_commit(NULL, NULL) == RETOUR_STATUS_NULL_POINTER);
+}
+
+int main(void) {
+  detour();
+  transaction();
+  errors();
+  puts("ok");
+  return 0;
+}
@@ -0,0 +1,76 @@
+//! The crash handler is process-wide, so it is tested in its own process.
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use retour::{crash, RawDetour, Result};
+use std::arch::global_asm;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::FromRawFd;
+
+global_asm!(r#"
+    .p2align 4, 0xcc
+    .global crash_load
+    crash_load:
+      mov eax, dword ptr [0x10]
+      ret
+    .p2align 4, 0xcc
+  "#);
+
+unsafe extern "C" {
+  fn crash_load() -> i32;
+}
+
+extern "C" fn ret10() -> i32 {
+  10
+}
+
+/// Forks, returning the signal which terminated the child and its stderr.
+unsafe fn fork<F: FnOnce()>(child: F) -> (Option<i32>, String) {
+  let mut pipe = [0; 2];
+  assert_eq!(libc::pipe(pipe.as_mut_ptr()), 0);
+
+  match libc::fork() {
+    -1 => panic!("fork failed"),
+    0 => {
+      // A deadlocked child is terminated
+      libc::alarm(5);
+      libc::dup2(pipe[1], 2);
+      child();
+      libc::_exit(0)
+    },
+    pid => {
+      libc::close(pipe[1]);
+      let mut stderr = String::new();
+      File::from_raw_fd(pipe[0]).read_to_string(&mut stderr).unwrap();
+
+      let mut status = 0;
+      assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+      let signal = Some(libc::WTERMSIG(status)).filter(|_| libc::WIFSIGNALED(status));
+      (signal, stderr)
+    },
+  }
+}
+
+#[test]
+fn attribute_fault() -> Result<()> {
+  unsafe {
+    let hook = RawDetour::new(crash_load as *const (), ret10 as *const ())?;
+    hook.set_name("crash_load");
+
+    let trampoline = hook.trampoline() as *const ();
+    assert!(crash::attribute(trampoline).unwrap().contains("detour 'crash_load'"));
+    assert!(crash::attribute(crash_load as *const ()).unwrap().contains("patch area"));
+    assert_eq!(crash::attribute(std::ptr::null()), None);
+
+    // The load faults within the trampoline, once relocated
+    let (signal, stderr) = fork(|| {
+      crash::install().unwrap();
+      let call: extern "C" fn() -> i32 = std::mem::transmute(trampoline);
+      call();
+    });
+
+    assert_eq!(signal, Some(libc::SIGSEGV));
+    assert!(stderr.contains("within the trampoline of detour 'crash_load'"), "{}", stderr);
+    assert!(stderr.contains("relocated from target+0x0 [8b 04 25 10 00 00 00]"), "{}", stderr);
+  }
+  Ok(())
+}
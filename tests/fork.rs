@@ -0,0 +1,90 @@
+//! The fork policy is process-wide, so it is tested in its own process.
+#![cfg(unix)]
+use retour::{set_fork_policy, ForkPolicy, GenericDetour, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+#[inline(never)]
+extern "C" fn add5(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) + 5 }
+}
+
+#[inline(never)]
+extern "C" fn sub5(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) - 5 }
+}
+
+extern "C" fn ret10(_: i32) -> i32 {
+  10
+}
+
+type FnAdd = extern "C" fn(i32) -> i32;
+
+/// Forks, returning whether the child exited successfully.
+unsafe fn fork<F: FnOnce() -> bool>(child: F) -> bool {
+  match libc::fork() {
+    -1 => panic!("fork failed"),
+    0 => {
+      // A deadlocked child is terminated
+      libc::alarm(5);
+      libc::_exit(if child() { 0 } else { 1 })
+    },
+    pid => {
+      let mut status = 0;
+      assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+      libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+    },
+  }
+}
+
+#[test]
+fn fork_policy() -> Result<()> {
+  unsafe {
+    let add = GenericDetour::<FnAdd>::new(add5, ret10)?;
+    let sub = GenericDetour::<FnAdd>::new(sub5, ret10)?;
+    add.set_group("add");
+    add.enable()?;
+
+    // Another thread is continuously holding the global locks
+    let stop = Arc::new(AtomicBool::new(false));
+    let toggler = {
+      let stop = stop.clone();
+      let sub = Arc::new(sub);
+      thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+          sub.enable().unwrap();
+          sub.disable().unwrap();
+        }
+      })
+    };
+
+    for _ in 0..20 {
+      assert!(fork(|| {
+        // The locks are usable, and the detours are kept by default
+        let hook = GenericDetour::<FnAdd>::new(sub5, ret10);
+        hook.is_ok() && add5(1) == 10
+      }));
+    }
+
+    // The policy is applied once the child first acquires the global lock
+    set_fork_policy(ForkPolicy::DisableGroups(vec!["add".to_string()]));
+    assert!(fork(|| {
+      let inherited = add5(1) == 10;
+      let hook = GenericDetour::<FnAdd>::new(sub5, ret10);
+      inherited && hook.is_ok() && add5(1) == 6
+    }));
+    assert_eq!(add5(1), 10);
+
+    set_fork_policy(ForkPolicy::DisableAll);
+    assert!(fork(|| {
+      let hook = GenericDetour::<FnAdd>::new(sub5, ret10);
+      hook.is_ok() && add5(1) == 6 && !add.is_enabled()
+    }));
+    assert!(add.is_enabled());
+
+    stop.store(true, Ordering::SeqCst);
+    toggler.join().unwrap();
+  }
+  Ok(())
+}
@@ -78,6 +78,1081 @@ mod generic {
     }
     Ok(())
   }
+
+  #[test]
+  fn set_detour() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    extern "C" fn mul_detour(x: i32, y: i32) -> i32 {
+      x * y
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    unsafe { hook.enable()? };
+    assert_eq!(add(10, 5), 5);
+
+    let patched = hook_bytes(add as *const ());
+    hook.set_detour(mul_detour)?;
+    assert_eq!(add(10, 5), 50);
+    assert_eq!(hook.call(10, 5), 15);
+    assert_eq!(hook_bytes(add as *const ()), patched);
+
+    unsafe { hook.disable()? };
+    assert_eq!(add(10, 5), 15);
+    Ok(())
+  }
+
+  fn hook_bytes(target: *const ()) -> [u8; 5] {
+    unsafe { std::ptr::read(target as *const [u8; 5]) }
+  }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod resolve {
+  use super::*;
+  use matches::assert_matches;
+  use retour::{resolve, Error, RawDetour};
+  use std::arch::global_asm;
+
+  global_asm!(r#"
+      .global resolve_stub
+      resolve_stub:
+        jmp resolve_indirect
+      .global resolve_indirect
+      resolve_indirect:
+        jmp qword ptr [rip + resolve_slot]
+      .global resolve_ret7
+      resolve_ret7:
+        mov eax, 7
+        ret
+      .global resolve_foreign
+      resolve_foreign:
+        jmp qword ptr [rip + resolve_foreign_slot]
+      .data
+      .balign 8
+      resolve_slot:
+        .quad resolve_ret7
+      .global resolve_foreign_slot
+      resolve_foreign_slot:
+        .quad resolve_ret7
+      .text
+    "#);
+
+  type FnRet = extern "C" fn() -> i32;
+
+  unsafe extern "C" {
+    safe fn resolve_stub() -> i32;
+    safe fn resolve_indirect() -> i32;
+    safe fn resolve_ret7() -> i32;
+    safe fn resolve_foreign() -> i32;
+    static mut resolve_foreign_slot: usize;
+  }
+
+  extern "C" fn ret10() -> i32 {
+    10
+  }
+
+  #[test]
+  fn follows_stubs() -> Result<()> {
+    let resolution = unsafe { resolve(resolve_stub as *const (), 8)? };
+    assert_eq!(resolution.address, resolve_ret7 as *const ());
+    assert_eq!(resolution.jumps, vec![
+      resolve_stub as *const (),
+      resolve_indirect as *const ()
+    ]);
+    assert!(!resolution.is_hooked());
+
+    // The depth limits how many jumps are followed
+    let resolution = unsafe { resolve(resolve_stub as *const (), 1)? };
+    assert_eq!(resolution.address, resolve_indirect as *const ());
+
+    unsafe {
+      let hook = RawDetour::new_resolved(resolve_stub as *const (), ret10 as *const (), 8)?;
+      hook.enable()?;
+      assert_eq!(resolve_ret7(), 10);
+      assert_eq!(resolve_stub(), 10);
+
+      let original: FnRet = mem::transmute(hook.trampoline());
+      assert_eq!(original(), 7);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn detects_foreign_hook() -> Result<()> {
+    extern "C" fn ret5() -> i32 {
+      5
+    }
+
+    unsafe {
+      // Dynamically allocated code is treated as a foreign detour
+      let other = RawDetour::new(ret5 as *const (), ret10 as *const ())?;
+      resolve_foreign_slot = other.trampoline() as *const () as usize;
+
+      let resolution = resolve(resolve_foreign as *const (), 8)?;
+      assert!(resolution.is_hooked());
+      assert_eq!(resolution.address, resolve_foreign as *const ());
+      assert_eq!(resolution.foreign_hook, Some(other.trampoline() as *const ()));
+
+      let error = RawDetour::new_resolved(resolve_foreign as *const (), ret10 as *const (), 8)
+        .unwrap_err();
+      assert_matches!(error, Error::AlreadyHooked);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod plan {
+  use super::*;
+  use matches::assert_matches;
+  use retour::{Error, HookPlan, PatchKind, RawDetour, Relocation};
+  use std::arch::global_asm;
+
+  global_asm!(r#"
+      .global plan_relative
+      plan_relative:
+        lea rax, [rip + plan_value]
+        mov eax, [rax]
+        ret
+      .global plan_loop
+      plan_loop:
+        loop 1f
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+      1:
+        ret
+      .data
+      .balign 8
+      plan_value:
+        .quad 7
+      .text
+    "#);
+
+  unsafe extern "C" {
+    safe fn plan_relative() -> i32;
+    safe fn plan_loop();
+  }
+
+  extern "C" fn ret10() -> i32 {
+    10
+  }
+
+  #[test]
+  fn relocations() -> Result<()> {
+    let plan = unsafe { HookPlan::analyze(plan_relative as *const ()) };
+    assert!(plan.is_hookable());
+    assert!(!plan.chained);
+    assert_eq!(plan.patch, Some(PatchKind::Jump));
+    assert_eq!(plan.instructions.len(), 1);
+    assert_eq!(plan.instructions[0].relocation, Relocation::Displacement);
+    assert_eq!(plan.instructions[0].bytes.len(), 7);
+
+    unsafe {
+      let hook = RawDetour::new(plan_relative as *const (), ret10 as *const ())?;
+      assert!(HookPlan::analyze(plan_relative as *const ()).chained);
+      assert!(format!("{:?}", hook).contains("original: 48 8d 05"));
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn unsupported() {
+    let plan = unsafe { HookPlan::analyze(plan_loop as *const ()) };
+    assert!(!plan.is_hookable());
+    assert_matches!(plan.error, Some(Error::UnsupportedInstruction(ref error)) if error.mnemonic == "loop" && error.offset == 0);
+    assert_eq!(plan.patch, None);
+    assert_eq!(plan.instructions[0].relocation, Relocation::Unsupported);
+    assert!(plan.to_string().contains("loop"));
+  }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod builder {
+  use super::*;
+  use matches::assert_matches;
+  use retour::{registry, DetourBuilder, Error, PatchKind, PatchStrategy, RelayMode, ThreadSafety};
+  use std::arch::global_asm;
+
+  global_asm!(r#"
+      .p2align 4, 0xcc
+      .global builder_short
+      builder_short:
+        xor eax, eax
+        ret
+      .p2align 4, 0xcc
+    "#);
+
+  unsafe extern "C" {
+    safe fn builder_short() -> i32;
+  }
+
+  #[inline(never)]
+  extern "C" fn add(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+  }
+
+  #[test]
+  fn raw() -> Result<()> {
+    unsafe {
+      let hook = DetourBuilder::new(add as *const (), sub_detour as *const ())
+        .relay(RelayMode::Dispatch)
+        .thread_safety(ThreadSafety::SuspendThreads)
+        .name("builder_add")
+        .group("builder")
+        .build()?;
+
+      assert_eq!(hook.thread_safety(), ThreadSafety::SuspendThreads);
+      assert_eq!(registry::find("builder_add").unwrap().group.as_deref(), Some("builder"));
+
+      // The detour is relayed through a dispatch stub from the start
+      hook.enable_stats(false)?;
+      hook.enable()?;
+      assert_eq!(add(10, 5), 5);
+      assert_eq!(hook.stats().calls, 1);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn generic() -> Result<()> {
+    extern "C" fn ret10() -> i32 {
+      10
+    }
+
+    let builder = DetourBuilder::generic(builder_short as extern "C" fn() -> i32, ret10 as extern "C" fn() -> i32);
+    let strict = builder.clone().allow_padding(false).allow_hot_patch(false);
+
+    unsafe {
+      assert_eq!(builder.plan().patch, Some(PatchKind::Padding));
+      assert_matches!(strict.plan().error, Some(Error::NoPatchArea(_)));
+      assert_matches!(
+        strict.build(),
+        Err(Error::NoPatchArea(error)) if error.available == 3 && error.needed == 5 && error.tried == [PatchKind::Jump]
+      );
+
+      let hook = builder.patch(PatchStrategy::Permanent).build()?;
+      assert_eq!(builder_short(), 0);
+      hook.enable()?;
+      assert_eq!(builder_short(), 10);
+      assert_eq!(hook.call(), 0);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn arena() -> Result<()> {
+    use retour::Arena;
+
+    let arena = Arena::reserve(add as *const (), 0x1000)?;
+    unsafe {
+      let hook = DetourBuilder::new(add as *const (), sub_detour as *const ())
+        .arena(&arena)
+        .relay(RelayMode::Dispatch)
+        .build()?;
+
+      hook.enable()?;
+      assert_eq!(add(10, 5), 5);
+      hook.disable()?;
+    }
+
+    // Nothing else is allocated once the arena is exhausted
+    let mut hooks = Vec::new();
+    let exhausted = (0..0x1000).find_map(|_| {
+      match unsafe { DetourBuilder::new(add as *const (), sub_detour as *const ()).arena(&arena).build() } {
+        Ok(hook) => {
+          hooks.push(hook);
+          None
+        },
+        Err(error) => Some(error),
+      }
+    });
+    assert_matches!(exhausted, Some(Error::OutOfMemory));
+    Ok(())
+  }
+}
+
+mod integrity {
+  use super::*;
+  use matches::assert_matches;
+  use retour::{Error, GenericDetour, Integrity};
+
+  #[inline(never)]
+  extern "C" fn add5(val: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&val) + 5 }
+  }
+
+  extern "C" fn add10(val: i32) -> i32 {
+    val + 10
+  }
+
+  type FnAdd = extern "C" fn(i32) -> i32;
+
+  /// Overwrites the start of a function, as another library would.
+  unsafe fn overwrite(target: *const (), bytes: &[u8]) {
+    region::protect(target, bytes.len(), region::Protection::READ_WRITE_EXECUTE).unwrap();
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), target as *mut u8, bytes.len());
+  }
+
+  #[test]
+  fn verify() -> Result<()> {
+    unsafe {
+      let hook = GenericDetour::<FnAdd>::new(add5, add10)?;
+      let target = add5 as FnAdd as *const ();
+      let original = std::slice::from_raw_parts(target as *const u8, 5).to_vec();
+      assert_eq!(hook.verify(), Integrity::Intact);
+
+      hook.enable()?;
+      assert_eq!(hook.verify(), Integrity::Intact);
+      let patch = std::slice::from_raw_parts(target as *const u8, 5).to_vec();
+
+      // Restoring the original code is detected, and accepted when disabling
+      overwrite(target, &original);
+      assert_eq!(hook.verify(), Integrity::Restored);
+      hook.disable()?;
+      assert_eq!(hook.verify(), Integrity::Intact);
+
+      // Unrecognized code is never overwritten
+      hook.enable()?;
+      overwrite(target, &[0xE9, 0, 0, 0, 0]);
+      assert_matches!(hook.verify(), Integrity::Overwritten(_));
+      assert_matches!(hook.disable(), Err(Error::CodeModified));
+      assert!(hook.is_enabled());
+
+      overwrite(target, &patch);
+      hook.disable()?;
+      assert_eq!(add5(5), 10);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn drop_modified() -> Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[inline(never)]
+    extern "C" fn add1(val: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&val) + 1 }
+    }
+
+    // The entry of the chain, which another library jumps to
+    static ENTRY: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn foreign(val: i32) -> i32 {
+      let entry: FnAdd = unsafe { mem::transmute(ENTRY.load(Ordering::SeqCst)) };
+      entry(val) * 2
+    }
+
+    unsafe {
+      let hook = GenericDetour::<FnAdd>::new(add1, add10)?;
+      hook.enable()?;
+
+      // Another library relocates the patch, and redirects the target
+      let target = add1 as FnAdd as *const ();
+      let patch = std::slice::from_raw_parts(target as *const u8, 5);
+      assert_eq!(patch[0], 0xE9);
+      let offset = i32::from_le_bytes([patch[1], patch[2], patch[3], patch[4]]);
+      ENTRY.store((target as usize + 5).wrapping_add(offset as isize as usize), Ordering::SeqCst);
+
+      let jump = (foreign as FnAdd as usize).wrapping_sub(target as usize + 5) as i32;
+      let mut bytes = vec![0xE9];
+      bytes.extend_from_slice(&jump.to_le_bytes());
+      overwrite(target, &bytes);
+      assert_eq!(add1(5), 30);
+
+      // The target cannot be restored, so the chain's code is kept, and
+      // reused by later detours of the target
+      drop(hook);
+      assert!(retour::wait_for_quiescence(Duration::from_secs(5))?);
+      let _hook = GenericDetour::<FnAdd>::new(add1, add10)?;
+      assert_eq!(add1(5), 12);
+    }
+    Ok(())
+  }
+}
+
+mod dispatch {
+  use super::*;
+  use retour::GenericDetour;
+
+  #[test]
+  fn reentrancy_guard() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    // Without a guard, this would recurse forever
+    extern "C" fn double_detour(x: i32, y: i32) -> i32 {
+      add(x, y) * 2
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, double_detour)? };
+    hook.set_reentrancy_guard(true)?;
+    unsafe { hook.enable()? };
+
+    assert_eq!(add(10, 5), 30);
+    assert_eq!(hook.call(10, 5), 15);
+    Ok(())
+  }
+
+  #[test]
+  fn reentrancy_guard_by_default() -> Result<()> {
+    use retour::{DetourBuilder, RelayMode};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    extern "C" fn nested_detour(x: i32, y: i32) -> i32 {
+      if DEPTH.fetch_add(1, Ordering::SeqCst) < 2 {
+        add(x, y) * 2
+      } else {
+        x - y
+      }
+    }
+
+    unsafe {
+      let hook = DetourBuilder::new(add as *const (), nested_detour as *const ())
+        .relay(RelayMode::Dispatch)
+        .build()?;
+      hook.enable()?;
+
+      // A dispatched detour is guarded unless opted out
+      assert_eq!(add(10, 5), 30);
+      DEPTH.store(0, Ordering::SeqCst);
+      hook.set_reentrancy_guard(false)?;
+      assert_eq!(add(10, 5), 20);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn bypass_on_current_thread() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    unsafe { hook.enable()? };
+
+    assert_eq!(add(10, 5), 5);
+    assert_eq!(hook.bypass_on_current_thread(|| add(10, 5))?, 15);
+    assert_eq!(add(10, 5), 5);
+    Ok(())
+  }
+
+  #[test]
+  fn enable_for_current_thread() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    unsafe { hook.enable_for_current_thread()? };
+
+    assert_eq!(add(10, 5), 5);
+    assert_eq!(std::thread::spawn(|| add(10, 5)).join().unwrap(), 15);
+
+    hook.disable_for_current_thread()?;
+    assert_eq!(add(10, 5), 15);
+
+    unsafe { hook.enable()? };
+    assert_eq!(add(10, 5), 15);
+    assert_eq!(std::thread::spawn(|| add(10, 5)).join().unwrap(), 5);
+    Ok(())
+  }
+
+  #[test]
+  fn stats() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    assert_eq!(hook.stats(), retour::Stats::default());
+
+    hook.enable_stats(true)?;
+    unsafe { hook.enable()? };
+    for _ in 0..3 {
+      assert_eq!(add(10, 5), 5);
+    }
+    assert_eq!(hook.bypass_on_current_thread(|| add(10, 5))?, 15);
+    assert_eq!(hook.call(10, 5), 15);
+
+    let stats = hook.stats();
+    assert_eq!((stats.calls, stats.bypassed), (3, 1));
+    assert!(stats.detour_time.is_some() && stats.original_time.is_some());
+
+    hook.reset_stats();
+    assert_eq!(hook.stats().calls, 0);
+    Ok(())
+  }
+
+  #[test]
+  fn preserves_arguments() -> Result<()> {
+    type FnMixed = extern "C" fn(i64, f64, i64, f64, i64, f64, i64, f64, i64, f64, i64, f64) -> f64;
+
+    #[inline(never)]
+    extern "C" fn mixed(
+      a: i64,
+      b: f64,
+      c: i64,
+      d: f64,
+      e: i64,
+      f: f64,
+      g: i64,
+      h: f64,
+      i: i64,
+      j: f64,
+      k: i64,
+      l: f64,
+    ) -> f64 {
+      let integers = unsafe { std::ptr::read_volatile(&a) } + c * 2 + e * 3 + g * 4 + i * 5 + k * 6;
+      integers as f64 + b + d * 2.0 + f * 3.0 + h * 4.0 + j * 5.0 + l * 6.0
+    }
+
+    extern "C" fn mixed_detour(
+      a: i64,
+      b: f64,
+      c: i64,
+      d: f64,
+      e: i64,
+      f: f64,
+      g: i64,
+      h: f64,
+      i: i64,
+      j: f64,
+      k: i64,
+      l: f64,
+    ) -> f64 {
+      -mixed(a, b, c, d, e, f, g, h, i, j, k, l)
+    }
+
+    let hook = unsafe { GenericDetour::<FnMixed>::new(mixed, mixed_detour)? };
+    hook.set_reentrancy_guard(true)?;
+
+    let expected = mixed(1, 0.5, 2, 1.5, 3, 2.5, 4, 3.5, 5, 4.5, 6, 5.5);
+    unsafe { hook.enable()? };
+    assert_eq!(mixed(1, 0.5, 2, 1.5, 3, 2.5, 4, 3.5, 5, 4.5, 6, 5.5), -expected);
+    Ok(())
+  }
+
+  #[test]
+  fn caller_filter() -> Result<()> {
+    use matches::assert_matches;
+    use retour::{CallerFilter, Error};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLER: AtomicUsize = AtomicUsize::new(0);
+
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    extern "C" fn record_detour(x: i32, y: i32) -> i32 {
+      CALLER.store(retour::caller().map_or(0, |caller| caller as usize), Ordering::SeqCst);
+      x - y
+    }
+
+    #[inline(never)]
+    fn call_a() -> i32 {
+      std::hint::black_box(add as FnAdd)(10, 5)
+    }
+
+    #[inline(never)]
+    fn call_b() -> i32 {
+      std::hint::black_box(add as FnAdd)(10, 5)
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, record_detour)? };
+    hook.set_caller_filter(Some(CallerFilter::Module(add as *const ())))?;
+    unsafe { hook.enable()? };
+
+    // The caller is the return address within the calling function
+    assert_eq!(call_a(), 5);
+    let caller = CALLER.load(Ordering::SeqCst);
+    let start = call_a as *const () as usize;
+    assert!((start..start + 0x100).contains(&caller));
+    assert_eq!(retour::caller(), None);
+
+    // Calls returning elsewhere are passed on by the stub
+    hook.set_caller_filter(Some(CallerFilter::Range(caller..caller + 1)))?;
+    assert_eq!(call_a(), 5);
+    assert_eq!(call_b(), 15);
+
+    hook.set_caller_filter(None)?;
+    assert_eq!(call_b(), 5);
+
+    assert_matches!(
+      hook.set_caller_filter(Some(CallerFilter::Module(std::ptr::null()))),
+      Err(Error::UnknownModule)
+    );
+    Ok(())
+  }
+}
+
+mod quiescence {
+  use super::*;
+  use retour::GenericDetour;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::time::Duration;
+
+  static ENTERED: AtomicBool = AtomicBool::new(false);
+  static RELEASED: AtomicBool = AtomicBool::new(false);
+
+  #[inline(never)]
+  extern "C" fn add(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+  }
+
+  #[inline(never)]
+  extern "C" fn mul(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+  }
+
+  #[inline(never)]
+  extern "C" fn sub(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) - y }
+  }
+
+  extern "C" fn block_detour(x: i32, y: i32) -> i32 {
+    ENTERED.store(true, Ordering::SeqCst);
+    while !RELEASED.load(Ordering::SeqCst) {
+      std::hint::spin_loop();
+    }
+    x * y
+  }
+
+  #[test]
+  fn reclaims_dropped_detours() -> Result<()> {
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    unsafe { hook.enable()? };
+    assert_eq!(add(10, 5), 5);
+    assert_eq!(hook.call(10, 5), 15);
+
+    drop(hook);
+    assert_eq!(add(10, 5), 15);
+    assert!(retour::wait_for_quiescence(Duration::from_secs(5))?);
+    Ok(())
+  }
+
+  #[test]
+  fn retains_code_in_use() -> Result<()> {
+    // Calls through the dispatch stub are accounted for
+    let hook = unsafe { GenericDetour::<FnAdd>::new(sub, block_detour)? };
+    hook.enable_stats(false)?;
+    unsafe { hook.enable()? };
+
+    let caller = std::thread::spawn(|| sub(10, 5));
+    while !ENTERED.load(Ordering::SeqCst) {
+      std::hint::spin_loop();
+    }
+
+    // Code with calls in progress is not reclaimed once other detours are
+    // created and dropped
+    drop(hook);
+    drop(unsafe { GenericDetour::<FnAdd>::new(mul, sub_detour)? });
+    assert!(!retour::wait_for_quiescence(Duration::from_millis(10))?);
+
+    RELEASED.store(true, Ordering::SeqCst);
+    assert_eq!(caller.join().unwrap(), 50);
+    assert_eq!(sub(10, 5), 5);
+    assert!(retour::wait_for_quiescence(Duration::from_secs(5))?);
+    Ok(())
+  }
+}
+
+mod transaction {
+  use super::*;
+  use matches::assert_matches;
+  use retour::{DetourTransaction, Error, GenericDetour};
+
+  #[inline(never)]
+  extern "C" fn add(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+  }
+
+  #[inline(never)]
+  extern "C" fn mul(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+  }
+
+  #[test]
+  fn commit() -> Result<()> {
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+
+    let mut transaction = DetourTransaction::new();
+    transaction
+      .enable(&hook)
+      .create(mul as *const (), sub_detour as *const ());
+    let created = unsafe { transaction.commit()? };
+
+    assert_eq!(created.len(), 1);
+    assert!(hook.is_enabled() && created[0].is_enabled());
+    assert_eq!(add(10, 5), 5);
+    assert_eq!(mul(10, 5), 5);
+
+    let mut transaction = DetourTransaction::new();
+    transaction.disable(&hook).disable(&created[0]);
+    unsafe { transaction.commit()? };
+
+    assert!(!hook.is_enabled() && !created[0].is_enabled());
+    assert_eq!(add(10, 5), 15);
+    assert_eq!(mul(10, 5), 50);
+    Ok(())
+  }
+
+  #[test]
+  fn rollback() -> Result<()> {
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+
+    let mut transaction = DetourTransaction::new();
+    transaction
+      .enable(&hook)
+      .create(mul as *const (), sub_detour as *const ())
+      .create(mul as *const (), mul as *const ());
+
+    let error = unsafe { transaction.commit() }.unwrap_err();
+    assert_matches!(error, Error::SameAddress);
+
+    // Nothing is applied when any operation fails
+    assert!(!hook.is_enabled());
+    assert_eq!(add(10, 5), 15);
+    assert_eq!(mul(10, 5), 50);
+    Ok(())
+  }
+
+  #[test]
+  fn clears_thread_scope() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    unsafe { hook.enable_for_current_thread()? };
+    assert_eq!(std::thread::spawn(|| add(10, 5)).join().unwrap(), 15);
+
+    let mut transaction = DetourTransaction::new();
+    transaction.enable(&hook);
+    unsafe { transaction.commit()? };
+    assert_eq!(std::thread::spawn(|| add(10, 5)).join().unwrap(), 5);
+    Ok(())
+  }
+
+  #[test]
+  #[cfg(target_os = "linux")]
+  fn suspends_threads_once() -> Result<()> {
+    use retour::ThreadSafety;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    #[inline(never)]
+    extern "C" fn mul(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+    }
+
+    let first = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    let second = unsafe { GenericDetour::<FnAdd>::new(mul, sub_detour)? };
+    first.set_thread_safety(ThreadSafety::SuspendThreads);
+    second.set_thread_safety(ThreadSafety::SuspendThreads);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let workers = (0..4)
+      .map(|_| {
+        let running = running.clone();
+        thread::spawn(move || {
+          while running.load(Ordering::Relaxed) {
+            assert!(matches!(add(10, 5), 15 | 5));
+            assert!(matches!(mul(10, 5), 50 | 5));
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+
+    for enabled in (0..100).map(|index| index % 2 == 0) {
+      let mut transaction = DetourTransaction::new();
+      if enabled {
+        transaction.enable(&first).enable(&second);
+      } else {
+        transaction.disable(&first).disable(&second);
+      }
+      unsafe { transaction.commit()? };
+      assert_eq!(first.is_enabled(), enabled);
+      assert_eq!(second.is_enabled(), enabled);
+    }
+
+    running.store(false, Ordering::Relaxed);
+    for worker in workers {
+      worker.join().unwrap();
+    }
+
+    assert_eq!(add(10, 5), 15);
+    assert_eq!(mul(10, 5), 50);
+    Ok(())
+  }
+}
+
+#[cfg(unix)]
+mod signal_safe {
+  use super::*;
+  use retour::RawDetour;
+  use std::ptr;
+  use std::sync::atomic::{AtomicPtr, Ordering};
+
+  static HOOK: AtomicPtr<RawDetour> = AtomicPtr::new(ptr::null_mut());
+
+  #[inline(never)]
+  extern "C" fn add5(val: i32) -> i32 {
+    unsafe { ptr::read_volatile(&val) + 5 }
+  }
+
+  extern "C" fn ret10(_: i32) -> i32 {
+    10
+  }
+
+  type FnAdd = extern "C" fn(i32) -> i32;
+
+  extern "C" fn toggle(_: std::os::raw::c_int) {
+    unsafe {
+      let hook = &*HOOK.load(Ordering::SeqCst);
+      if hook.is_enabled() {
+        hook.disable().unwrap();
+      } else {
+        hook.enable().unwrap();
+      }
+    }
+  }
+
+  #[test]
+  fn toggle_from_signal_handler() -> Result<()> {
+    unsafe {
+      let target = add5 as FnAdd as *const ();
+      let original = std::slice::from_raw_parts(target as *const u8, 5).to_vec();
+
+      let mut hook = Box::new(RawDetour::new_signal_safe(target, ret10 as FnAdd as *const ())?);
+      HOOK.store(&mut *hook, Ordering::SeqCst);
+      libc::signal(libc::SIGUSR1, toggle as extern "C" fn(_) as libc::sighandler_t);
+
+      // The target is patched, but the detour is disabled
+      assert!(!hook.is_enabled());
+      assert_ne!(std::slice::from_raw_parts(target as *const u8, 5), &original[..]);
+      assert_eq!(add5(1), 6);
+
+      libc::raise(libc::SIGUSR1);
+      assert!(hook.is_enabled());
+      assert_eq!(add5(1), 10);
+
+      let trampoline: FnAdd = mem::transmute(hook.trampoline());
+      assert_eq!(trampoline(1), 6);
+
+      libc::raise(libc::SIGUSR1);
+      assert!(!hook.is_enabled());
+      assert_eq!(add5(1), 6);
+
+      HOOK.store(ptr::null_mut(), Ordering::SeqCst);
+      drop(hook);
+      assert_eq!(std::slice::from_raw_parts(target as *const u8, 5), &original[..]);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(target_os = "linux")]
+mod suspend_threads {
+  use super::*;
+  use retour::{GenericDetour, ThreadSafety};
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Arc;
+  use std::thread;
+
+  #[inline(never)]
+  extern "C" fn add(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+  }
+
+  #[test]
+  fn toggle_whilst_called() -> Result<()> {
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    hook.set_thread_safety(ThreadSafety::SuspendThreads);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let workers = (0..4)
+      .map(|_| {
+        let running = running.clone();
+        thread::spawn(move || {
+          while running.load(Ordering::Relaxed) {
+            let result = add(10, 5);
+            assert!(result == 15 || result == 5);
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+
+    for _ in 0..100 {
+      unsafe {
+        hook.enable()?;
+        hook.disable()?;
+      }
+    }
+
+    running.store(false, Ordering::Relaxed);
+    for worker in workers {
+      worker.join().unwrap();
+    }
+
+    assert_eq!(add(10, 5), 15);
+    Ok(())
+  }
+}
+
+mod parallel {
+  use super::*;
+  use retour::GenericDetour;
+  use std::thread;
+
+  #[inline(never)]
+  extern "C" fn add(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+  }
+
+  #[inline(never)]
+  extern "C" fn mul(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+  }
+
+  #[test]
+  fn create_and_toggle() -> Result<()> {
+    // Chains are repeatedly created and released by competing threads
+    let workers = [add as FnAdd, mul, add, mul]
+      .iter()
+      .map(|&target| {
+        thread::spawn(move || -> Result<()> {
+          for _ in 0..50 {
+            let hook = unsafe { GenericDetour::<FnAdd>::new(target, sub_detour)? };
+            unsafe {
+              hook.enable()?;
+              hook.disable()?;
+            }
+          }
+          Ok(())
+        })
+      })
+      .collect::<Vec<_>>();
+
+    for worker in workers {
+      worker.join().unwrap()?;
+    }
+
+    assert_eq!(add(10, 5), 15);
+    assert_eq!(mul(10, 5), 50);
+    Ok(())
+  }
+}
+
+#[cfg(target_os = "linux")]
+mod breakpoint {
+  use super::*;
+  use retour::{GenericDetour, ThreadSafety};
+  use std::arch::global_asm;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Arc;
+  use std::thread;
+
+  // The first patch crosses an 8-byte boundary, the second does not
+  global_asm!(r#"
+      .balign 16
+      .skip 6, 0x90
+      .global unaligned_ret5
+      unaligned_ret5:
+        mov eax, 5
+        ret
+      .balign 16
+      .global aligned_ret6
+      aligned_ret6:
+        mov eax, 6
+        ret
+    "#);
+
+  type FnRet = extern "C" fn() -> i32;
+
+  unsafe extern "C" {
+    safe fn unaligned_ret5() -> i32;
+    safe fn aligned_ret6() -> i32;
+  }
+
+  extern "C" fn ret10() -> i32 {
+    10
+  }
+
+  fn toggle_whilst_called(target: FnRet, result: i32) -> Result<()> {
+    let hook = unsafe { GenericDetour::<FnRet>::new(target, ret10)? };
+    hook.set_thread_safety(ThreadSafety::Breakpoint);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let workers = (0..4)
+      .map(|_| {
+        let running = running.clone();
+        thread::spawn(move || {
+          while running.load(Ordering::Relaxed) {
+            let value = target();
+            assert!(value == result || value == 10);
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+
+    for _ in 0..1000 {
+      unsafe {
+        hook.enable()?;
+        hook.disable()?;
+      }
+    }
+
+    running.store(false, Ordering::Relaxed);
+    for worker in workers {
+      worker.join().unwrap();
+    }
+
+    assert_eq!(target(), result);
+    assert_eq!(hook.call(), result);
+    Ok(())
+  }
+
+  #[test]
+  fn unaligned() -> Result<()> {
+    toggle_whilst_called(unaligned_ret5, 5)
+  }
+
+  #[test]
+  fn aligned() -> Result<()> {
+    toggle_whilst_called(aligned_ret6, 6)
+  }
 }
 
 #[cfg(feature = "static-detour")]
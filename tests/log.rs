@@ -0,0 +1,56 @@
+//! The logger is process-wide, so it is tested in its own process.
+#![cfg(all(feature = "log", not(feature = "tracing"), any(target_arch = "x86", target_arch = "x86_64")))]
+use log::{Log, Metadata, Record};
+use retour::{RawDetour, Result};
+use std::sync::Mutex;
+
+/// A logger recording the message of each event.
+struct Recorder(Mutex<Vec<String>>);
+
+impl Log for Recorder {
+  fn enabled(&self, _: &Metadata) -> bool {
+    true
+  }
+
+  fn log(&self, record: &Record) {
+    if record.target().starts_with("retour") {
+      self.0.lock().unwrap().push(record.args().to_string());
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+#[inline(never)]
+extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&x) + y }
+}
+
+extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+#[test]
+fn decisions() -> Result<()> {
+  let recorder: &'static Recorder = Box::leak(Box::new(Recorder(Mutex::new(Vec::new()))));
+  log::set_logger(recorder).unwrap();
+  log::set_max_level(log::LevelFilter::Debug);
+
+  let _hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let events = recorder.0.lock().unwrap().clone();
+  let find = |message: &str| {
+    events
+      .iter()
+      .find(|event| event.starts_with(message))
+      .unwrap_or_else(|| panic!("missing '{}' in {:#?}", message, events))
+  };
+
+  let target = format!("target={:?}", add as *const ());
+  assert!(find("relocated instruction").contains(&target));
+  assert!(find("relocated instruction").contains("offset=0"));
+  assert!(find("selected patch area").contains("kind=Jump"));
+  assert!(find("allocated relay").contains(&format!("origin={:?}", add as *const ())));
+  find("allocated pool");
+  find("found free region");
+  Ok(())
+}
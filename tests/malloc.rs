@@ -0,0 +1,106 @@
+//! Detouring the allocator is process-wide, so it is tested in its own
+//! process.
+//!
+//! Any allocation, or lock taken, on the call path of an enabled detour would
+//! re-enter the detoured allocator, and recurse or deadlock.
+#![cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+use retour::{registry, DetourTransaction, GenericDetour, RawDetour, Result};
+use std::cell::Cell;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+type FnMalloc = unsafe extern "C" fn(usize) -> *mut c_void;
+
+static HOOK: AtomicPtr<GenericDetour<FnMalloc>> = AtomicPtr::new(ptr::null_mut());
+
+thread_local! {
+  /// The detoured calls of each thread, since other tests allocate as well.
+  static CALLS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe extern "C" fn malloc_detour(size: usize) -> *mut c_void {
+  CALLS.with(|calls| calls.set(calls.get() + 1));
+  (*HOOK.load(Ordering::SeqCst)).call(size)
+}
+
+#[inline(never)]
+extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x) + y }
+}
+
+extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+/// Returns the number of detoured calls made by a single allocation.
+unsafe fn detoured_calls() -> usize {
+  CALLS.with(|calls| calls.set(0));
+  libc::free(libc::malloc(16));
+  CALLS.with(Cell::get)
+}
+
+#[test]
+fn detour_allocator() -> Result<()> {
+  unsafe {
+    let hook = Box::leak(Box::new(GenericDetour::<FnMalloc>::new(libc::malloc, malloc_detour)?));
+    HOOK.store(hook, Ordering::SeqCst);
+    hook.enable()?;
+
+    // Each allocation is detoured exactly once
+    assert_eq!(detoured_calls(), 1);
+
+    // ... including when relayed through a dispatch stub
+    hook.enable_stats(true)?;
+    hook.set_reentrancy_guard(true)?;
+    assert_eq!(detoured_calls(), 1);
+    assert!(hook.stats().calls > 0);
+
+    // Retour allocates whilst holding its locks
+    let other = RawDetour::new(add as *const (), sub as *const ())?;
+    let mut transaction = DetourTransaction::new();
+    transaction.enable(&other);
+    transaction.commit()?;
+    assert_eq!(add(10, 5), 5);
+    assert!(registry::hooks().len() >= 2);
+    registry::disable_all()?;
+    registry::restore_all()?;
+    drop(other);
+
+    hook.disable()?;
+    assert_eq!(detoured_calls(), 0);
+  }
+  Ok(())
+}
+
+#[cfg(feature = "static-detour")]
+mod statik {
+  use super::*;
+  use retour::static_detour;
+
+  static_detour! {
+    static Calloc: unsafe extern "C" fn(usize, usize) -> *mut c_void;
+  }
+
+  #[test]
+  fn detour_allocator() -> Result<()> {
+    thread_local! {
+      static CALLOCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe {
+      Calloc
+        .initialize(libc::calloc, |count, size| {
+          CALLOCS.with(|calls| calls.set(calls.get() + 1));
+          Calloc.call(count, size)
+        })?
+        .enable()?;
+
+      libc::free(libc::calloc(4, 4));
+      Calloc.disable()?;
+    }
+
+    assert_eq!(CALLOCS.with(Cell::get), 1);
+    Ok(())
+  }
+}
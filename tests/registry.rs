@@ -0,0 +1,89 @@
+//! The registry is process-wide, so it is tested in its own process.
+use retour::{registry, GenericDetour, Integrity, Result, Watchdog};
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[inline(never)]
+extern "C" fn add5(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) + 5 }
+}
+
+#[inline(never)]
+extern "C" fn sub5(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) - 5 }
+}
+
+#[inline(never)]
+extern "C" fn mul5(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) * 5 }
+}
+
+extern "C" fn ret10(_: i32) -> i32 {
+  10
+}
+
+type FnAdd = extern "C" fn(i32) -> i32;
+
+#[test]
+fn registry() -> Result<()> {
+  unsafe {
+    let target = add5 as FnAdd as *const ();
+    let original = std::slice::from_raw_parts(target as *const u8, 5).to_vec();
+
+    let add = GenericDetour::<FnAdd>::new(add5, ret10)?;
+    let sub = GenericDetour::<FnAdd>::new(sub5, ret10)?;
+    let mul = GenericDetour::<FnAdd>::new(mul5, ret10)?;
+    add.set_name("add");
+    add.set_group("arithmetic");
+    sub.set_group("arithmetic");
+
+    let hooks = registry::hooks();
+    assert_eq!(hooks.len(), 3);
+    assert_eq!(hooks[0].target, add5 as FnAdd as *const ());
+    assert_eq!(hooks[0].detour, ret10 as FnAdd as *const ());
+    assert_eq!(registry::find("add").unwrap().group.as_deref(), Some("arithmetic"));
+    assert!(registry::find("sub").is_none());
+
+    registry::enable_group("arithmetic")?;
+    assert!(add.is_enabled() && sub.is_enabled() && !mul.is_enabled());
+    assert_eq!(add5(1), 10);
+    assert_eq!(sub5(1), 10);
+
+    mul.enable()?;
+    registry::disable_all()?;
+    assert!(registry::hooks().iter().all(|hook| !hook.enabled));
+    assert_eq!(add5(1), 6);
+    assert_eq!(mul5(1), 5);
+
+    // Only the detours disabled by the registry are restored
+    sub.enable()?;
+    sub.disable()?;
+    registry::restore_all()?;
+    assert!(add.is_enabled() && !sub.is_enabled() && mul.is_enabled());
+
+    registry::disable_group("arithmetic")?;
+    assert!(!add.is_enabled() && mul.is_enabled());
+
+    drop(mul);
+    assert_eq!(registry::hooks().len(), 2);
+
+    // The watchdog patches targets restored by another party
+    add.enable()?;
+    region::protect(target, 5, region::Protection::READ_WRITE_EXECUTE).unwrap();
+    std::ptr::copy_nonoverlapping(original.as_ptr(), target as *mut u8, 5);
+    assert_eq!(registry::verify_all()[0].1, Integrity::Restored);
+
+    let (sender, receiver) = mpsc::channel();
+    let watchdog = Watchdog::spawn_reapplying(Duration::from_millis(1), move |hook, integrity| {
+      let _ = sender.send((hook.name.clone(), integrity.clone()));
+    });
+
+    let (name, integrity) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    drop(watchdog);
+    assert_eq!(name.as_deref(), Some("add"));
+    assert_eq!(integrity, Integrity::Restored);
+    assert_eq!(add.verify(), Integrity::Intact);
+    assert_eq!(add5(1), 10);
+  }
+  Ok(())
+}
@@ -0,0 +1,161 @@
+//! Reloads a detour library, built from `tests/reload/detours.c`.
+#![cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+use retour::reload::ReloadManager;
+use retour::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::Duration;
+use std::{fs, ptr, thread};
+
+#[inline(never)]
+extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x) + y }
+}
+
+/// Builds a version of the detour library.
+fn build(name: &str, defines: &[&str]) -> PathBuf {
+  let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/reload/detours.c");
+  let library = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+  let status = Command::new("cc")
+    .args(["-shared", "-fPIC", "-O0"])
+    .args(defines)
+    .arg(source)
+    .arg("-o")
+    .arg(&library)
+    .status()
+    .expect("running cc");
+  assert!(status.success());
+  library
+}
+
+/// Returns whether an address is mapped from a temporary copy of a library.
+fn is_loaded(address: *const ()) -> bool {
+  let address = address as usize;
+  fs::read_to_string("/proc/self/maps").unwrap().lines().any(|line| {
+    let (range, path) = line.split_once(' ').unwrap();
+    let (start, end) = range.split_once('-').unwrap();
+    let range = usize::from_str_radix(start, 16).unwrap()..usize::from_str_radix(end, 16).unwrap();
+    range.contains(&address) && path.contains("retour-")
+  })
+}
+
+#[test]
+fn reload() -> Result<()> {
+  let v1 = build("libreload-v1.so", &[]);
+  let v2 = build("libreload-v2.so", &["-DVERSION2"]);
+
+  // The same path is reloaded once the library has been rebuilt
+  let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("libreload.so");
+  fs::copy(&v1, &path).unwrap();
+
+  unsafe {
+    let mut manager = ReloadManager::new(&path)?;
+    let hook = manager.hook(add as *const (), "detour")?;
+    let trampoline = hook.trampoline() as *const ();
+    hook.enable()?;
+    assert_eq!(add(10, 5), 5);
+
+    let previous = manager.library().symbol("detour")?;
+    fs::copy(&v2, &path).unwrap();
+    let unloaded = manager.reload_with(&path, Duration::from_secs(5), |library| {
+      *(library.symbol("original")? as *mut *const ()) = trampoline;
+      Ok(())
+    })?;
+
+    assert!(unloaded);
+    assert!(!is_loaded(previous));
+    assert_eq!(add(10, 5), 30);
+
+    // The detours are left as is if a library cannot be loaded
+    let error = manager.reload("/dev/null", Duration::ZERO).unwrap_err();
+    assert!(matches!(error, Error::LibraryFailure(_)));
+    assert_eq!(add(10, 5), 30);
+  }
+  Ok(())
+}
+
+#[test]
+fn reload_waits_for_calls() -> Result<()> {
+  static RESULT: AtomicI32 = AtomicI32::new(0);
+
+  let v1 = build("libwait-v1.so", &[]);
+  let v2 = build("libwait-v2.so", &["-DVERSION2"]);
+
+  unsafe {
+    let mut manager = ReloadManager::new(&v1)?;
+    let trampoline = manager.hook(add as *const (), "detour")?.trampoline() as *const ();
+    manager.get("detour").unwrap().enable()?;
+
+    let block = manager.library().symbol("block")? as *mut i32;
+    let entered = manager.library().symbol("entered")? as *mut i32;
+    let previous = manager.library().symbol("detour")?;
+    ptr::write_volatile(block, 1);
+
+    let caller = thread::spawn(|| RESULT.store(add(10, 5), Ordering::SeqCst));
+    while ptr::read_volatile(entered) == 0 {
+      thread::yield_now();
+    }
+
+    // The previous library is retained whilst a call is in progress
+    let unloaded = manager.reload_with(&v2, Duration::from_millis(50), |library| {
+      *(library.symbol("original")? as *mut *const ()) = trampoline;
+      Ok(())
+    })?;
+    assert!(!unloaded);
+    assert!(is_loaded(previous));
+    assert_eq!(add(10, 5), 30);
+
+    ptr::write_volatile(block, 0);
+    caller.join().unwrap();
+    assert_eq!(RESULT.load(Ordering::SeqCst), 5);
+
+    assert!(manager.wait_for_unload(Duration::from_secs(5))?);
+    assert!(!is_loaded(previous));
+  }
+  Ok(())
+}
+
+#[test]
+fn reload_under_load() -> Result<()> {
+  static RUNNING: AtomicBool = AtomicBool::new(true);
+
+  let v1 = build("libload-v1.so", &[]);
+  let v2 = build("libload-v2.so", &["-DVERSION2"]);
+
+  unsafe {
+    let mut manager = ReloadManager::new(&v1)?;
+    let trampoline = manager.hook(add as *const (), "detour")?.trampoline() as *const ();
+    manager.get("detour").unwrap().enable()?;
+    let previous = manager.library().symbol("detour")?;
+
+    let caller = thread::spawn(|| {
+      while RUNNING.load(Ordering::SeqCst) {
+        add(10, 5);
+      }
+    });
+
+    // Calls to the new library are blocked once it is loaded
+    manager.reload_with(&v2, Duration::ZERO, |library| {
+      *(library.symbol("original")? as *mut *const ()) = trampoline;
+      ptr::write_volatile(library.symbol("block")? as *mut i32, 1);
+      Ok(())
+    })?;
+
+    let block = manager.library().symbol("block")? as *mut i32;
+    let entered = manager.library().symbol("entered")? as *mut i32;
+    while ptr::read_volatile(entered) == 0 {
+      thread::yield_now();
+    }
+
+    // Only calls to the previous library are waited for
+    assert!(manager.wait_for_unload(Duration::from_secs(5))?);
+    assert!(!is_loaded(previous));
+
+    RUNNING.store(false, Ordering::SeqCst);
+    ptr::write_volatile(block, 0);
+    caller.join().unwrap();
+    assert_eq!(add(10, 5), 30);
+  }
+  Ok(())
+}
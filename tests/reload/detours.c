@@ -0,0 +1,21 @@
+// A detour library with two versions, see `tests/reload.rs`.
+typedef int (*binary_fn)(int, int);
+
+// Blocks calls to the detour whilst set
+volatile int block = 0;
+volatile int entered = 0;
+
+// The trampoline of the detour, set by the host
+binary_fn original = 0;
+
+int detour(int x, int y) {
+  entered = 1;
+  while (block) {
+  }
+
+#ifdef VERSION2
+  return original(x, y) * 2;
+#else
+  return x - y;
+#endif
+}
@@ -0,0 +1,173 @@
+//! Detaches the detours of a library, built from `tests/unload/target.c`,
+//! once it has been unloaded. This detours `dlclose`, which affects the whole
+//! process.
+#![cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+use retour::{registry, Error, Integrity, RawDetour, Result};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::{mem, ptr, thread};
+
+/// The size of the code mapped from a file.
+const CODE_SIZE: usize = 64;
+
+extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+/// Builds the library containing the target.
+fn build() -> PathBuf {
+  let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/unload/target.c");
+  let library = Path::new(env!("CARGO_TARGET_TMPDIR")).join("libunload.so");
+  let status = Command::new("cc")
+    .args(["-shared", "-fPIC", "-O0"])
+    .arg(source)
+    .arg("-o")
+    .arg(&library)
+    .status()
+    .expect("running cc");
+  assert!(status.success());
+  library
+}
+
+/// Converts a path to a C string.
+fn path(path: &Path) -> CString {
+  CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+/// Loads the library, and detours its target.
+unsafe fn load(library: &Path) -> Result<(*mut libc::c_void, RawDetour)> {
+  let handle = libc::dlopen(path(library).as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+  assert!(!handle.is_null());
+
+  let target = libc::dlsym(handle, b"add\0".as_ptr().cast());
+  let add: extern "C" fn(i32, i32) -> i32 = mem::transmute(target);
+  let hook = RawDetour::new(target as *const (), sub as *const ())?;
+  hook.set_name("unloaded");
+  hook.enable()?;
+  assert_eq!(add(10, 5), 5);
+  Ok((handle, hook))
+}
+
+/// Maps a file containing a target, and detours it.
+///
+/// Renaming the file afterwards detaches the detour as if its module had been
+/// unloaded, whilst the target remains mapped.
+unsafe fn map(path: &Path) -> Result<(*mut libc::c_void, RawDetour)> {
+  // A sequence of 'nop' followed by 'ret' is valid on both x86 and x86-64
+  let mut code = vec![0x90; CODE_SIZE - 1];
+  code.push(0xC3);
+  fs::write(path, &code).unwrap();
+
+  let file = File::open(path).unwrap();
+  let address = libc::mmap(
+    ptr::null_mut(),
+    code.len(),
+    libc::PROT_READ | libc::PROT_EXEC,
+    libc::MAP_PRIVATE,
+    file.as_raw_fd(),
+    0,
+  );
+  assert_ne!(address, libc::MAP_FAILED);
+
+  let hook = RawDetour::new(address as *const (), sub as *const ())?;
+  Ok((address, hook))
+}
+
+/// Asserts that a detour no longer accesses its unloaded target.
+unsafe fn assert_detached(hook: RawDetour) {
+  assert!(hook.is_detached());
+  assert!(!hook.is_enabled());
+  assert_eq!(hook.verify(), Integrity::Detached);
+  assert!(matches!(hook.enable(), Err(Error::ModuleUnloaded)));
+  hook.disable().unwrap();
+
+  let info = registry::find("unloaded").unwrap();
+  assert!(info.detached && !info.enabled);
+  assert!(registry::verify_all().iter().all(|(info, _)| !info.detached));
+  mem::drop(hook);
+}
+
+#[test]
+fn unload() -> Result<()> {
+  let library = build();
+
+  unsafe {
+    // Unloads are detected explicitly
+    let (handle, hook) = load(&library)?;
+    assert_eq!(libc::dlclose(handle), 0);
+    assert!(!hook.is_detached());
+
+    let detached = registry::detach_unloaded();
+    assert_eq!(detached.len(), 1);
+    assert_eq!(detached[0].name.as_deref(), Some("unloaded"));
+    assert_detached(hook);
+    assert!(registry::detach_unloaded().is_empty());
+
+    // Unloads are detected by a detour of 'dlclose'
+    registry::detach_on_unload()?;
+    let (handle, hook) = load(&library)?;
+    assert!(!hook.is_detached());
+    assert_eq!(libc::dlclose(handle), 0);
+    assert_detached(hook);
+
+    // The address can be detoured once reused
+    let (handle, hook) = load(&library)?;
+    hook.disable()?;
+    mem::drop(hook);
+    assert_eq!(libc::dlclose(handle), 0);
+
+    // Targets are detached whilst their detours are toggled by other threads
+    let mapped = Path::new(env!("CARGO_TARGET_TMPDIR")).join("unload.bin");
+    let renamed = mapped.with_extension("old");
+    for _ in 0..200 {
+      let (address, hook) = map(&mapped)?;
+      let hook = Arc::new(hook);
+      let toggler = thread::spawn({
+        let hook = hook.clone();
+        move || {
+          while !hook.is_detached() {
+            let _ = hook.enable();
+            let _ = hook.disable();
+          }
+        }
+      });
+
+      fs::rename(&mapped, &renamed).unwrap();
+      let handle = libc::dlopen(path(&library).as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+      assert!(!handle.is_null());
+      assert_eq!(libc::dlclose(handle), 0);
+
+      toggler.join().unwrap();
+      assert!(hook.is_detached());
+      mem::drop(hook);
+      libc::munmap(address, CODE_SIZE);
+    }
+    fs::remove_file(&renamed).unwrap();
+
+    // Detours of a library are not toggled whilst it is being unloaded
+    for _ in 0..200 {
+      let (handle, hook) = load(&library)?;
+      let hook = Arc::new(hook);
+      let toggler = thread::spawn({
+        let hook = hook.clone();
+        move || {
+          // The target is never accessed once it may have been unmapped
+          while !hook.is_detached() {
+            assert!(matches!(hook.enable(), Ok(()) | Err(Error::ModuleUnloaded)));
+            hook.disable().unwrap();
+          }
+        }
+      });
+
+      assert_eq!(libc::dlclose(handle), 0);
+      toggler.join().unwrap();
+      assert!(hook.is_detached());
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,5 @@
+// A library containing a target, which is unloaded, see `tests/unload.rs`.
+int add(int x, int y) {
+  volatile int result = x + y;
+  return result;
+}